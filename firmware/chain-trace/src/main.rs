@@ -0,0 +1,46 @@
+//! Convert a log holding `PINTRACE` lines of `rmk_custom_device::trace::LogSink` to VCD, for GTKWave.
+//!
+//! ```sh
+//! probe-rs run --chip RP2040 firmware.elf | tee trace.log
+//! chain-trace trace.log > trace.vcd
+//! ```
+//!
+//! The log is read from the file given, or from stdin. Other lines of the log are skipped.
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    process::ExitCode,
+};
+
+use rmk_custom_device::trace::{write_vcd, PinEvent};
+
+fn main() -> ExitCode {
+    let input: Box<dyn BufRead> = match env::args().nth(1) {
+        Some(path) => match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Cannot open {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+
+    let events: Vec<_> = input
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| PinEvent::parse_log_line(&line))
+        .collect();
+    if events.is_empty() {
+        eprintln!("No {} lines found", PinEvent::LOG_TAG);
+        return ExitCode::FAILURE;
+    }
+
+    let mut vcd = String::new();
+    write_vcd(&mut vcd, events).expect("Writing to a String does not fail");
+    if io::stdout().lock().write_all(vcd.as_bytes()).is_err() {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,1394 @@
+//! Code generation for the firmware of sequential-chain keyboards, run from the `build.rs` of each board.
+//!
+//! A board describes itself in `keyboard.toml` and `vial.json`, [generate] turns them into the keymap, the Vial
+//! config, the USB identity, the chain pin setup, the split topology and the debounce defaults,
+//! which `rmk_chain_keyboard::board!` includes. [link_cortex_m] sets up the linker with the board's `memory.x`:
+//!
+//! ```ignore
+//! fn main() {
+//!     rmk_chain_keyboard_build::generate("keyboard.toml", "vial.json");
+//!     rmk_chain_keyboard_build::link_cortex_m(include_bytes!("memory.x"));
+//! }
+//! ```
+//!
+//! Tools running the keymap elsewhere pick the parts they need from [Board].
+//! The keymap and the debounce defaults name `rmk` and `rmk_custom_device` as crates in scope, which
+//! `board!` imports from `rmk_chain_keyboard`. The other parts reach every crate through `rmk_chain_keyboard`,
+//! so boards need not depend on the crates the generated code uses.
+//! A config at fault aborts the build with a message naming it, instead of a panic.
+
+use const_gen::*;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use xz2::read::XzEncoder;
+
+/// Crates the board-only generated code reaches through `rmk_chain_keyboard`
+const EXPORT: &str = "::rmk_chain_keyboard::__export";
+
+/// Generate everything a board's firmware includes, from its `keyboard.toml` and `vial.json`
+///
+/// If `vial.json` does not exist, a plain grid generated from `keyboard.toml` is used and written to `OUT_DIR`,
+/// to be copied next to `keyboard.toml` and arranged like the board. The board's sources are never written.
+pub fn generate(keyboard_toml: impl AsRef<Path>, vial_json: impl AsRef<Path>) {
+    let board = Board::read(keyboard_toml);
+    board.generate_keymap();
+    board.generate_chain_pins();
+    board.generate_split();
+    board.generate_usb_config();
+    board.generate_debounce();
+    board.generate_vial_config(vial_json);
+}
+
+/// Put `memory.x` on the linker search path and set the linker scripts of cortex-m-rt and defmt
+pub fn link_cortex_m(memory_x: &[u8]) {
+    // Put `memory.x` in our output directory and ensure it's
+    // on the linker search path.
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(memory_x)
+        .unwrap();
+    println!("cargo::rustc-link-search={}", out.display());
+
+    // By default, Cargo will re-run a build script whenever
+    // any file in the project changes. By specifying `memory.x`
+    // here, we ensure the build script is only re-run when
+    // `memory.x` is changed.
+    println!("cargo::rerun-if-changed=memory.x");
+
+    // Specify linker arguments.
+
+    // `--nmagic` is required if memory section addresses are not aligned to 0x10000,
+    // for example the FLASH and RAM sections in your `memory.x`.
+    // See https://github.com/rust-embedded/cortex-m-quickstart/pull/95
+    println!("cargo::rustc-link-arg=--nmagic");
+
+    // Set the linker script to the one provided by cortex-m-rt.
+    println!("cargo::rustc-link-arg=-Tlink.x");
+
+    // Set the linker script of the defmt
+    println!("cargo::rustc-link-arg=-Tdefmt.x");
+}
+
+/// `keyboard.toml` of a board, with its `[layout]` checked
+pub struct Board {
+    /// Name of the config in error messages
+    name: String,
+    config: toml::Table,
+    layout: Layout,
+}
+
+impl Board {
+    /// Read and check the config at `path`, the build is run again when it changes
+    pub fn read(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        println!("cargo::rerun-if-changed={}", path.display());
+        let name = path.display().to_string();
+        let config = read_keyboard_toml(path);
+        let layout = parse_layout(&config).unwrap_or_else(|e| fail(format!("{}: {}", name, e)));
+        Self {
+            name,
+            config,
+            layout,
+        }
+    }
+
+    /// Abort the build unless `result` is fine
+    fn check(&self, result: Result<(), String>) {
+        result.unwrap_or_else(|e| fail(format!("{}: {}", self.name, e)));
+    }
+
+    /// `ROW`, `COL`, `NUM_LAYER` and `get_default_keymap` from `[layout]`
+    pub fn generate_keymap(&self) {
+        generate_keymap(&self.layout);
+    }
+
+    /// `config_chain_pins!` from `[matrix]` and the matrix overrides of the split halves
+    pub fn generate_chain_pins(&self) {
+        self.check(generate_chain_pins(&self.config));
+    }
+
+    /// Split topology and serial links from `[split]`, empty without one
+    pub fn generate_split(&self) {
+        self.check(generate_split(&self.config, &self.layout));
+    }
+
+    /// USB identity from `[keyboard]`
+    pub fn generate_usb_config(&self) {
+        self.check(generate_usb_config(&self.config));
+    }
+
+    /// Default debounce settings from `[debounce]`
+    pub fn generate_debounce(&self) {
+        self.check(generate_debounce(&self.config));
+    }
+
+    /// Vial config from `vial_json`, checked against the layout, the build is run again when it changes
+    pub fn generate_vial_config(&self, vial_json: impl AsRef<Path>) {
+        let vial_json = vial_json.as_ref();
+        println!("cargo::rerun-if-changed={}", vial_json.display());
+        generate_vial_config(vial_json, &self.layout, &self.config);
+    }
+}
+
+fn generate_vial_config(p: &Path, layout: &Layout, config: &toml::Table) {
+    // Generated vial config file
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("config_generated.rs");
+
+    let vial = match fs::read_to_string(p) {
+        Ok(content) => json::parse(&content)
+            .unwrap_or_else(|e| fail(format!("vial.json is not valid JSON: {}", e))),
+        Err(_) => {
+            // Use a plain grid, written to OUT_DIR to be copied next to keyboard.toml and arranged like the physical board
+            let vial = default_vial_json(layout, config);
+            let generated = Path::new(&env::var_os("OUT_DIR").unwrap()).join("vial.json");
+            fs::write(&generated, json::stringify_pretty(vial.clone(), 4))
+                .unwrap_or_else(|e| fail(format!("cannot write {}: {}", generated.display(), e)));
+            println!(
+                "cargo::warning={} not found, using a grid generated from keyboard.toml, copy {} there to arrange it",
+                p.display(),
+                generated.display()
+            );
+            vial
+        }
+    };
+    check_vial_json(&vial, layout, config).unwrap_or_else(|e| fail(e));
+
+    let vial_cfg = json::stringify(vial);
+    let mut keyboard_def_compressed: Vec<u8> = Vec::new();
+    XzEncoder::new(vial_cfg.as_bytes(), 6)
+        .read_to_end(&mut keyboard_def_compressed)
+        .unwrap();
+
+    let keyboard_id =
+        vial_keyboard_id(config).unwrap_or_else(|e| fail(format!("keyboard.toml: {}", e)));
+    let const_declarations = [
+        const_declaration!(pub VIAL_KEYBOARD_DEF = keyboard_def_compressed),
+        const_declaration!(pub VIAL_KEYBOARD_ID = keyboard_id),
+    ]
+    .join("\n");
+    fs::write(out_file, const_declarations).unwrap();
+}
+
+/// Signals of the sequential chain, in the order `rmk_chain_keyboard::config_sequential_matrix_pins_rp!` takes them
+const CHAIN_SIGNALS: [&str; 5] = ["row_clock", "col_clock", "any_not", "reset_not", "input"];
+
+/// What clocks a chain, `backend` of its matrix
+enum ChainBackend {
+    /// GPIOs driven by the CPU, `SequentialMatrix`
+    Gpio,
+    /// State machine 0 of `pio`, its samples moved by `dma`, `rmk_custom_device::rp_pio`
+    Pio { pio: String, dma: String },
+}
+
+/// Pin setup of one sequential chain
+struct ChainPins {
+    backend: ChainBackend,
+    pins: Vec<String>,
+    /// `Up`, `Down` or `None`, as in `embassy_rp::gpio::Pull`
+    input_pull: &'static str,
+    input_active_low: bool,
+}
+
+/// Abort the build with a message naming the config at fault, instead of a panic
+fn fail(msg: String) -> ! {
+    eprintln!("error: {}", msg);
+    std::process::exit(1);
+}
+
+fn read_keyboard_toml(path: &Path) -> toml::Table {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|e| fail(format!("cannot read {}: {}", path.display(), e)));
+    content
+        .parse::<toml::Table>()
+        .unwrap_or_else(|e| fail(format!("{} is not valid TOML: {}", path.display(), e)))
+}
+
+/// Resolve the chain of `section`, whose keys override the ones of `[matrix]`
+fn chain_pins(
+    base: Option<&toml::Table>,
+    overrides: Option<&toml::Table>,
+    section: &str,
+) -> Result<ChainPins, String> {
+    let get = |key: &str| {
+        overrides
+            .and_then(|t| t.get(key))
+            .or_else(|| base.and_then(|t| t.get(key)))
+    };
+    let get_str = |key: &str| -> Result<Option<&str>, String> {
+        match get(key) {
+            None => Ok(None),
+            Some(toml::Value::String(s)) => Ok(Some(s.as_str())),
+            Some(v) => Err(format!(
+                "{}.{} should be a string, found {}",
+                section, key, v
+            )),
+        }
+    };
+
+    match get_str("matrix_type")? {
+        Some("sequential") => {}
+        Some(other) => {
+            return Err(format!(
+                "{}.matrix_type is \"{}\", only \"sequential\" chains are supported",
+                section, other
+            ))
+        }
+        None => {
+            return Err(format!(
+                "{}.matrix_type is missing, expected \"sequential\"",
+                section
+            ))
+        }
+    }
+
+    let mut pins = Vec::new();
+    for signal in CHAIN_SIGNALS {
+        let pin = get_str(signal)?.ok_or_else(|| format!("{}.{} is missing", section, signal))?;
+        let valid = pin
+            .strip_prefix("PIN_")
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        if !valid {
+            return Err(format!(
+                "{}.{} is \"{}\", expected a pin name such as \"PIN_9\"",
+                section, signal, pin
+            ));
+        }
+        if let Some(i) = pins.iter().position(|p| p == pin) {
+            return Err(format!(
+                "{}.{} and {}.{} are both {}",
+                section, CHAIN_SIGNALS[i], section, signal, pin
+            ));
+        }
+        pins.push(pin.to_string());
+    }
+
+    let input_pull = match get_str("input_pull")?.unwrap_or("down") {
+        "up" => "Up",
+        "down" => "Down",
+        "none" => "None",
+        other => {
+            return Err(format!(
+                "{}.input_pull is \"{}\", expected \"up\", \"down\" or \"none\"",
+                section, other
+            ))
+        }
+    };
+    let input_active_low = match get("input_active_low") {
+        None => false,
+        Some(toml::Value::Boolean(b)) => *b,
+        Some(v) => {
+            return Err(format!(
+                "{}.input_active_low should be a boolean, found {}",
+                section, v
+            ))
+        }
+    };
+
+    let backend = match get_str("backend")?.unwrap_or("gpio") {
+        "gpio" => {
+            for key in ["pio", "dma"] {
+                if get(key).is_some() {
+                    return Err(format!(
+                        "{}.{} is only used with backend \"pio\"",
+                        section, key
+                    ));
+                }
+            }
+            ChainBackend::Gpio
+        }
+        "pio" => {
+            let pio = match get_str("pio")? {
+                Some(pio @ ("PIO0" | "PIO1")) => pio.to_string(),
+                Some(other) => {
+                    return Err(format!(
+                        "{}.pio is \"{}\", expected \"PIO0\" or \"PIO1\"",
+                        section, other
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "{}.pio is missing, backend \"pio\" needs one",
+                        section
+                    ))
+                }
+            };
+            let dma = match get_str("dma")? {
+                Some(dma)
+                    if dma
+                        .strip_prefix("DMA_CH")
+                        .and_then(|n| n.parse::<u8>().ok())
+                        .is_some_and(|n| n < 12) =>
+                {
+                    dma.to_string()
+                }
+                Some(other) => {
+                    return Err(format!(
+                        "{}.dma is \"{}\", expected a DMA channel such as \"DMA_CH1\"",
+                        section, other
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "{}.dma is missing, backend \"pio\" needs one",
+                        section
+                    ))
+                }
+            };
+            // A PIO drives the side-set pins and the set pins as ranges
+            let number = |i: usize| pins[i][4..].parse::<u32>().unwrap_or(u32::MAX);
+            for (first, second) in [(0, 1), (2, 3)] {
+                if number(first).checked_add(1) != Some(number(second)) {
+                    return Err(format!(
+                        "{}.{} should be the pin right after {}.{} with backend \"pio\", found {} and {}",
+                        section, CHAIN_SIGNALS[second], section, CHAIN_SIGNALS[first], pins[first], pins[second]
+                    ));
+                }
+            }
+            ChainBackend::Pio { pio, dma }
+        }
+        other => {
+            return Err(format!(
+                "{}.backend is \"{}\", expected \"gpio\" or \"pio\"",
+                section, other
+            ))
+        }
+    };
+
+    Ok(ChainPins {
+        backend,
+        pins,
+        input_pull,
+        input_active_low,
+    })
+}
+
+/// Arm of `config_chain_pins!` setting up `chain`, of `rows` x `cols` positions, for `half` or `[matrix]` if `None`
+fn chain_pins_arm(half: Option<&str>, chain: &ChainPins, rows: &str, cols: &str) -> String {
+    let half = half.map(|half| format!(", {}", half)).unwrap_or_default();
+    let mut arm = match &chain.backend {
+        ChainBackend::Gpio => format!(
+            "    ($p:ident{}) => {{\n        ::rmk_chain_keyboard::config_sequential_matrix_pins_rp!(\n            peripherals: $p,\n",
+            half
+        ),
+        // Without the interrupts, point at the call taking them
+        ChainBackend::Pio { pio, dma } => format!(
+            "    ($p:ident{half}) => {{\n        \
+             compile_error!(\"this chain is clocked by {pio}, call config_chain_pins!(p, Irqs{half}) with its interrupt bound in Irqs\")\n    \
+             }};\n    \
+             ($p:ident, $irqs:ident{half}) => {{\n        \
+             ::rmk_chain_keyboard::config_pio_chain_pins_rp!(\n            \
+             peripherals: $p,\n            \
+             irqs: $irqs,\n            \
+             pio: {pio},\n            \
+             dma: {dma},\n            \
+             rows: {rows},\n            \
+             cols: {cols},\n",
+            half = half,
+            pio = pio,
+            dma = dma,
+            rows = rows,
+            cols = cols
+        ),
+    };
+    for (signal, pin) in CHAIN_SIGNALS.iter().zip(chain.pins.iter()) {
+        arm += &format!("            {}: {},\n", signal, pin);
+    }
+    arm += &format!(
+        "            input_pull: {},\n            input_active_low: {},\n        )\n    }};\n",
+        chain.input_pull, chain.input_active_low
+    );
+    arm
+}
+
+/// Matrix overrides of a split half
+fn matrix_of(half: Option<&toml::Value>) -> Option<&toml::Table> {
+    half.and_then(|v| v.get("matrix"))
+        .and_then(|v| v.as_table())
+}
+
+/// Generate `config_chain_pins!` from `[matrix]` and the matrix overrides of the split halves
+///
+/// `config_chain_pins!(p)` sets up the chain of `[matrix]`, `config_chain_pins!(p, central)` the one of the central
+/// and `config_chain_pins!(p, peripheral 0)` the one of the first peripheral.
+/// A chain with backend `"pio"` takes the interrupts binding its PIO too, e.g. `config_chain_pins!(p, Irqs, central)`.
+fn generate_chain_pins(config: &toml::Table) -> Result<(), String> {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("chain_pins_generated.rs");
+
+    let base = config.get("matrix").and_then(|v| v.as_table());
+    let split = config.get("split").and_then(|v| v.as_table());
+    let mut arms = String::new();
+    if let Some(split) = split {
+        let central = matrix_of(split.get("central"));
+        let chain = chain_pins(base, central, "split.central.matrix")?;
+        arms += &chain_pins_arm(
+            Some("central"),
+            &chain,
+            "$crate::split::CENTRAL_ROW",
+            "$crate::split::CENTRAL_COL",
+        );
+
+        let peripherals = split
+            .get("peripheral")
+            .and_then(|v| v.as_array())
+            .map(|v| v.as_slice())
+            .unwrap_or_default();
+        for (i, peripheral) in peripherals.iter().enumerate() {
+            let section = format!("split.peripheral[{}].matrix", i);
+            let chain = chain_pins(base, matrix_of(Some(peripheral)), &section)?;
+            arms += &chain_pins_arm(
+                Some(&format!("peripheral {}", i)),
+                &chain,
+                &format!("$crate::split::SPLIT.peripherals[{}].rows", i),
+                &format!("$crate::split::SPLIT.peripherals[{}].cols", i),
+            );
+        }
+    }
+    // Last, `($p:ident, $irqs:ident)` of a PIO chain would match `(p, central)` too
+    if base.is_some() {
+        let chain = chain_pins(base, None, "matrix")?;
+        arms += &chain_pins_arm(None, &chain, "$crate::keymap::ROW", "$crate::keymap::COL");
+    }
+    if arms.is_empty() {
+        return Err("neither [matrix] nor [split] describes a chain".to_string());
+    }
+
+    fs::write(
+        out_file,
+        format!("macro_rules! config_chain_pins {{\n{}}}\n", arms),
+    )
+    .unwrap();
+    Ok(())
+}
+
+/// Rust expression of the `No` action
+const NO_ACTION: &str = "rmk::a!(No)";
+
+/// `[layout]` of `keyboard.toml`
+struct Layout {
+    rows: usize,
+    cols: usize,
+    layers: usize,
+    /// Rust expression of each key action, by layer, row and column
+    keymap: Vec<Vec<Vec<String>>>,
+}
+
+fn parse_layout(config: &toml::Table) -> Result<Layout, String> {
+    let layout = config
+        .get("layout")
+        .and_then(|v| v.as_table())
+        .ok_or("[layout] is missing")?;
+    let get_usize = |key: &str| -> Result<usize, String> {
+        match layout.get(key) {
+            Some(toml::Value::Integer(n)) if *n > 0 => Ok(*n as usize),
+            Some(v) => Err(format!(
+                "layout.{} should be a positive integer, found {}",
+                key, v
+            )),
+            None => Err(format!("layout.{} is missing", key)),
+        }
+    };
+    let rows = get_usize("rows")?;
+    let cols = get_usize("cols")?;
+    let layers = get_usize("layers")?;
+
+    let layer_values = layout
+        .get("keymap")
+        .and_then(|v| v.as_array())
+        .ok_or("layout.keymap should be an array of layers")?;
+    if layer_values.is_empty() || layer_values.len() > layers {
+        return Err(format!(
+            "layout.keymap has {} layers, expected 1 to layout.layers = {}",
+            layer_values.len(),
+            layers
+        ));
+    }
+
+    let mut keymap = Vec::new();
+    for (l, layer) in layer_values.iter().enumerate() {
+        let row_values = layer
+            .as_array()
+            .ok_or_else(|| format!("layout.keymap[{}] should be an array of rows", l))?;
+        if row_values.len() != rows {
+            return Err(format!(
+                "layout.keymap[{}] has {} rows, expected layout.rows = {}",
+                l,
+                row_values.len(),
+                rows
+            ));
+        }
+        let mut layer_keys = Vec::new();
+        for (r, row) in row_values.iter().enumerate() {
+            let key_values = row
+                .as_array()
+                .ok_or_else(|| format!("layout.keymap[{}][{}] should be an array of keys", l, r))?;
+            if key_values.len() != cols {
+                return Err(format!(
+                    "layout.keymap[{}][{}] has {} keys, expected layout.cols = {}",
+                    l,
+                    r,
+                    key_values.len(),
+                    cols
+                ));
+            }
+            let mut row_keys = Vec::new();
+            for (c, key) in key_values.iter().enumerate() {
+                let action = key
+                    .as_str()
+                    .ok_or_else(|| format!("should be a string, found {}", key))
+                    .and_then(|key| key_action(key, layers));
+                row_keys.push(
+                    action.map_err(|e| format!("layout.keymap[{}][{}][{}]: {}", l, r, c, e))?,
+                );
+            }
+            layer_keys.push(row_keys);
+        }
+        keymap.push(layer_keys);
+    }
+    // Layers without a keymap do nothing
+    while keymap.len() < layers {
+        keymap.push(vec![vec![NO_ACTION.to_string(); cols]; rows]);
+    }
+
+    Ok(Layout {
+        rows,
+        cols,
+        layers,
+        keymap,
+    })
+}
+
+/// Rust expression of a key action written as in RMK's `keyboard.toml`, e.g. `"A"`, `"MO(1)"` or `"LT(2, Kc9)"`
+fn key_action(key: &str, layers: usize) -> Result<String, String> {
+    let key = key.trim();
+    match key {
+        "_" | "__" | "Trns" => return Ok("rmk::a!(Transparent)".to_string()),
+        "No" => return Ok(NO_ACTION.to_string()),
+        _ => {}
+    }
+    let Some((name, args)) = key.strip_suffix(')').and_then(|k| k.split_once('(')) else {
+        return Ok(format!("rmk::k!({})", keycode(key)?));
+    };
+    let args: Vec<&str> = args.split(',').map(|arg| arg.trim()).collect();
+    let layer = |arg: &str| -> Result<usize, String> {
+        match arg.parse::<usize>() {
+            Ok(n) if n < layers => Ok(n),
+            Ok(n) => Err(format!("layer {} is out of layout.layers = {}", n, layers)),
+            Err(_) => Err(format!("\"{}\" is not a layer number", arg)),
+        }
+    };
+    match (name.trim(), args.as_slice()) {
+        ("MO", [n]) => Ok(format!("rmk::mo!({})", layer(n)?)),
+        ("OSL", [n]) => Ok(format!("rmk::osl!({})", layer(n)?)),
+        ("TT", [n]) => Ok(format!("rmk::tt!({})", layer(n)?)),
+        ("TG", [n]) => Ok(format!("rmk::tg!({})", layer(n)?)),
+        ("TO", [n]) => Ok(format!("rmk::to!({})", layer(n)?)),
+        ("DF", [n]) => Ok(format!("rmk::df!({})", layer(n)?)),
+        ("LT", [n, k]) => Ok(format!("rmk::lt!({}, {})", layer(n)?, keycode(k)?)),
+        ("LM", [n, m]) => Ok(format!("rmk::lm!({}, {})", layer(n)?, modifiers(m)?)),
+        ("WM", [k, m]) => Ok(format!("rmk::wm!({}, {})", keycode(k)?, modifiers(m)?)),
+        ("MT", [k, m]) => Ok(format!("rmk::mt!({}, {})", keycode(k)?, modifiers(m)?)),
+        ("OSM", [m]) => Ok(format!("rmk::osm!({})", modifiers(m)?)),
+        _ => Err(format!("\"{}\" is not a supported action", key)),
+    }
+}
+
+/// Variants of RMK's `KeyCode`, besides the letters and the numbered ones of [NUMBERED_KEYCODES]
+#[rustfmt::skip]
+const KEYCODES: &[&str] = &[
+    // HID keyboard page
+    "No", "ErrorRollover", "PostFail", "ErrorUndefined",
+    "Enter", "Escape", "Backspace", "Tab", "Space", "Minus", "Equal", "LeftBracket", "RightBracket",
+    "Backslash", "NonusHash", "Semicolon", "Quote", "Grave", "Comma", "Dot", "Slash", "CapsLock",
+    "PrintScreen", "ScrollLock", "Pause", "Insert", "Home", "PageUp", "Delete", "End", "PageDown",
+    "Right", "Left", "Down", "UP",
+    "NumLock", "KpSlash", "KpAsterisk", "KpMinus", "KpPlus", "KpEnter", "KpDot", "NonusBackslash",
+    "Application", "KbPower", "KpEqual",
+    "Execute", "Help", "Menu", "Select", "Stop", "Again", "Undo", "Cut", "Copy", "Paste", "Find",
+    "KbMute", "KbVolumeUp", "KbVolumeDown", "LockingCapsLock", "LockingNumLock", "LockingScrollLock",
+    "KpComma", "KpEqualAs400", "AlternateErase", "SystemRequest", "Cancel", "Clear", "Prior", "Return",
+    "Separator", "Out", "Oper", "ClearAgain", "Crsel", "Exsel",
+    "LCtrl", "LShift", "LAlt", "LGui", "RCtrl", "RShift", "RAlt", "RGui",
+    // System and consumer controls
+    "SystemPower", "SystemSleep", "SystemWake",
+    "AudioMute", "AudioVolUp", "AudioVolDown", "MediaNextTrack", "MediaPrevTrack", "MediaStop",
+    "MediaPlayPause", "MediaSelect", "MediaEject", "Mail", "Calculator", "MyComputer", "WwwSearch",
+    "WwwHome", "WwwBack", "WwwForward", "WwwStop", "WwwRefresh", "WwwFavorites", "MediaFastForward",
+    "MediaRewind", "BrightnessUp", "BrightnessDown", "ControlPanel", "Assistant", "MissionControl",
+    "Launchpad",
+    // Mouse keys
+    "MouseUp", "MouseDown", "MouseLeft", "MouseRight",
+    "MouseWheelUp", "MouseWheelDown", "MouseWheelLeft", "MouseWheelRight",
+    // Keyboard functions
+    "Bootloader", "Reboot", "GraveEscape", "CapsWordToggle", "ComboOn", "ComboOff", "ComboToggle",
+    "TriLayerLower", "TriLayerUpper", "OutputAuto", "OutputUsb", "OutputBluetooth",
+    "BacklightOn", "BacklightOff", "BacklightToggle", "BacklightDown", "BacklightUp", "BacklightStep",
+    "BacklightToggleBreathing",
+    "RgbTog", "RgbModeForward", "RgbModeReverse", "RgbHui", "RgbHud", "RgbSai", "RgbSad", "RgbVai",
+    "RgbVad", "RgbSpi", "RgbSpd", "RgbModePlain", "RgbModeBreathe", "RgbModeRainbow", "RgbModeSwirl",
+    "RgbModeSnake", "RgbModeKnight", "RgbModeXmas", "RgbModeGradient",
+];
+
+/// Numbered variants of RMK's `KeyCode`, `("F", 1, 24)` stands for `F1` to `F24`
+const NUMBERED_KEYCODES: &[(&str, u32, u32)] = &[
+    ("Kc", 0, 9),
+    ("F", 1, 24),
+    ("Kp", 0, 9),
+    ("International", 1, 9),
+    ("Language", 1, 9),
+    ("MouseBtn", 1, 8),
+    ("MouseAccel", 0, 2),
+    ("Macro", 0, 31),
+    ("User", 0, 31),
+];
+
+/// Name of a `KeyCode` variant, checked against the variants of RMK's `KeyCode`
+fn keycode(key: &str) -> Result<&str, String> {
+    let letter = key.len() == 1 && key.starts_with(|c: char| c.is_ascii_uppercase());
+    let numbered = NUMBERED_KEYCODES.iter().any(|&(prefix, first, last)| {
+        key.strip_prefix(prefix)
+            .filter(|n| n.bytes().all(|b| b.is_ascii_digit()) && (*n == "0" || !n.starts_with('0')))
+            .and_then(|n| n.parse::<u32>().ok())
+            .is_some_and(|n| (first..=last).contains(&n))
+    });
+    if letter || numbered || KEYCODES.contains(&key) {
+        Ok(key)
+    } else {
+        Err(format!("\"{}\" is not a key name of RMK's KeyCode", key))
+    }
+}
+
+/// `ModifierCombination` expression of modifiers joined with `|`, e.g. `LShift | LGui`
+fn modifiers(mods: &str) -> Result<String, String> {
+    let (mut right, mut left) = (false, false);
+    let (mut gui, mut alt, mut shift, mut ctrl) = (false, false, false, false);
+    for m in mods.split('|').map(|m| m.trim()) {
+        let (is_right, name) = match m.split_at_checked(1) {
+            Some(("L", name)) => (false, name),
+            Some(("R", name)) => (true, name),
+            _ => return Err(format!("\"{}\" is not a modifier", m)),
+        };
+        match name {
+            "Gui" => gui = true,
+            "Alt" => alt = true,
+            "Shift" => shift = true,
+            "Ctrl" => ctrl = true,
+            _ => return Err(format!("\"{}\" is not a modifier", m)),
+        }
+        right |= is_right;
+        left |= !is_right;
+    }
+    if right && left {
+        return Err(format!("\"{}\" mixes left and right modifiers", mods));
+    }
+    Ok(format!(
+        "rmk::keycode::ModifierCombination::new_from({}, {}, {}, {}, {})",
+        right, gui, alt, shift, ctrl
+    ))
+}
+
+/// Generate `ROW`, `COL`, `NUM_LAYER` and `get_default_keymap` from `[layout]`
+fn generate_keymap(layout: &Layout) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("keymap_generated.rs");
+
+    let mut code = format!(
+        "pub(crate) const COL: usize = {};\npub(crate) const ROW: usize = {};\npub(crate) const NUM_LAYER: usize = {};\n\n",
+        layout.cols, layout.rows, layout.layers
+    );
+    code += "#[rustfmt::skip]\npub fn get_default_keymap() -> [[[rmk::action::KeyAction; COL]; ROW]; NUM_LAYER] {\n    [\n";
+    for layer in &layout.keymap {
+        code += "        rmk::layer!([\n";
+        let rows: Vec<String> = layer
+            .iter()
+            .map(|row| format!("            [{}]", row.join(", ")))
+            .collect();
+        code += &rows.join(",\n");
+        code += "\n        ]),\n";
+    }
+    code += "    ]\n}\n";
+    fs::write(out_file, code).unwrap();
+}
+
+/// Value of a `0x`-prefixed hex string or an integer, as both `keyboard.toml` and `vial.json` write IDs
+fn id_value(v: &json::JsonValue) -> Option<i64> {
+    match v.as_str() {
+        Some(s) => {
+            i64::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16).ok()
+        }
+        None => v.as_i64(),
+    }
+}
+
+/// Check that `vial.json` describes the matrix of `[layout]` and the IDs of `[keyboard]`
+fn check_vial_json(
+    vial: &json::JsonValue,
+    layout: &Layout,
+    config: &toml::Table,
+) -> Result<(), String> {
+    let (rows, cols) = (&vial["matrix"]["rows"], &vial["matrix"]["cols"]);
+    if rows.as_usize() != Some(layout.rows) || cols.as_usize() != Some(layout.cols) {
+        return Err(format!(
+            "vial.json matrix is {} x {}, but layout is {} x {}",
+            rows, cols, layout.rows, layout.cols
+        ));
+    }
+
+    let keyboard = config.get("keyboard").and_then(|v| v.as_table());
+    for (toml_key, json_key) in [("vendor_id", "vendorId"), ("product_id", "productId")] {
+        let Some(expected) = keyboard
+            .and_then(|k| k.get(toml_key))
+            .and_then(|v| v.as_integer())
+        else {
+            continue;
+        };
+        let found = id_value(&vial[json_key]);
+        if found != Some(expected) {
+            return Err(format!(
+                "vial.json {} is {}, but keyboard.{} is {:#06x}",
+                json_key, vial[json_key], toml_key, expected
+            ));
+        }
+    }
+
+    // Keys of the layouts are labelled "row,col", the first line of a label is the position
+    for row in vial["layouts"]["keymap"].members() {
+        for key in row.members().filter_map(|key| key.as_str()) {
+            let position = key.lines().next().unwrap_or_default();
+            let parsed = position.split_once(',').and_then(|(r, c)| {
+                Some((
+                    r.trim().parse::<usize>().ok()?,
+                    c.trim().parse::<usize>().ok()?,
+                ))
+            });
+            match parsed {
+                Some((r, c)) if r < layout.rows && c < layout.cols => {}
+                Some(_) => {
+                    return Err(format!(
+                        "vial.json key \"{}\" is out of the {} x {} layout",
+                        position, layout.rows, layout.cols
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "vial.json key \"{}\" is not a \"row,col\" position",
+                        position
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `vial.json` with one key per position of `[layout]`, in a plain grid
+fn default_vial_json(layout: &Layout, config: &toml::Table) -> json::JsonValue {
+    let keyboard = config.get("keyboard").and_then(|v| v.as_table());
+    let get = |key: &str| keyboard.and_then(|k| k.get(key));
+    let id = |key: &str| {
+        format!(
+            "0x{:04X}",
+            get(key).and_then(|v| v.as_integer()).unwrap_or(0)
+        )
+    };
+
+    let keymap: Vec<json::JsonValue> = (0..layout.rows)
+        .map(|r| {
+            (0..layout.cols)
+                .map(|c| format!("{},{}", r, c))
+                .collect::<Vec<_>>()
+                .into()
+        })
+        .collect();
+    let mut vial = json::JsonValue::new_object();
+    vial["name"] = get("name").and_then(|v| v.as_str()).unwrap_or("rmk").into();
+    vial["vendorId"] = id("vendor_id").into();
+    vial["productId"] = id("product_id").into();
+    vial["lighting"] = "none".into();
+    vial["matrix"]["rows"] = layout.rows.into();
+    vial["matrix"]["cols"] = layout.cols.into();
+    vial["layouts"]["keymap"] = keymap.into();
+    vial
+}
+
+/// Serial number prefix Vial looks for, used unless `[keyboard]` sets `serial_number_prefix`
+const DEFAULT_SERIAL_PREFIX: &str = "vial:f64c2b3c:";
+/// Room for the prefix in the 32-byte serial number of `rmk_chain_keyboard::rp::unique_serial_number`,
+/// after the 16 hex digits of the flash unique ID
+const MAX_SERIAL_PREFIX_LEN: usize = 32 - 16;
+
+/// Generate the USB identity from `[keyboard]`
+///
+/// The serial number ends with the unique ID of the board, so only its prefix is generated.
+fn generate_usb_config(config: &toml::Table) -> Result<(), String> {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("usb_config_generated.rs");
+
+    let keyboard = config
+        .get("keyboard")
+        .and_then(|v| v.as_table())
+        .ok_or("[keyboard] is missing")?;
+    let get_id = |key: &str| -> Result<u16, String> {
+        match keyboard.get(key) {
+            Some(toml::Value::Integer(n)) => u16::try_from(*n)
+                .map_err(|_| format!("keyboard.{} is {}, which does not fit in 16 bits", key, n)),
+            Some(v) => Err(format!(
+                "keyboard.{} should be an integer, found {}",
+                key, v
+            )),
+            None => Err(format!("keyboard.{} is missing", key)),
+        }
+    };
+    let get_str = |key: &str| -> Result<Option<&str>, String> {
+        match keyboard.get(key) {
+            None => Ok(None),
+            Some(toml::Value::String(s)) => Ok(Some(s.as_str())),
+            Some(v) => Err(format!("keyboard.{} should be a string, found {}", key, v)),
+        }
+    };
+    let vid = get_id("vendor_id")?;
+    let pid = get_id("product_id")?;
+    let manufacturer = get_str("manufacturer")?.ok_or("keyboard.manufacturer is missing")?;
+    let product_name = get_str("product_name")?.ok_or("keyboard.product_name is missing")?;
+    let serial_prefix = get_str("serial_number_prefix")?.unwrap_or(DEFAULT_SERIAL_PREFIX);
+    if serial_prefix.len() > MAX_SERIAL_PREFIX_LEN {
+        return Err(format!(
+            "keyboard.serial_number_prefix is {} bytes, at most {} fit before the flash unique ID",
+            serial_prefix.len(),
+            MAX_SERIAL_PREFIX_LEN
+        ));
+    }
+
+    let code = format!(
+        "pub const USB_VID: u16 = {:#06x};\n\
+         pub const USB_PID: u16 = {:#06x};\n\
+         pub const USB_MANUFACTURER: &str = {:?};\n\
+         pub const USB_PRODUCT_NAME: &str = {:?};\n\
+         pub const USB_SERIAL_PREFIX: &str = {:?};\n",
+        vid, pid, manufacturer, product_name, serial_prefix
+    );
+    fs::write(out_file, code).unwrap();
+    Ok(())
+}
+
+/// Generate the default debounce settings from `[debounce]`, used until settings are stored in flash
+fn generate_debounce(config: &toml::Table) -> Result<(), String> {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("debounce_generated.rs");
+
+    let empty = toml::Table::new();
+    let debounce = match config.get("debounce") {
+        None => &empty,
+        Some(toml::Value::Table(debounce)) => debounce,
+        Some(v) => return Err(format!("[debounce] should be a table, found {}", v)),
+    };
+    let algorithm = match debounce.get("algorithm") {
+        None => "Defer",
+        Some(toml::Value::String(s)) if s == "defer" => "Defer",
+        Some(toml::Value::String(s)) if s == "eager_press" => "EagerPress",
+        Some(toml::Value::String(s)) if s == "asymmetric" => "Asymmetric",
+        Some(v) => {
+            return Err(format!(
+                "debounce.algorithm is {}, expected \"defer\", \"eager_press\" or \"asymmetric\"",
+                v
+            ))
+        }
+    };
+    let get_ms = |key: &str| -> Result<Option<u8>, String> {
+        match debounce.get(key) {
+            None => Ok(None),
+            Some(toml::Value::Integer(n)) => u8::try_from(*n)
+                .map(Some)
+                .map_err(|_| format!("debounce.{} is {}, expected 0 to 255", key, n)),
+            Some(v) => Err(format!(
+                "debounce.{} should be an integer, found {}",
+                key, v
+            )),
+        }
+    };
+    let press_ms = get_ms("press_ms")?.unwrap_or(10);
+    let release_ms = get_ms("release_ms")?.unwrap_or(press_ms);
+    if algorithm == "Defer" && release_ms != press_ms {
+        return Err(format!(
+            "debounce.release_ms = {} differs from press_ms = {}, but \"defer\" waits press_ms for releases too, \
+             use \"asymmetric\" to wait release_ms for them",
+            release_ms, press_ms
+        ));
+    }
+
+    let code = format!(
+        "pub const DEBOUNCE_DEFAULT: rmk_custom_device::debounce::DebounceSettings =\n    \
+         rmk_custom_device::debounce::DebounceSettings {{\n        \
+         algorithm: rmk_custom_device::debounce::DebounceAlgorithm::{},\n        \
+         press_ms: {},\n        \
+         release_ms: {},\n    \
+         }};\n",
+        algorithm, press_ms, release_ms
+    );
+    fs::write(out_file, code).unwrap();
+    Ok(())
+}
+
+/// Vial keyboard ID set by `keyboard.vial_keyboard_id`, or derived from `keyboard.name`
+///
+/// The derived ID is the FNV-1a hash of the name, so boards with different names get different IDs.
+fn vial_keyboard_id(config: &toml::Table) -> Result<Vec<u8>, String> {
+    let keyboard = config.get("keyboard").and_then(|v| v.as_table());
+    if let Some(id) = keyboard.and_then(|k| k.get("vial_keyboard_id")) {
+        let bytes: Option<Vec<u8>> =
+            id.as_array()
+                .filter(|bytes| bytes.len() == 8)
+                .and_then(|bytes| {
+                    bytes
+                        .iter()
+                        .map(|b| b.as_integer().and_then(|b| u8::try_from(b).ok()))
+                        .collect()
+                });
+        return bytes.ok_or_else(|| {
+            format!(
+                "keyboard.vial_keyboard_id should be an array of 8 bytes, found {}",
+                id
+            )
+        });
+    }
+
+    let name = keyboard
+        .and_then(|k| k.get("name"))
+        .and_then(|v| v.as_str())
+        .ok_or("keyboard.name is missing, it is needed to derive the Vial keyboard ID")?;
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    Ok(hash.to_be_bytes().to_vec())
+}
+
+/// One `serial` entry of a split half: a UART, or a PIO sending and receiving on a single wire
+struct SplitSerial {
+    instance: String,
+    /// `tx_pin` and `rx_pin` of a UART, the only `pin` of a PIO
+    pins: Vec<String>,
+}
+
+/// Line of `bind_interrupts!` for a PIO, running a split link or a chain
+fn pio_interrupt(pio: &str) -> String {
+    format!(
+        "{}_IRQ_0 => {EXPORT}::embassy_rp::pio::InterruptHandler<{EXPORT}::embassy_rp::peripherals::{}>;",
+        pio, pio
+    )
+}
+
+impl SplitSerial {
+    /// Line of `bind_interrupts!` for the instance
+    fn interrupt(&self) -> String {
+        if self.instance.starts_with("PIO") {
+            pio_interrupt(&self.instance)
+        } else {
+            format!(
+                "{}_IRQ => {EXPORT}::embassy_rp::uart::BufferedInterruptHandler<{EXPORT}::embassy_rp::peripherals::{}>;",
+                self.instance, self.instance
+            )
+        }
+    }
+
+    /// Expression opening the link with the macros of `rmk_chain_keyboard`
+    fn config(&self) -> String {
+        if self.instance.starts_with("PIO") {
+            format!(
+                "::rmk_chain_keyboard::config_split_half_duplex_rp!($p, $irqs, {}, {})",
+                self.instance, self.pins[0]
+            )
+        } else {
+            format!(
+                "::rmk_chain_keyboard::config_split_serial_rp!($p, $irqs, {}, {}, {})",
+                self.instance, self.pins[0], self.pins[1]
+            )
+        }
+    }
+}
+
+/// One half of `[split]`
+struct SplitHalf {
+    rows: usize,
+    cols: usize,
+    /// `None` places the half right of the previous one
+    offset: Option<(usize, usize)>,
+    serial: Vec<SplitSerial>,
+}
+
+fn parse_split_half(half: &toml::Value, section: &str) -> Result<SplitHalf, String> {
+    let get_usize = |key: &str| -> Result<Option<usize>, String> {
+        match half.get(key) {
+            None => Ok(None),
+            Some(toml::Value::Integer(n)) if *n >= 0 => Ok(Some(*n as usize)),
+            Some(v) => Err(format!(
+                "{}.{} should be a non-negative integer, found {}",
+                section, key, v
+            )),
+        }
+    };
+    let rows = get_usize("rows")?.ok_or_else(|| format!("{}.rows is missing", section))?;
+    let cols = get_usize("cols")?.ok_or_else(|| format!("{}.cols is missing", section))?;
+    let offset = match (get_usize("row_offset")?, get_usize("col_offset")?) {
+        (Some(row), Some(col)) => Some((row, col)),
+        (None, None) => None,
+        _ => {
+            return Err(format!(
+                "{} should set both row_offset and col_offset, or neither",
+                section
+            ))
+        }
+    };
+
+    let mut serial = Vec::new();
+    let entries = half
+        .get("serial")
+        .and_then(|v| v.as_array())
+        .map(|v| v.as_slice())
+        .unwrap_or_default();
+    for (i, entry) in entries.iter().enumerate() {
+        let get_str = |key: &str| {
+            entry
+                .get(key)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| format!("{}.serial[{}].{} should be a string", section, i, key))
+        };
+        let instance = get_str("instance")?;
+        if !matches!(instance, "UART0" | "UART1" | "PIO0" | "PIO1") {
+            return Err(format!(
+                "{}.serial[{}].instance is \"{}\", expected \"UART0\", \"UART1\", \"PIO0\" or \"PIO1\"",
+                section, i, instance
+            ));
+        }
+        let pin = |key: &str| -> Result<String, String> {
+            let pin = get_str(key)?;
+            let valid = pin
+                .strip_prefix("PIN_")
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if valid {
+                Ok(pin.to_string())
+            } else {
+                Err(format!(
+                    "{}.serial[{}].{} is \"{}\", expected a pin name such as \"PIN_0\"",
+                    section, i, key, pin
+                ))
+            }
+        };
+        // A PIO link is half-duplex on a single wire
+        let pins = if instance.starts_with("PIO") {
+            vec![pin("pin")?]
+        } else {
+            vec![pin("tx_pin")?, pin("rx_pin")?]
+        };
+        serial.push(SplitSerial {
+            instance: instance.to_string(),
+            pins,
+        });
+    }
+
+    Ok(SplitHalf {
+        rows,
+        cols,
+        offset,
+        serial,
+    })
+}
+
+/// Generate the split topology and the setup of its serial links from `[split]`
+///
+/// `SPLIT` places the halves in the keymap, its `check` fails the build if they do not fit or overlap.
+/// Keymap positions no half holds are an error here unless they are `No` on every layer, they are then passed to `check`.
+/// The central talks to peripheral `i` over its `serial[i]`, each peripheral over its only `serial` entry.
+fn generate_split(config: &toml::Table, layout: &Layout) -> Result<(), String> {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("split_generated.rs");
+    // Set when the central clocks its chain through the peripheral, see `ChainExtension`
+    println!("cargo::rustc-check-cfg=cfg(split_chain)");
+    let Some(split) = config.get("split") else {
+        fs::write(out_file, "").unwrap();
+        return Ok(());
+    };
+
+    let chain = match split.get("connection").and_then(|v| v.as_str()) {
+        Some("serial") => false,
+        Some("chain") => true,
+        Some(other) => {
+            return Err(format!(
+                "split.connection is \"{}\", expected \"serial\" or \"chain\"",
+                other
+            ))
+        }
+        None => {
+            return Err("split.connection is missing, expected \"serial\" or \"chain\"".to_string())
+        }
+    };
+    let central = parse_split_half(
+        split.get("central").ok_or("[split.central] is missing")?,
+        "split.central",
+    )?;
+    let peripherals = split
+        .get("peripheral")
+        .and_then(|v| v.as_array())
+        .filter(|v| !v.is_empty())
+        .ok_or("[[split.peripheral]] is missing")?
+        .iter()
+        .enumerate()
+        .map(|(i, half)| parse_split_half(half, &format!("split.peripheral[{}]", i)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if chain {
+        if peripherals.len() != 1 {
+            return Err("split.connection \"chain\" extends the central's chain into exactly one peripheral".to_string());
+        }
+        let serial_count =
+            central.serial.len() + peripherals.iter().map(|p| p.serial.len()).sum::<usize>();
+        if serial_count > 0 {
+            return Err(
+                "split.connection \"chain\" uses no serial link, remove the serial entries"
+                    .to_string(),
+            );
+        }
+    } else {
+        if central.serial.len() != peripherals.len() {
+            return Err(format!(
+                "split.central.serial has {} entries, expected one per peripheral, {}",
+                central.serial.len(),
+                peripherals.len()
+            ));
+        }
+        for (i, a) in central.serial.iter().enumerate() {
+            if central.serial[..i].iter().any(|b| b.instance == a.instance) {
+                return Err(format!(
+                    "split.central.serial uses {} more than once",
+                    a.instance
+                ));
+            }
+        }
+        for (i, peripheral) in peripherals.iter().enumerate() {
+            if peripheral.serial.len() != 1 {
+                return Err(format!(
+                    "split.peripheral[{}].serial should have exactly one entry",
+                    i
+                ));
+            }
+            if peripheral.serial[0].pins.len() != central.serial[i].pins.len() {
+                return Err(format!(
+                    "split.peripheral[{}].serial and split.central.serial[{}] should both be UARTs or both PIOs",
+                    i, i
+                ));
+            }
+        }
+    }
+
+    let mut code = format!(
+        "pub const SPLIT: {EXPORT}::rmk_custom_device::split::SplitTopology<{}> =\n    {EXPORT}::rmk_custom_device::split::SplitTopology::new({}, {})",
+        peripherals.len(),
+        central.rows,
+        central.cols
+    );
+    if let Some((row, col)) = central.offset {
+        code += &format!("\n        .central_at({}, {})", row, col);
+    }
+    for peripheral in &peripherals {
+        code += &match peripheral.offset {
+            Some((row, col)) => format!(
+                "\n        .peripheral_at({}, {}, {}, {})",
+                peripheral.rows, peripheral.cols, row, col
+            ),
+            None => format!(
+                "\n        .peripheral({}, {})",
+                peripheral.rows, peripheral.cols
+            ),
+        };
+    }
+    // Placed as `SplitTopology` does, a half without offsets goes right of the previous one
+    let mut placements: Vec<(usize, usize, usize, usize)> = Vec::new();
+    for half in std::iter::once(&central).chain(&peripherals) {
+        let (row_offset, col_offset) = match (half.offset, placements.last()) {
+            (Some(offset), _) => offset,
+            (None, Some(&(row, col, _, cols))) => (row, col + cols),
+            (None, None) => (0, 0),
+        };
+        placements.push((row_offset, col_offset, half.rows, half.cols));
+    }
+    let mut unused = Vec::new();
+    for row in 0..layout.rows {
+        for col in 0..layout.cols {
+            let held = placements
+                .iter()
+                .any(|&(row_offset, col_offset, rows, cols)| {
+                    (row_offset..row_offset + rows).contains(&row)
+                        && (col_offset..col_offset + cols).contains(&col)
+                });
+            if held {
+                continue;
+            }
+            if let Some(l) = (0..layout.layers).find(|&l| layout.keymap[l][row][col] != NO_ACTION) {
+                return Err(format!(
+                    "layout.keymap[{}][{}][{}] is held by no half of [split], set it to \"No\" on every layer or place a half over it",
+                    l, row, col
+                ));
+            }
+            unused.push(format!("({}, {})", row, col));
+        }
+    }
+    code += &format!(
+        "\n        .check({}, {}, &[{}]);\n\n",
+        layout.rows,
+        layout.cols,
+        unused.join(", ")
+    );
+
+    // Chain scanned by the central and where its keys go in the keymap
+    if chain {
+        let along = match split.get("chain_along").and_then(|v| v.as_str()) {
+            None | Some("rows") => "Rows",
+            Some("cols") => "Cols",
+            Some(other) => {
+                return Err(format!(
+                    "split.chain_along is \"{}\", expected \"rows\" or \"cols\"",
+                    other
+                ))
+            }
+        };
+        println!("cargo::rustc-cfg=split_chain");
+        code += &format!(
+            "pub const CENTRAL_REMAP: {EXPORT}::rmk_custom_device::split::ChainExtension =\n    \
+             {EXPORT}::rmk_custom_device::split::ChainExtension::new(\n        \
+             SPLIT.central,\n        \
+             SPLIT.peripherals[0],\n        \
+             {EXPORT}::rmk_custom_device::split::ExtendAlong::{},\n    \
+             );\n\
+             pub const CENTRAL_ROW: usize = CENTRAL_REMAP.chain_rows();\n\
+             pub const CENTRAL_COL: usize = CENTRAL_REMAP.chain_cols();\n\n",
+            along
+        );
+    } else {
+        code += &format!(
+            "pub const CENTRAL_ROW: usize = SPLIT.central.rows;\n\
+             pub const CENTRAL_COL: usize = SPLIT.central.cols;\n\
+             pub const CENTRAL_REMAP: {EXPORT}::rmk_custom_device::remap::Transform<CENTRAL_ROW, CENTRAL_COL> =\n    \
+             {EXPORT}::rmk_custom_device::remap::Transform::offset(SPLIT.central.row_offset, SPLIT.central.col_offset);\n\n"
+        );
+    }
+
+    // Serial links of each half, the central has one per peripheral
+    let mut halves = vec![("central".to_string(), &central.serial)];
+    for (i, peripheral) in peripherals.iter().enumerate() {
+        halves.push((format!("peripheral {}", i), &peripheral.serial));
+    }
+
+    // Interrupts of each half: its serial links, and the PIO clocking its chain
+    let base = config.get("matrix").and_then(|v| v.as_table());
+    let mut sections = vec![("split.central".to_string(), split.get("central"))];
+    for i in 0..peripherals.len() {
+        sections.push((
+            format!("split.peripheral[{}]", i),
+            split.get("peripheral").and_then(|v| v.get(i)),
+        ));
+    }
+    let mut half_interrupts = Vec::new();
+    for ((half, serial), (section, value)) in halves.iter().zip(sections) {
+        let mut interrupts: Vec<String> = serial.iter().map(|link| link.interrupt()).collect();
+        let chain = chain_pins(base, matrix_of(value), &format!("{}.matrix", section))?;
+        if let ChainBackend::Pio { pio, .. } = &chain.backend {
+            if let Some(i) = serial.iter().position(|link| &link.instance == pio) {
+                return Err(format!(
+                    "{}.matrix.pio and {}.serial[{}].instance are both {}, each needs a PIO of its own",
+                    section, section, i, pio
+                ));
+            }
+            interrupts.push(pio_interrupt(pio));
+        }
+        half_interrupts.push((half, interrupts));
+    }
+
+    code += "macro_rules! bind_split_interrupts {\n";
+    for (half, interrupts) in &half_interrupts {
+        code += &format!(
+            "    (struct $name:ident {{ $($rest:tt)* }}, {}) => {{\n        {EXPORT}::embassy_rp::bind_interrupts!(struct $name {{\n            $($rest)*\n",
+            half
+        );
+        for interrupt in interrupts {
+            code += &format!("            {}\n", interrupt);
+        }
+        code += "        });\n    };\n";
+    }
+    // Every instance used by any half, for the image deciding its role at runtime
+    let mut interrupts: Vec<String> = Vec::new();
+    for interrupt in half_interrupts
+        .iter()
+        .flat_map(|(_, interrupts)| interrupts)
+    {
+        if !interrupts.contains(interrupt) {
+            interrupts.push(interrupt.clone());
+        }
+    }
+    code += &format!(
+        "    (struct $name:ident {{ $($rest:tt)* }}, all) => {{\n        {EXPORT}::embassy_rp::bind_interrupts!(struct $name {{\n            $($rest)*\n"
+    );
+    for interrupt in interrupts {
+        code += &format!("            {}\n", interrupt);
+    }
+    code += "        });\n    };\n";
+    code += "}\n\n";
+
+    if chain {
+        fs::write(out_file, code).unwrap();
+        return Ok(());
+    }
+
+    // UARTs wrapped in a health monitored link, the central releases the keys of a lost peripheral
+    code += "macro_rules! config_split_serial {\n";
+    for (half, serial) in &halves {
+        for (i, link) in serial.iter().enumerate() {
+            let (pattern, id, placement) = match half.as_str() {
+                "central" => (
+                    format!("central {}", i),
+                    i,
+                    format!("Some($crate::split::SPLIT.peripherals[{}])", i),
+                ),
+                peripheral => (peripheral.to_string(), 0, "None".to_string()),
+            };
+            code += &format!(
+                "    ($p:ident, $irqs:ident, {}) => {{\n        \
+                 {EXPORT}::rmk_custom_device::link::SplitLink::new(\n            \
+                 {},\n            \
+                 {},\n            \
+                 {},\n        \
+                 )\n    }};\n",
+                pattern,
+                link.config(),
+                id,
+                placement
+            );
+        }
+    }
+    code += "}\n\n";
+
+    // Monitors of all peripherals, joined pairwise
+    let monitor = |i: usize| {
+        format!(
+            "{EXPORT}::rmk::split::central::run_peripheral_monitor::<\n            \
+             {{ $crate::split::SPLIT.peripherals[{i}].rows }},\n            \
+             {{ $crate::split::SPLIT.peripherals[{i}].cols }},\n            \
+             {{ $crate::split::SPLIT.peripherals[{i}].row_offset }},\n            \
+             {{ $crate::split::SPLIT.peripherals[{i}].col_offset }},\n            \
+             _,\n        \
+             >({i}, config_split_serial!($p, $irqs, central {i}))",
+            i = i
+        )
+    };
+    let mut monitors = monitor(peripherals.len() - 1);
+    for i in (0..peripherals.len() - 1).rev() {
+        monitors = format!(
+            "{EXPORT}::embassy_futures::join::join(\n        {},\n        {},\n    )",
+            monitor(i),
+            monitors
+        );
+    }
+    code += &format!(
+        "macro_rules! run_peripheral_monitors {{\n    ($p:ident, $irqs:ident) => {{\n        {}\n    }};\n}}\n",
+        monitors
+    );
+
+    fs::write(out_file, code).unwrap();
+    Ok(())
+}
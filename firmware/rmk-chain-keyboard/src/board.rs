@@ -0,0 +1,90 @@
+//! Board definition generated from `keyboard.toml` and `vial.json` by `rmk-chain-keyboard-build`.
+use embedded_hal::digital::OutputPin;
+use rmk::config::{KeyboardUsbConfig, RmkConfig, VialConfig};
+
+/// USB identity and Vial definition of a board, `IDENTITY` of [board!](crate::board!)
+#[derive(Clone, Copy, Debug)]
+pub struct BoardIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: &'static str,
+    pub product_name: &'static str,
+    /// Start of the serial number, which ends with the unique ID of each board
+    pub serial_prefix: &'static str,
+    pub vial_keyboard_id: &'static [u8],
+    /// Compressed `vial.json`
+    pub vial_keyboard_def: &'static [u8],
+}
+
+impl BoardIdentity {
+    /// RMK's config of the board, `serial_number` tells identical boards apart
+    pub fn rmk_config<Out: OutputPin>(
+        &self,
+        serial_number: &'static str,
+    ) -> RmkConfig<'static, Out> {
+        RmkConfig {
+            usb_config: KeyboardUsbConfig {
+                vid: self.vid,
+                pid: self.pid,
+                manufacturer: self.manufacturer,
+                product_name: self.product_name,
+                serial_number,
+            },
+            vial_config: VialConfig::new(self.vial_keyboard_id, self.vial_keyboard_def),
+            ..Default::default()
+        }
+    }
+}
+
+/// Include the board definition generated by `build.rs`, at the root of each binary of the board.
+///
+/// * `keymap` - `ROW`, `COL`, `NUM_LAYER` and `get_default_keymap`, from `[layout]`
+/// * `split` - `SPLIT`, the chain of the central and the setup of the serial links, from `[split]`
+/// * `debounce` - `DEBOUNCE_DEFAULT`, from `[debounce]`
+/// * `usb` and `vial` - the USB identity from `[keyboard]` and the Vial config, gathered in `IDENTITY`
+/// * `config_chain_pins!` - the chain pins, from `[matrix]` and the matrix of each split half
+#[macro_export]
+macro_rules! board {
+    () => {
+        // Each image of a board only uses a part of it
+        #[allow(dead_code)]
+        mod keymap {
+            use $crate::__export::rmk;
+            include!(concat!(env!("OUT_DIR"), "/keymap_generated.rs"));
+        }
+        #[allow(dead_code, unused_macros)]
+        #[macro_use]
+        mod split {
+            include!(concat!(env!("OUT_DIR"), "/split_generated.rs"));
+        }
+        #[allow(dead_code)]
+        mod debounce {
+            use $crate::__export::rmk_custom_device;
+            include!(concat!(env!("OUT_DIR"), "/debounce_generated.rs"));
+        }
+        #[allow(dead_code)]
+        mod usb {
+            include!(concat!(env!("OUT_DIR"), "/usb_config_generated.rs"));
+        }
+        #[allow(dead_code)]
+        mod vial {
+            include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
+        }
+        #[allow(unused_macros)]
+        #[macro_use]
+        mod chain_pins {
+            include!(concat!(env!("OUT_DIR"), "/chain_pins_generated.rs"));
+        }
+
+        #[allow(dead_code)]
+        const IDENTITY: $crate::BoardIdentity = $crate::BoardIdentity {
+            vid: usb::USB_VID,
+            pid: usb::USB_PID,
+            manufacturer: usb::USB_MANUFACTURER,
+            product_name: usb::USB_PRODUCT_NAME,
+            serial_prefix: usb::USB_SERIAL_PREFIX,
+            vial_keyboard_id: vial::VIAL_KEYBOARD_ID,
+            vial_keyboard_def: vial::VIAL_KEYBOARD_DEF,
+        };
+    };
+}
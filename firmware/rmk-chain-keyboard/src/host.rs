@@ -0,0 +1,149 @@
+//! Host commands of `rmk_custom_device::host`, carried in Vial's raw HID reports.
+//!
+//! RMK owns the USB device and answers every Vial report itself, so [HostCommandDriver] wraps the USB driver
+//! and serves the reports starting with [HOST_REPORT_ID] before RMK sees them. A host tool writes
+//! `[HOST_REPORT_ID, command, args..]` to the Vial interface (usage page `0xFF60`) and reads back
+//! `[HOST_REPORT_ID, command, status, result..]`.
+//!
+//! Vial's reports are the only interrupt packets of [HOST_REPORT_SIZE] bytes. RMK still gets a report for each
+//! host command, an unhandled VIA command, and answers it in turn, so the response to the host command takes
+//! the place of RMK's answer and requests and responses stay in order.
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_usb::driver::{
+    Driver, Endpoint, EndpointAllocError, EndpointError, EndpointIn, EndpointInfo, EndpointOut,
+    EndpointType,
+};
+use rmk_custom_device::host::{handle_host_command, HOST_REPORT_SIZE};
+
+/// First byte of the Vial reports carrying a host command, a command id VIA leaves unused
+pub const HOST_REPORT_ID: u8 = 0xF0;
+/// VIA's `id_unhandled`, what RMK gets in place of a host command
+const VIA_UNHANDLED: u8 = 0xFF;
+
+/// Response to the last host command, until RMK answers the report that stood in for it
+static RESPONSE: Mutex<CriticalSectionRawMutex, RefCell<Option<[u8; HOST_REPORT_SIZE]>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Serve the host command in `report`, and turn it into the report RMK gets
+fn serve(report: &mut [u8]) {
+    let mut response = [0; HOST_REPORT_SIZE];
+    handle_host_command(&report[1..], &mut response);
+
+    // Results end well before the report does, the last byte of a response is free for the report id
+    let mut packet = [0; HOST_REPORT_SIZE];
+    packet[0] = HOST_REPORT_ID;
+    packet[1..].copy_from_slice(&response[..HOST_REPORT_SIZE - 1]);
+    RESPONSE.lock(|slot| *slot.borrow_mut() = Some(packet));
+
+    report.fill(0);
+    report[0] = VIA_UNHANDLED;
+}
+
+fn is_vial(ep_type: EndpointType, max_packet_size: u16) -> bool {
+    ep_type == EndpointType::Interrupt && max_packet_size as usize == HOST_REPORT_SIZE
+}
+
+/// USB driver handing Vial's reports to RMK, but the ones carrying a host command, see the module documentation
+pub struct HostCommandDriver<D> {
+    inner: D,
+}
+
+impl<D> HostCommandDriver<D> {
+    pub fn new(inner: D) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, D: Driver<'a>> Driver<'a> for HostCommandDriver<D> {
+    type EndpointOut = HostEndpointOut<D::EndpointOut>;
+    type EndpointIn = HostEndpointIn<D::EndpointIn>;
+    type ControlPipe = D::ControlPipe;
+    type Bus = D::Bus;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        Ok(HostEndpointOut {
+            inner: self
+                .inner
+                .alloc_endpoint_out(ep_type, max_packet_size, interval_ms)?,
+            vial: is_vial(ep_type, max_packet_size),
+        })
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        Ok(HostEndpointIn {
+            inner: self
+                .inner
+                .alloc_endpoint_in(ep_type, max_packet_size, interval_ms)?,
+            vial: is_vial(ep_type, max_packet_size),
+        })
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        self.inner.start(control_max_packet_size)
+    }
+}
+
+pub struct HostEndpointOut<E> {
+    inner: E,
+    vial: bool,
+}
+
+impl<E: EndpointOut> Endpoint for HostEndpointOut<E> {
+    fn info(&self) -> &EndpointInfo {
+        self.inner.info()
+    }
+
+    async fn wait_enabled(&mut self) {
+        self.inner.wait_enabled().await
+    }
+}
+
+impl<E: EndpointOut> EndpointOut for HostEndpointOut<E> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let n = self.inner.read(buf).await?;
+        if self.vial && n > 0 && buf[0] == HOST_REPORT_ID {
+            serve(&mut buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+pub struct HostEndpointIn<E> {
+    inner: E,
+    vial: bool,
+}
+
+impl<E: EndpointIn> Endpoint for HostEndpointIn<E> {
+    fn info(&self) -> &EndpointInfo {
+        self.inner.info()
+    }
+
+    async fn wait_enabled(&mut self) {
+        self.inner.wait_enabled().await
+    }
+}
+
+impl<E: EndpointIn> EndpointIn for HostEndpointIn<E> {
+    async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        let response = self
+            .vial
+            .then(|| RESPONSE.lock(|slot| slot.borrow_mut().take()))
+            .flatten();
+        match response {
+            Some(packet) => self.inner.write(&packet).await,
+            None => self.inner.write(buf).await,
+        }
+    }
+}
@@ -0,0 +1,863 @@
+//! Builder of a keyboard scanning a chain of flip-flops, for every role it can have.
+//!
+//! [ChainKeyboard::new] takes the [ChainScanner]: the chain pins of a [SequentialMatrix], or on RP2040 the PIO
+//! scanning them. The other parts have defaults changed by the methods named after them.
+//! The transport and the split role then decide what `run` starts:
+//!
+//! * `.storage(..).usb(..)` - a whole keyboard, served over USB
+//! * `.storage(..).usb(..).split_central(monitors)` - the central of a split keyboard, with the monitors of its peripherals
+//! * `.split_peripheral(serial)` - a peripheral, sending its keys to the central over `serial`
+//!
+//! Chips with BLE serve the host with `.nrf_ble(..)` or `.esp_ble(..)` instead of `.usb(..)`, and an nRF peripheral
+//! reaches the central with `.split_peripheral_nrf_ble(..)`. RMK keeps its storage in their own flash then,
+//! so they take no `.storage(..)`, and the debounce settings are the defaults. So do boards with USB and
+//! the `_no_external_storage` feature.
+//!
+//! ```ignore
+//! ChainKeyboard::<_, ROW, COL>::new(pins)
+//!     .debounce_default(DEBOUNCE_DEFAULT)
+//!     .storage(FLASH.init(Mutex::new(flash)), STORAGE)
+//!     .usb(driver, IDENTITY.rmk_config(serial_number))
+//!     .run(&mut keymap::get_default_keymap())
+//!     .await
+//! ```
+#[cfg(any(
+    not(feature = "_no_external_storage"),
+    all(feature = "split", feature = "_nrf_ble")
+))]
+use core::future::Future;
+
+#[cfg(feature = "_nrf_ble")]
+use embassy_executor::Spawner;
+#[cfg(any(
+    not(feature = "_no_external_storage"),
+    all(feature = "split", feature = "_nrf_ble")
+))]
+use embassy_futures::join::join;
+#[cfg(all(
+    feature = "split",
+    not(any(feature = "_no_usb", feature = "_no_external_storage"))
+))]
+use embassy_futures::join::join3;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+#[cfg(not(feature = "_no_external_storage"))]
+use embassy_sync::mutex::Mutex;
+#[cfg(not(feature = "_no_usb"))]
+use embassy_usb::driver::Driver;
+use embedded_hal::digital::{InputPin, OutputPin};
+#[cfg(feature = "async_matrix")]
+use embedded_hal_async::digital::Wait;
+#[cfg(all(feature = "split", not(feature = "_nrf_ble")))]
+use embedded_io_async::{Read, Write};
+#[cfg(not(feature = "_no_external_storage"))]
+use embedded_storage_async::nor_flash::NorFlash;
+use rmk::action::KeyAction;
+#[cfg(feature = "_esp_ble")]
+use rmk::ble::esp::initialize_esp_ble_keyboard_with_config_and_run;
+#[cfg(feature = "_nrf_ble")]
+use rmk::ble::nrf::initialize_nrf_ble_keyboard_and_run;
+use rmk::config::RmkConfig;
+use rmk::debounce::DebouncerTrait;
+#[cfg(not(any(feature = "_no_usb", feature = "_ble")))]
+use rmk::initialize_usb_keyboard_and_run;
+use rmk::matrix::MatrixTrait;
+#[cfg(all(
+    feature = "split",
+    not(any(feature = "_no_usb", feature = "_no_external_storage"))
+))]
+use rmk::split::central::initialize_usb_split_central_and_run;
+#[cfg(all(feature = "split", feature = "_nrf_ble"))]
+use rmk::split::nrf::peripheral::initialize_nrf_ble_split_peripheral_and_run;
+#[cfg(all(feature = "split", not(feature = "_nrf_ble")))]
+use rmk::split::serial::initialize_serial_split_peripheral_and_run;
+
+use rmk_custom_device::debounce::{init_debounce_settings, DebounceSettings, RuntimeDebouncer};
+#[cfg(not(feature = "_no_external_storage"))]
+use rmk_custom_device::debounce::{load_debounce_settings, persist_debounce_settings};
+use rmk_custom_device::flash::SharedFlash;
+#[cfg(not(feature = "_no_external_storage"))]
+use rmk_custom_device::key_stats::{load_key_stats, persist_key_stats};
+use rmk_custom_device::matrix::{SequentialMatrix, SequentialMatrixPins};
+use rmk_custom_device::remap::{Identity, KeyRemap};
+#[cfg(feature = "async_matrix")]
+use rmk_custom_device::sleep::{DeepSleep, IdleConfig};
+use rmk_custom_device::timing::ChainTiming;
+
+#[cfg(not(feature = "_no_usb"))]
+use crate::host::HostCommandDriver;
+
+/// Flash shared by RMK's storage, the debounce settings and the key statistics
+pub type BoardFlash<F> = SharedFlash<'static, CriticalSectionRawMutex, F>;
+
+/// Sectors of the settings kept besides RMK's storage, each one a sector RMK's storage does not use
+#[derive(Clone, Copy, Debug)]
+pub struct StorageLayout {
+    /// Sector of the debounce settings changed at runtime
+    pub debounce_offset: u32,
+    /// Sector of the key statistics
+    pub stats_offset: u32,
+}
+
+/// No storage, a peripheral takes its settings from the central
+pub struct NoStorage;
+
+/// Flash kept for the keyboard's life
+#[cfg(not(feature = "_no_external_storage"))]
+pub struct FlashStorage<F: NorFlash + 'static> {
+    flash: &'static Mutex<CriticalSectionRawMutex, F>,
+    layout: StorageLayout,
+}
+
+#[cfg(not(feature = "_no_external_storage"))]
+impl<F: NorFlash + 'static> FlashStorage<F> {
+    /// Restore the settings stored in flash, and share it with RMK.
+    /// Returns the shared flash and the task storing the settings when they change.
+    async fn open(self, debounce_default: DebounceSettings) -> (BoardFlash<F>, impl Future) {
+        {
+            let mut flash = self.flash.lock().await;
+            // Debounce settings changed at runtime are kept in flash, else the board's defaults set them
+            let settings = load_debounce_settings(&mut *flash, self.layout.debounce_offset).await;
+            init_debounce_settings(settings.unwrap_or(debounce_default));
+            // Switch health counters add up across power cycles
+            if !load_key_stats(&mut *flash, self.layout.stats_offset).await {
+                defmt::info!("No key statistics stored");
+            }
+        }
+        let flash = SharedFlash::new(self.flash).await;
+        let persist = join(
+            persist_debounce_settings(flash.clone(), self.layout.debounce_offset),
+            persist_key_stats(flash.clone(), self.layout.stats_offset),
+        );
+        (flash, persist)
+    }
+}
+
+/// No transport set yet
+pub struct NoTransport;
+
+/// USB connection to the host, with RMK's config of the keyboard
+#[cfg(not(any(feature = "_no_usb", feature = "_ble")))]
+pub struct Usb<D, Out: OutputPin> {
+    driver: D,
+    config: RmkConfig<'static, Out>,
+}
+
+/// BLE connection of an nRF chip to the host, with USB on the chips having it and RMK's config of the keyboard
+#[cfg(feature = "_nrf_ble")]
+pub struct NrfBle<U, Out: OutputPin> {
+    /// USB driver, [NoUsb] on the chips without USB
+    #[cfg_attr(feature = "_no_usb", allow(dead_code))]
+    usb: U,
+    config: RmkConfig<'static, Out>,
+    central_addr: Option<[u8; 6]>,
+    spawner: Spawner,
+}
+
+/// No USB, on the nRF chips without it
+#[cfg(all(feature = "_nrf_ble", feature = "_no_usb"))]
+pub struct NoUsb;
+
+/// BLE connection of an ESP chip to the host, with RMK's config of the keyboard
+#[cfg(feature = "_esp_ble")]
+pub struct EspBle<Out: OutputPin> {
+    config: RmkConfig<'static, Out>,
+}
+
+/// Serial link of a peripheral to the central
+#[cfg(all(feature = "split", not(feature = "_nrf_ble")))]
+pub struct Serial<S> {
+    serial: S,
+}
+
+/// BLE link of an nRF peripheral to the central, with the static addresses of both
+#[cfg(all(feature = "split", feature = "_nrf_ble"))]
+pub struct NrfBleSplit {
+    central_addr: [u8; 6],
+    peripheral_addr: [u8; 6],
+    spawner: Spawner,
+}
+
+/// Whole keyboard, the default role
+pub struct Monolithic;
+
+/// Central of a split keyboard, with the monitors of its peripherals
+#[cfg(all(
+    feature = "split",
+    any(not(feature = "_no_external_storage"), feature = "_nrf_ble")
+))]
+pub struct Central<M> {
+    monitors: M,
+}
+
+/// Peripheral of a split keyboard
+#[cfg(feature = "split")]
+pub struct Peripheral;
+
+/// Hardware scanning the chain of a [ChainKeyboard], turned into the matrix debouncing it:
+/// the GPIOs of a [SequentialMatrix], or the PIO of `rmk_custom_device::rp_pio` on RP2040
+pub trait ChainScanner<const ROW: usize, const COL: usize> {
+    /// Output pin of RMK's config
+    type Out: OutputPin;
+    type Matrix<D: DebouncerTrait, R: KeyRemap>: MatrixTrait;
+
+    fn into_matrix<D: DebouncerTrait, R: KeyRemap>(
+        self,
+        debouncer: D,
+        remap: R,
+        timing: ChainTiming,
+    ) -> Self::Matrix<D, R>;
+
+    /// Put the MCU into deep sleep through `sleep` once `matrix` is idle for `idle.sleep_timeout_ms`
+    #[cfg(feature = "async_matrix")]
+    fn with_deep_sleep<D: DebouncerTrait, R: KeyRemap>(
+        matrix: Self::Matrix<D, R>,
+        sleep: &'static mut dyn DeepSleep,
+        idle: IdleConfig,
+    ) -> Self::Matrix<D, R>;
+}
+
+impl<
+        #[cfg(feature = "async_matrix")] In: Wait + InputPin,
+        #[cfg(not(feature = "async_matrix"))] In: InputPin,
+        Out: OutputPin,
+        const ROW: usize,
+        const COL: usize,
+    > ChainScanner<ROW, COL> for SequentialMatrixPins<In, Out>
+{
+    type Out = Out;
+    type Matrix<D: DebouncerTrait, R: KeyRemap> = SequentialMatrix<In, Out, D, ROW, COL, R>;
+
+    fn into_matrix<D: DebouncerTrait, R: KeyRemap>(
+        self,
+        debouncer: D,
+        remap: R,
+        timing: ChainTiming,
+    ) -> Self::Matrix<D, R> {
+        SequentialMatrix::new_with_timing(self, debouncer, remap, timing)
+    }
+
+    #[cfg(feature = "async_matrix")]
+    fn with_deep_sleep<D: DebouncerTrait, R: KeyRemap>(
+        matrix: Self::Matrix<D, R>,
+        sleep: &'static mut dyn DeepSleep,
+        idle: IdleConfig,
+    ) -> Self::Matrix<D, R> {
+        matrix.with_deep_sleep(sleep, idle)
+    }
+}
+
+/// Keyboard scanning the chain of `ROW` x `COL` flip-flops through `P`, see the module documentation
+pub struct ChainKeyboard<
+    P,
+    const ROW: usize,
+    const COL: usize,
+    D = RuntimeDebouncer<ROW, COL>,
+    R = Identity,
+    S = NoStorage,
+    T = NoTransport,
+    Role = Monolithic,
+> {
+    scanner: P,
+    debouncer: D,
+    remap: R,
+    timing: ChainTiming,
+    debounce_default: DebounceSettings,
+    storage: S,
+    transport: T,
+    role: Role,
+    #[cfg(feature = "async_matrix")]
+    sleep: Option<(&'static mut dyn DeepSleep, IdleConfig)>,
+}
+
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+    > ChainKeyboard<P, ROW, COL>
+{
+    /// Keyboard of the chain scanned by `scanner`, with the runtime debouncer, the chain as is in the keymap,
+    /// the default timing and debounce settings, and neither storage nor transport
+    pub fn new(scanner: P) -> Self {
+        Self {
+            scanner,
+            debouncer: RuntimeDebouncer::new(),
+            remap: Identity,
+            timing: ChainTiming::DEFAULT,
+            debounce_default: DebounceSettings::DEFAULT,
+            storage: NoStorage,
+            transport: NoTransport,
+            role: Monolithic,
+            #[cfg(feature = "async_matrix")]
+            sleep: None,
+        }
+    }
+}
+
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D,
+        R,
+        S,
+        T,
+        Role,
+    > ChainKeyboard<P, ROW, COL, D, R, S, T, Role>
+{
+    /// Debouncer of the chain, instead of the runtime one following `rmk_custom_device::debounce`'s settings
+    pub fn debouncer<D2: DebouncerTrait>(
+        self,
+        debouncer: D2,
+    ) -> ChainKeyboard<P, ROW, COL, D2, R, S, T, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: self.transport,
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+
+    /// Mapping of the chain to the keymap, e.g. a `Transform` placing a split half at its offset
+    pub fn remap<R2: KeyRemap>(
+        self,
+        remap: R2,
+    ) -> ChainKeyboard<P, ROW, COL, D, R2, S, T, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: self.transport,
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+
+    /// Timing of the chain signals, for boards whose flip-flops need longer than the default
+    pub fn timing(mut self, timing: ChainTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Debounce settings until ones are stored in flash, or sent by the central to a peripheral
+    pub fn debounce_default(mut self, settings: DebounceSettings) -> Self {
+        self.debounce_default = settings;
+        self
+    }
+
+    /// Put the MCU into deep sleep through `sleep` once idle for `idle.sleep_timeout_ms`
+    #[cfg(feature = "async_matrix")]
+    pub fn deep_sleep(mut self, sleep: &'static mut dyn DeepSleep, idle: IdleConfig) -> Self {
+        self.sleep = Some((sleep, idle));
+        self
+    }
+
+    /// Flash holding RMK's storage, and the debounce settings and key statistics at `layout`
+    #[cfg(not(feature = "_no_external_storage"))]
+    pub fn storage<F: NorFlash + 'static>(
+        self,
+        flash: &'static Mutex<CriticalSectionRawMutex, F>,
+        layout: StorageLayout,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, FlashStorage<F>, T, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: FlashStorage { flash, layout },
+            transport: self.transport,
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+}
+
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        S,
+        T,
+        Role,
+    > ChainKeyboard<P, ROW, COL, D, R, S, T, Role>
+{
+    fn into_parts(
+        self,
+    ) -> (
+        P::Matrix<D, R>,
+        DebounceSettings,
+        S,
+        T,
+        Role,
+    ) {
+        let matrix = self
+            .scanner
+            .into_matrix(self.debouncer, self.remap, self.timing);
+        #[cfg(feature = "async_matrix")]
+        let matrix = match self.sleep {
+            Some((sleep, idle)) => P::with_deep_sleep(matrix, sleep, idle),
+            None => matrix,
+        };
+        (
+            matrix,
+            self.debounce_default,
+            self.storage,
+            self.transport,
+            self.role,
+        )
+    }
+}
+
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D,
+        R,
+        S,
+        Role,
+    > ChainKeyboard<P, ROW, COL, D, R, S, NoTransport, Role>
+{
+    /// Serve the host over USB, with RMK's `config` of the keyboard and the host commands of `crate::host`
+    #[cfg(not(any(feature = "_no_usb", feature = "_ble")))]
+    pub fn usb<U: Driver<'static>>(
+        self,
+        driver: U,
+        config: RmkConfig<'static, P::Out>,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, S, Usb<U, P::Out>, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: Usb { driver, config },
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+
+    /// Serve the host over BLE and over USB through `driver`, with RMK's `config` of the keyboard and
+    /// the host commands of `crate::host` on USB. `central_addr` is the static address of a split central.
+    #[cfg(all(feature = "_nrf_ble", not(feature = "_no_usb")))]
+    pub fn nrf_ble<U: Driver<'static>>(
+        self,
+        driver: U,
+        config: RmkConfig<'static, P::Out>,
+        central_addr: Option<[u8; 6]>,
+        spawner: Spawner,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, S, NrfBle<U, P::Out>, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: NrfBle {
+                usb: driver,
+                config,
+                central_addr,
+                spawner,
+            },
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+
+    /// Serve the host over BLE, with RMK's `config` of the keyboard.
+    /// `central_addr` is the static address of a split central.
+    #[cfg(all(feature = "_nrf_ble", feature = "_no_usb"))]
+    pub fn nrf_ble(
+        self,
+        config: RmkConfig<'static, P::Out>,
+        central_addr: Option<[u8; 6]>,
+        spawner: Spawner,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, S, NrfBle<NoUsb, P::Out>, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: NrfBle {
+                usb: NoUsb,
+                config,
+                central_addr,
+                spawner,
+            },
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+
+    /// Serve the host over BLE, with RMK's `config` of the keyboard
+    #[cfg(feature = "_esp_ble")]
+    pub fn esp_ble(
+        self,
+        config: RmkConfig<'static, P::Out>,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, S, EspBle<P::Out>, Role> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: EspBle { config },
+            role: self.role,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "split",
+    any(not(feature = "_no_external_storage"), feature = "_nrf_ble")
+))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D,
+        R,
+        S,
+        T,
+    > ChainKeyboard<P, ROW, COL, D, R, S, T, Monolithic>
+{
+    /// Be the central of a split keyboard, running with `monitors`, which receive the keys of all peripherals.
+    /// Pass `async {}` when the central scans the peripheral's chain itself.
+    pub fn split_central<M: Future>(
+        self,
+        monitors: M,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, S, T, Central<M>> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: self.transport,
+            role: Central { monitors },
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+}
+
+#[cfg(feature = "split")]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D,
+        R,
+    > ChainKeyboard<P, ROW, COL, D, R>
+{
+    /// Be a peripheral of a split keyboard, sending its keys to the central over `serial`
+    #[cfg(not(feature = "_nrf_ble"))]
+    pub fn split_peripheral<S: Read + Write>(
+        self,
+        serial: S,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, NoStorage, Serial<S>, Peripheral> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: Serial { serial },
+            role: Peripheral,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+
+    /// Be a peripheral of a split keyboard, sending its keys over BLE to the central at `central_addr`
+    #[cfg(feature = "_nrf_ble")]
+    pub fn split_peripheral_nrf_ble(
+        self,
+        central_addr: [u8; 6],
+        peripheral_addr: [u8; 6],
+        spawner: Spawner,
+    ) -> ChainKeyboard<P, ROW, COL, D, R, NoStorage, NrfBleSplit, Peripheral> {
+        ChainKeyboard {
+            scanner: self.scanner,
+            debouncer: self.debouncer,
+            remap: self.remap,
+            timing: self.timing,
+            debounce_default: self.debounce_default,
+            storage: self.storage,
+            transport: NrfBleSplit {
+                central_addr,
+                peripheral_addr,
+                spawner,
+            },
+            role: Peripheral,
+            #[cfg(feature = "async_matrix")]
+            sleep: self.sleep,
+        }
+    }
+}
+
+#[cfg(not(any(feature = "_no_usb", feature = "_no_external_storage")))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        F: NorFlash + 'static,
+        U: Driver<'static>,
+    > ChainKeyboard<P, ROW, COL, D, R, FlashStorage<F>, Usb<U, P::Out>, Monolithic>
+{
+    /// Run the keyboard, with `default_keymap` until the keymap is stored in flash. This function should never return.
+    pub async fn run<const NUM_LAYER: usize>(
+        self,
+        default_keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
+    ) -> ! {
+        let (matrix, debounce_default, storage, Usb { driver, config }, _) = self.into_parts();
+        let (flash, persist) = storage.open(debounce_default).await;
+
+        let driver = HostCommandDriver::new(driver);
+        let keyboard =
+            initialize_usb_keyboard_and_run(matrix, driver, flash, default_keymap, config);
+        join(keyboard, persist).await;
+
+        // The fut should never return.
+        // If there's no fut, the feature flags must not be correct.
+        defmt::panic!("The keyboard should never return");
+    }
+}
+
+#[cfg(all(
+    feature = "split",
+    not(any(feature = "_no_usb", feature = "_no_external_storage"))
+))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        F: NorFlash + 'static,
+        U: Driver<'static>,
+        M: Future,
+    > ChainKeyboard<P, ROW, COL, D, R, FlashStorage<F>, Usb<U, P::Out>, Central<M>>
+{
+    /// Run the central, with `default_keymap` of the whole keyboard until the keymap is stored in flash.
+    /// This function should never return.
+    pub async fn run<const TOTAL_ROW: usize, const TOTAL_COL: usize, const NUM_LAYER: usize>(
+        self,
+        default_keymap: &mut [[[KeyAction; TOTAL_COL]; TOTAL_ROW]; NUM_LAYER],
+    ) -> ! {
+        let (matrix, debounce_default, storage, Usb { driver, config }, Central { monitors }) =
+            self.into_parts();
+        let (flash, persist) = storage.open(debounce_default).await;
+
+        let driver = HostCommandDriver::new(driver);
+        let central = initialize_usb_split_central_and_run::<
+            _,
+            _,
+            HostCommandDriver<U>,
+            BoardFlash<F>,
+            TOTAL_ROW,
+            TOTAL_COL,
+            NUM_LAYER,
+        >(matrix, driver, flash, default_keymap, config);
+        join3(central, persist, monitors).await;
+
+        defmt::panic!("The central should never return");
+    }
+}
+
+#[cfg(all(feature = "split", not(feature = "_nrf_ble")))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        S: Read + Write,
+    > ChainKeyboard<P, ROW, COL, D, R, NoStorage, Serial<S>, Peripheral>
+{
+    /// Run the peripheral
+    pub async fn run(self) {
+        let (matrix, debounce_default, _, Serial { serial }, _) = self.into_parts();
+        // Until the central sends its settings over the split link
+        init_debounce_settings(debounce_default);
+
+        initialize_serial_split_peripheral_and_run::<_, S, ROW, COL>(matrix, serial).await;
+    }
+}
+
+#[cfg(all(
+    feature = "_no_external_storage",
+    not(any(feature = "_no_usb", feature = "_ble"))
+))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        U: Driver<'static>,
+    > ChainKeyboard<P, ROW, COL, D, R, NoStorage, Usb<U, P::Out>, Monolithic>
+{
+    /// Run the keyboard, with `default_keymap` until RMK stores the keymap. This function should never return.
+    pub async fn run<const NUM_LAYER: usize>(
+        self,
+        default_keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
+    ) -> ! {
+        let (matrix, debounce_default, _, Usb { driver, config }, _) = self.into_parts();
+        init_debounce_settings(debounce_default);
+
+        let driver = HostCommandDriver::new(driver);
+        initialize_usb_keyboard_and_run(matrix, driver, default_keymap, config).await
+    }
+}
+
+#[cfg(feature = "_nrf_ble")]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        #[cfg(not(feature = "_no_usb"))] U: Driver<'static>,
+        #[cfg(feature = "_no_usb")] U,
+    > ChainKeyboard<P, ROW, COL, D, R, NoStorage, NrfBle<U, P::Out>, Monolithic>
+{
+    /// Run the keyboard, with `default_keymap` until RMK stores the keymap. This function should never return.
+    pub async fn run<const NUM_LAYER: usize>(
+        self,
+        default_keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
+    ) -> ! {
+        let (matrix, debounce_default, _, ble, _) = self.into_parts();
+        init_debounce_settings(debounce_default);
+
+        initialize_nrf_ble_keyboard_and_run(
+            matrix,
+            #[cfg(not(feature = "_no_usb"))]
+            HostCommandDriver::new(ble.usb),
+            default_keymap,
+            ble.config,
+            ble.central_addr,
+            ble.spawner,
+        )
+        .await
+    }
+}
+
+#[cfg(all(feature = "split", feature = "_nrf_ble"))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+        #[cfg(not(feature = "_no_usb"))] U: Driver<'static>,
+        #[cfg(feature = "_no_usb")] U,
+        M: Future,
+    > ChainKeyboard<P, ROW, COL, D, R, NoStorage, NrfBle<U, P::Out>, Central<M>>
+{
+    /// Run the central, with `default_keymap` of the whole keyboard until RMK stores the keymap.
+    /// This function should never return.
+    pub async fn run<const TOTAL_ROW: usize, const TOTAL_COL: usize, const NUM_LAYER: usize>(
+        self,
+        default_keymap: &mut [[[KeyAction; TOTAL_COL]; TOTAL_ROW]; NUM_LAYER],
+    ) -> ! {
+        let (matrix, debounce_default, _, ble, Central { monitors }) = self.into_parts();
+        init_debounce_settings(debounce_default);
+
+        let central = initialize_nrf_ble_keyboard_and_run(
+            matrix,
+            #[cfg(not(feature = "_no_usb"))]
+            HostCommandDriver::new(ble.usb),
+            default_keymap,
+            ble.config,
+            ble.central_addr,
+            ble.spawner,
+        );
+        join(central, monitors).await;
+
+        defmt::panic!("The central should never return");
+    }
+}
+
+#[cfg(feature = "_esp_ble")]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+    > ChainKeyboard<P, ROW, COL, D, R, NoStorage, EspBle<P::Out>, Monolithic>
+{
+    /// Run the keyboard, with `default_keymap` until RMK stores the keymap. This function should never return.
+    pub async fn run<const NUM_LAYER: usize>(
+        self,
+        default_keymap: &mut [[[KeyAction; COL]; ROW]; NUM_LAYER],
+    ) -> ! {
+        let (matrix, debounce_default, _, EspBle { config }, _) = self.into_parts();
+        init_debounce_settings(debounce_default);
+
+        initialize_esp_ble_keyboard_with_config_and_run(matrix, default_keymap, config).await
+    }
+}
+
+#[cfg(all(feature = "split", feature = "_nrf_ble"))]
+impl<
+        P: ChainScanner<ROW, COL>,
+        const ROW: usize,
+        const COL: usize,
+        D: DebouncerTrait,
+        R: KeyRemap,
+    > ChainKeyboard<P, ROW, COL, D, R, NoStorage, NrfBleSplit, Peripheral>
+{
+    /// Run the peripheral
+    pub async fn run(self) {
+        let (
+            matrix,
+            debounce_default,
+            _,
+            NrfBleSplit {
+                central_addr,
+                peripheral_addr,
+                spawner,
+            },
+            _,
+        ) = self.into_parts();
+        // Until the central sends its settings over the split link
+        init_debounce_settings(debounce_default);
+
+        initialize_nrf_ble_split_peripheral_and_run::<_, ROW, COL>(
+            matrix,
+            central_addr,
+            peripheral_addr,
+            spawner,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,27 @@
+//! Firmware core shared by the boards scanning a chain of flip-flops.
+//!
+//! A board is a `keyboard.toml`, turned into code by `rmk-chain-keyboard-build` and included with [board!],
+//! and a `main` putting together a [ChainKeyboard] for each of its images: a whole keyboard,
+//! the central or a peripheral of a split one. The split roles need the `split` feature.
+#![no_std]
+
+pub mod board;
+pub mod host;
+pub mod keyboard;
+#[cfg(feature = "rp2040")]
+pub mod rp;
+
+pub use board::BoardIdentity;
+pub use keyboard::{ChainKeyboard, ChainScanner, StorageLayout};
+
+/// Crates the exported macros and the generated board definition expand to, so that boards need not name them
+#[doc(hidden)]
+pub mod __export {
+    pub use embassy_futures;
+    #[cfg(feature = "rp2040")]
+    pub use embassy_rp;
+    pub use rmk;
+    pub use rmk_custom_device;
+    #[cfg(feature = "rp2040")]
+    pub use static_cell;
+}
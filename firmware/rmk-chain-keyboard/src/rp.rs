@@ -0,0 +1,217 @@
+//! RP2040 setup of a board: the serial number, the PIO chain scanner, and the macros the generated pin
+//! and serial link setup expands to.
+use embassy_rp::dma::Channel;
+use embassy_rp::flash::{Flash, Instance, Mode};
+use embassy_rp::gpio::Output;
+use embassy_rp::pio;
+use rmk::debounce::DebouncerTrait;
+use rmk_custom_device::remap::KeyRemap;
+use rmk_custom_device::rp_pio::{PioChainPins, PioSequentialMatrix};
+use rmk_custom_device::rp_sleep::RpClockGatedSleep;
+#[cfg(feature = "async_matrix")]
+use rmk_custom_device::sleep::{DeepSleep, IdleConfig};
+use rmk_custom_device::timing::ChainTiming;
+use rmk_custom_device::usb::serial_number;
+use static_cell::StaticCell;
+
+use crate::ChainScanner;
+
+/// USB serial number starting with `prefix` and ending with the unique ID of the flash chip,
+/// so identical boards can be told apart. Call it once.
+///
+/// The serial number is up to 32 bytes, so `prefix` is up to 16, which `rmk-chain-keyboard-build` checks.
+pub fn unique_serial_number<T: Instance, M: Mode, const FLASH_SIZE: usize>(
+    flash: &mut Flash<'_, T, M, FLASH_SIZE>,
+    prefix: &str,
+) -> &'static str {
+    let mut uid = [0; 8];
+    if flash.blocking_unique_id(&mut uid).is_err() {
+        defmt::warn!("Cannot read flash unique ID");
+    }
+    static SERIAL_NUMBER: StaticCell<[u8; 32]> = StaticCell::new();
+    serial_number(SERIAL_NUMBER.init([0; 32]), prefix, &uid)
+}
+
+/// Clock-gated sleep of the RP2040 kept for [ChainKeyboard::deep_sleep](crate::ChainKeyboard::deep_sleep). Call it once.
+pub fn clock_gated_sleep(sleep: RpClockGatedSleep) -> &'static mut RpClockGatedSleep {
+    static SLEEP: StaticCell<RpClockGatedSleep> = StaticCell::new();
+    SLEEP.init(sleep)
+}
+
+/// The PIO clocks the chain, RMK's config drives no other pins than GPIOs
+impl<PIO: pio::Instance, const SM: usize, C: Channel, const ROW: usize, const COL: usize>
+    ChainScanner<ROW, COL> for PioChainPins<'static, PIO, SM, C>
+{
+    type Out = Output<'static>;
+    type Matrix<D: DebouncerTrait, R: KeyRemap> =
+        PioSequentialMatrix<'static, PIO, SM, C, D, ROW, COL, R>;
+
+    fn into_matrix<D: DebouncerTrait, R: KeyRemap>(
+        self,
+        debouncer: D,
+        remap: R,
+        timing: ChainTiming,
+    ) -> Self::Matrix<D, R> {
+        PioSequentialMatrix::new(self, debouncer, remap, timing)
+    }
+
+    /// `sleep` must keep the PIO clocked, see [RpClockGatedSleep::with_pio_matrix]
+    #[cfg(feature = "async_matrix")]
+    fn with_deep_sleep<D: DebouncerTrait, R: KeyRemap>(
+        matrix: Self::Matrix<D, R>,
+        sleep: &'static mut dyn DeepSleep,
+        idle: IdleConfig,
+    ) -> Self::Matrix<D, R> {
+        matrix.with_deep_sleep(sleep, idle)
+    }
+}
+
+#[macro_export]
+macro_rules! config_output_pin_rp {
+    ($p:ident, $out_pin:ident) => {{
+        $crate::__export::embassy_rp::gpio::Output::new(
+            $crate::__export::embassy_rp::gpio::AnyPin::from($p.$out_pin),
+            $crate::__export::embassy_rp::gpio::Level::Low,
+        )
+    }};
+}
+
+#[macro_export]
+macro_rules! config_input_pin_rp {
+    ($p:ident, $in_pin:ident) => {{
+        $crate::config_input_pin_rp!($p, $in_pin, Down)
+    }};
+    ($p:ident, $in_pin:ident, $pull:ident) => {{
+        $crate::__export::embassy_rp::gpio::Input::new(
+            $crate::__export::embassy_rp::gpio::AnyPin::from($p.$in_pin),
+            $crate::__export::embassy_rp::gpio::Pull::$pull,
+        )
+    }};
+}
+
+#[macro_export]
+macro_rules! config_sequential_matrix_pins_rp {
+    (
+        peripherals: $p:ident,
+        row_clock: $row:ident,
+        col_clock: $col:ident,
+        any_not: $any_not:ident,
+        reset_not: $reset_not:ident,
+        input: $input:ident,
+    ) => {{
+        $crate::config_sequential_matrix_pins_rp!(
+            peripherals: $p,
+            row_clock: $row,
+            col_clock: $col,
+            any_not: $any_not,
+            reset_not: $reset_not,
+            input: $input,
+            input_pull: Down,
+            input_active_low: false,
+        )
+    }};
+    (
+        peripherals: $p:ident,
+        row_clock: $row:ident,
+        col_clock: $col:ident,
+        any_not: $any_not:ident,
+        reset_not: $reset_not:ident,
+        input: $input:ident,
+        input_pull: $pull:ident,
+        input_active_low: $active_low:literal,
+    ) => {{
+        $crate::__export::rmk_custom_device::matrix::SequentialMatrixPins::new(
+            $crate::config_output_pin_rp!($p, $row),
+            $crate::config_output_pin_rp!($p, $col),
+            $crate::config_output_pin_rp!($p, $any_not),
+            $crate::config_output_pin_rp!($p, $reset_not),
+            $crate::config_input_pin_rp!($p, $input, $pull),
+        )
+        .with_input_active_low($active_low)
+    }};
+}
+
+/// Chain of `rows` x `cols` positions clocked by state machine 0 of `pio`, its samples moved by `dma`,
+/// see `rmk_custom_device::rp_pio`. `irqs` binds the interrupt of `pio`.
+#[macro_export]
+macro_rules! config_pio_chain_pins_rp {
+    (
+        peripherals: $p:ident,
+        irqs: $irqs:ident,
+        pio: $pio:ident,
+        dma: $dma:ident,
+        rows: $rows:expr,
+        cols: $cols:expr,
+        row_clock: $row:ident,
+        col_clock: $col:ident,
+        any_not: $any_not:ident,
+        reset_not: $reset_not:ident,
+        input: $input:ident,
+        input_pull: $pull:ident,
+        input_active_low: $active_low:literal,
+    ) => {{
+        use $crate::__export::rmk_custom_device::rp_pio::bitmap_words;
+        use $crate::__export::static_cell::StaticCell;
+        static SAMPLES: StaticCell<[u32; bitmap_words($rows, $cols)]> = StaticCell::new();
+        let $crate::__export::embassy_rp::pio::Pio { common, sm0, .. } =
+            $crate::__export::embassy_rp::pio::Pio::new($p.$pio, $irqs);
+        $crate::__export::rmk_custom_device::rp_pio::PioChainPins::new(
+            common,
+            sm0,
+            $p.$dma,
+            SAMPLES.init([0; bitmap_words($rows, $cols)]),
+            $p.$row,
+            $p.$col,
+            $p.$any_not,
+            $p.$reset_not,
+            $p.$input,
+            $crate::__export::embassy_rp::gpio::Pull::$pull,
+        )
+        .with_input_active_low($active_low)
+    }};
+}
+
+/// UART link of a split half
+#[cfg(feature = "split")]
+#[macro_export]
+macro_rules! config_split_serial_rp {
+    ($p:ident, $irqs:ident, $uart:ident, $tx_pin:ident, $rx_pin:ident) => {{
+        use $crate::__export::rmk::split::SPLIT_MESSAGE_MAX_SIZE;
+        use $crate::__export::static_cell::StaticCell;
+        static TX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
+        let tx_buf = &mut TX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
+        static RX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
+        let rx_buf = &mut RX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
+        $crate::__export::embassy_rp::uart::BufferedUart::new(
+            $p.$uart,
+            $irqs,
+            $p.$tx_pin,
+            $p.$rx_pin,
+            tx_buf,
+            rx_buf,
+            $crate::__export::embassy_rp::uart::Config::default(),
+        )
+    }};
+}
+
+/// Half-duplex link of a split half, on a single wire driven by a PIO
+#[cfg(feature = "split")]
+#[macro_export]
+macro_rules! config_split_half_duplex_rp {
+    ($p:ident, $irqs:ident, $pio:ident, $pin:ident) => {{
+        let $crate::__export::embassy_rp::pio::Pio {
+            mut common,
+            sm0,
+            sm1,
+            ..
+        } = $crate::__export::embassy_rp::pio::Pio::new($p.$pio, $irqs);
+        // Same rate as the UART links
+        $crate::__export::rmk_custom_device::rp_half_duplex::PioHalfDuplexUart::new(
+            &mut common,
+            sm0,
+            sm1,
+            $p.$pin,
+            115_200,
+        )
+    }};
+}
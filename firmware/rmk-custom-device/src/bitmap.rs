@@ -0,0 +1,84 @@
+//! Packed bitmap of the chain positions, one word per row.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyBitmap<const ROW: usize, const COL: usize> {
+    rows: [u64; ROW],
+}
+
+impl<const ROW: usize, const COL: usize> KeyBitmap<ROW, COL> {
+    const FITS: () = assert!(COL <= 64, "KeyBitmap holds up to 64 columns");
+
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS;
+        Self { rows: [0; ROW] }
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        self.rows[row] & (1 << col) != 0
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: bool) {
+        if value {
+            self.rows[row] |= 1 << col;
+        } else {
+            self.rows[row] &= !(1 << col);
+        }
+    }
+
+    /// Bits of a row, column 0 at the LSB
+    pub fn row(&self, row: usize) -> u64 {
+        self.rows[row]
+    }
+
+    pub fn set_row(&mut self, row: usize, bits: u64) {
+        self.rows[row] = bits;
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; ROW];
+    }
+
+    /// Positions set in exactly one of the bitmaps
+    pub fn xor(&self, other: &Self) -> Self {
+        let mut out = *self;
+        out.rows.iter_mut().zip(other.rows.iter()).for_each(|(a, b)| *a ^= b);
+        out
+    }
+
+    /// Positions set in either of the bitmaps
+    pub fn or(&self, other: &Self) -> Self {
+        let mut out = *self;
+        out.rows.iter_mut().zip(other.rows.iter()).for_each(|(a, b)| *a |= b);
+        out
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|bits| *bits == 0)
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.rows.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    /// Iterate (row, col) of the set positions, in chain order
+    pub fn iter_ones(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.rows.iter().enumerate().flat_map(|(row, bits)| {
+            let mut bits = *bits;
+            core::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let col = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some((row, col))
+            })
+        })
+    }
+}
+
+impl<const ROW: usize, const COL: usize> Default for KeyBitmap<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
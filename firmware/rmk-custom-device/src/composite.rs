@@ -0,0 +1,122 @@
+//! Several matrices scanned concurrently as one logical matrix.
+//!
+//! Each child places its keys into its own region of the combined keymap through its [KeyRemap](crate::remap::KeyRemap),
+//! which translates both the events it sends and the state lookups it serves.
+//! The composite only dispatches state lookups to the child owning the position.
+use embassy_futures::join::join;
+#[cfg(feature = "async_matrix")]
+use embassy_futures::select::select;
+use rmk::matrix::{KeyState, MatrixTrait};
+
+/// Matrix placed into a region of a larger keymap
+pub trait MappedMatrix: MatrixTrait {
+    /// Whether the keymap position (row, col) belongs to this matrix
+    fn contains(&self, row: usize, col: usize) -> bool;
+
+    /// Keymap position of the key at (row, col) of this matrix, `None` if there is no key or it is unmapped
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)>;
+}
+
+/// Two child matrices combined into one `ROW` x `COL` matrix.
+///
+/// Nest composites to combine more than two, e.g. `CompositeMatrix<A, CompositeMatrix<B, C, ROW, COL>, ROW, COL>`.
+pub struct CompositeMatrix<
+    A: MappedMatrix,
+    B: MappedMatrix,
+    const ROW: usize,
+    const COL: usize,
+> {
+    a: A,
+    b: B,
+}
+
+impl<
+    A: MappedMatrix,
+    B: MappedMatrix,
+    const ROW: usize,
+    const COL: usize,
+> CompositeMatrix<A, B, ROW, COL> {
+    /// Combine `a` and `b`, whose keys must land inside the `ROW` x `COL` keymap and apart, checked in debug builds
+    pub fn new(a: A, b: B) -> Self {
+        if cfg!(debug_assertions) {
+            Self::check_region(&a, |_, _| false);
+            Self::check_region(&b, |row, col| a.contains(row, col));
+        }
+        Self { a, b }
+    }
+
+    /// Panic if a key of `child` lands outside the keymap, or on a position `taken` by the other child
+    fn check_region<M: MappedMatrix>(child: &M, taken: impl Fn(usize, usize) -> bool) {
+        for row in 0..M::ROW {
+            for col in 0..M::COL {
+                if let Some((l_row, l_col)) = child.to_logical(row, col) {
+                    assert!(
+                        l_row < ROW && l_col < COL,
+                        "key ({}, {}) of a composite child lands on ({}, {}), outside the {}x{} keymap",
+                        row, col, l_row, l_col, ROW, COL
+                    );
+                    assert!(
+                        !taken(l_row, l_col),
+                        "keymap position ({}, {}) is claimed by both children of a composite",
+                        l_row, l_col
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl<
+    A: MappedMatrix,
+    B: MappedMatrix,
+    const ROW: usize,
+    const COL: usize,
+> MatrixTrait for CompositeMatrix<A, B, ROW, COL> {
+    const ROW: usize = ROW;
+    const COL: usize = COL;
+
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {
+        select(self.a.wait_for_key(), self.b.wait_for_key()).await;
+    }
+
+    /// Run the scanning loops of both children, each sends its own events
+    async fn scan(&mut self) {
+        join(self.a.scan(), self.b.scan()).await;
+    }
+
+    /// Read key state at position (row, col), positions no child owns read as released
+    fn get_key_state(&mut self, row: usize, col: usize) -> KeyState {
+        if self.a.contains(row, col) {
+            self.a.get_key_state(row, col)
+        } else if self.b.contains(row, col) {
+            self.b.get_key_state(row, col)
+        } else {
+            KeyState::new()
+        }
+    }
+
+    fn update_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
+        if self.a.contains(row, col) {
+            self.a.update_key_state(row, col, f);
+        } else if self.b.contains(row, col) {
+            self.b.update_key_state(row, col, f);
+        }
+    }
+}
+
+impl<
+    A: MappedMatrix,
+    B: MappedMatrix,
+    const ROW: usize,
+    const COL: usize,
+> MappedMatrix for CompositeMatrix<A, B, ROW, COL> {
+    fn contains(&self, row: usize, col: usize) -> bool {
+        self.a.contains(row, col) || self.b.contains(row, col)
+    }
+
+    /// The composite spans the keymap, so its keys are at their keymap positions
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.contains(row, col).then_some((row, col))
+    }
+}
@@ -0,0 +1,267 @@
+//! Debouncer whose algorithm and thresholds can be changed while the keyboard runs.
+//!
+//! The settings are global: [set_debounce_settings] applies them to every [RuntimeDebouncer] from their next scan.
+//! They are kept in a flash sector of their own by [persist_debounce_settings], and the central sends them
+//! to its peripherals over the split links, see [link](crate::link).
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Instant, Timer};
+use embedded_storage_async::nor_flash::NorFlash;
+use rmk::{
+    debounce::{DebounceState, DebouncerTrait},
+    matrix::KeyState,
+};
+
+use crate::bitmap::KeyBitmap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DebounceAlgorithm {
+    /// Both edges are sent once the input has been stable for `press_ms`, `release_ms` is unused
+    Defer = 0,
+    /// Presses are sent at once, unless within `press_ms` of the last release.
+    /// Releases are sent once the input has been stable for `release_ms`
+    EagerPress = 1,
+    /// Presses are sent once the input has been stable for `press_ms`, releases for `release_ms`
+    Asymmetric = 2,
+}
+
+impl DebounceAlgorithm {
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Defer),
+            1 => Some(Self::EagerPress),
+            2 => Some(Self::Asymmetric),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DebounceSettings {
+    pub algorithm: DebounceAlgorithm,
+    pub press_ms: u8,
+    pub release_ms: u8,
+}
+
+impl DebounceSettings {
+    /// Same latency as RMK's default debouncer
+    pub const DEFAULT: Self = Self {
+        algorithm: DebounceAlgorithm::Defer,
+        press_ms: 10,
+        release_ms: 10,
+    };
+
+    /// Algorithm, `press_ms` and `release_ms`, as sent to the host and the peripherals
+    pub const fn to_bytes(self) -> [u8; 3] {
+        [self.algorithm as u8, self.press_ms, self.release_ms]
+    }
+
+    pub const fn from_bytes(bytes: [u8; 3]) -> Option<Self> {
+        match DebounceAlgorithm::from_u8(bytes[0]) {
+            Some(algorithm) => Some(Self {
+                algorithm,
+                press_ms: bytes[1],
+                release_ms: bytes[2],
+            }),
+            None => None,
+        }
+    }
+
+    const fn to_bits(self) -> u32 {
+        let [algorithm, press_ms, release_ms] = self.to_bytes();
+        algorithm as u32 | (press_ms as u32) << 8 | (release_ms as u32) << 16
+    }
+
+    /// Stable time needed before sending an edge to `pressed`, 0 sends it at once
+    fn threshold(&self, pressed: bool) -> u8 {
+        match (self.algorithm, pressed) {
+            (DebounceAlgorithm::Defer, _) => self.press_ms,
+            (DebounceAlgorithm::EagerPress, true) => 0,
+            (DebounceAlgorithm::EagerPress, false) => self.release_ms,
+            (DebounceAlgorithm::Asymmetric, true) => self.press_ms,
+            (DebounceAlgorithm::Asymmetric, false) => self.release_ms,
+        }
+    }
+}
+
+impl Default for DebounceSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Current settings, packed so that they change at once
+static SETTINGS: AtomicU32 = AtomicU32::new(DebounceSettings::DEFAULT.to_bits());
+/// Raised by every change of the settings, for [persist_debounce_settings]
+static SETTINGS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn debounce_settings() -> DebounceSettings {
+    let [algorithm, press_ms, release_ms, _] = SETTINGS.load(Ordering::Relaxed).to_le_bytes();
+    DebounceSettings::from_bytes([algorithm, press_ms, release_ms]).unwrap_or_default()
+}
+
+/// Settings at boot, from flash or the board's defaults. They are not stored, so that only changes made at runtime
+/// are kept in flash and the board's defaults still apply to boards without any
+pub fn init_debounce_settings(settings: DebounceSettings) {
+    log_settings(settings);
+    SETTINGS.store(settings.to_bits(), Ordering::Relaxed);
+}
+
+/// Change the settings at runtime, they are stored by [persist_debounce_settings]
+pub fn set_debounce_settings(settings: DebounceSettings) {
+    if settings != debounce_settings() {
+        log_settings(settings);
+        SETTINGS.store(settings.to_bits(), Ordering::Relaxed);
+        SETTINGS_CHANGED.signal(());
+    }
+}
+
+/// Log the settings taking effect, and a `release_ms` they leave unused
+fn log_settings(settings: DebounceSettings) {
+    info!("Debounce settings: {:?}", settings);
+    if settings.algorithm == DebounceAlgorithm::Defer && settings.release_ms != settings.press_ms {
+        warn!(
+            "Defer debounces releases for press_ms too, release_ms = {} is unused",
+            settings.release_ms
+        );
+    }
+}
+
+/// Marks a sector holding debounce settings
+const MAGIC: [u8; 4] = *b"DBNC";
+/// Magic, settings, padding, and the complement of settings and padding
+const RECORD_SIZE: usize = 12;
+
+/// Settings stored in the sector at `offset`, `None` if no valid record is there
+pub async fn load_debounce_settings<F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+) -> Option<DebounceSettings> {
+    let mut record = [0; RECORD_SIZE];
+    flash.read(offset, &mut record).await.ok()?;
+    let valid = record[..4] == MAGIC && (4..8).all(|i| record[i] == !record[i + 4]);
+    if !valid {
+        return None;
+    }
+    DebounceSettings::from_bytes([record[4], record[5], record[6]])
+}
+
+/// Store `settings` in the sector at `offset`, which nothing else may use
+pub async fn store_debounce_settings<F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+    settings: DebounceSettings,
+) -> Result<(), F::Error> {
+    assert!(
+        RECORD_SIZE % F::WRITE_SIZE == 0,
+        "Debounce record is not a multiple of the flash write size"
+    );
+    let [algorithm, press_ms, release_ms] = settings.to_bytes();
+    let mut record = [0; RECORD_SIZE];
+    record[..4].copy_from_slice(&MAGIC);
+    record[4..8].copy_from_slice(&[algorithm, press_ms, release_ms, 0]);
+    record[8..].copy_from_slice(&[!algorithm, !press_ms, !release_ms, !0]);
+    flash.erase(offset, offset + F::ERASE_SIZE as u32).await?;
+    flash.write(offset, &record).await
+}
+
+/// Store the settings in the sector at `offset` after every change. This function never returns.
+///
+/// Changes are stored once they stop for a while, so dragging a slider in a host tool does not wear the flash.
+pub async fn persist_debounce_settings<F: NorFlash>(mut flash: F, offset: u32) -> ! {
+    loop {
+        SETTINGS_CHANGED.wait().await;
+        while let Either::First(()) = select(SETTINGS_CHANGED.wait(), Timer::after_secs(2)).await {}
+
+        let settings = debounce_settings();
+        if load_debounce_settings(&mut flash, offset).await != Some(settings) {
+            info!("Storing debounce settings");
+            if store_debounce_settings(&mut flash, offset, settings)
+                .await
+                .is_err()
+            {
+                warn!("Failed to store debounce settings");
+            }
+        }
+    }
+}
+
+/// Debouncer following the global [DebounceSettings], indexed (row, col) as the matrices of this crate call it
+pub struct RuntimeDebouncer<const ROW: usize, const COL: usize> {
+    /// Positions whose input disagrees with their key state, waiting to be stable
+    settling: KeyBitmap<ROW, COL>,
+    /// Positions released recently with [DebounceAlgorithm::EagerPress], which ignore presses for `press_ms`
+    locked: KeyBitmap<ROW, COL>,
+    /// Time in ms when a position started settling, or when it was released if locked
+    since: [[u32; COL]; ROW],
+}
+
+impl<const ROW: usize, const COL: usize> RuntimeDebouncer<ROW, COL> {
+    pub const fn new() -> Self {
+        Self {
+            settling: KeyBitmap::new(),
+            locked: KeyBitmap::new(),
+            since: [[0; COL]; ROW],
+        }
+    }
+}
+
+impl<const ROW: usize, const COL: usize> Default for RuntimeDebouncer<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> DebouncerTrait for RuntimeDebouncer<ROW, COL> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn detect_change_with_debounce(
+        &mut self,
+        row: usize,
+        col: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        if pin_state == key_state.pressed {
+            self.settling.set(row, col, false);
+            return DebounceState::Ignored;
+        }
+
+        let settings = debounce_settings();
+        let now = Instant::now().as_millis() as u32;
+        let elapsed = now.wrapping_sub(self.since[row][col]);
+        let eager = settings.algorithm == DebounceAlgorithm::EagerPress;
+        if eager && pin_state && self.locked.get(row, col) {
+            if elapsed < settings.press_ms as u32 {
+                return DebounceState::InProgress;
+            }
+            self.locked.set(row, col, false);
+        }
+
+        let threshold = settings.threshold(pin_state) as u32;
+        if threshold > 0 {
+            if !self.settling.get(row, col) {
+                self.settling.set(row, col, true);
+                self.since[row][col] = now;
+                return DebounceState::InProgress;
+            }
+            if elapsed < threshold {
+                return DebounceState::InProgress;
+            }
+        }
+
+        self.settling.set(row, col, false);
+        if eager && !pin_state {
+            self.locked.set(row, col, true);
+            self.since[row][col] = now;
+        }
+        DebounceState::Debounced
+    }
+}
@@ -0,0 +1,114 @@
+//! Faults of the flip-flop chain found by the matrix' diagnostics pass.
+//!
+//! A fault is set once it is seen in [FAULT_CONFIRM_PASSES] passes in a row, and cleared by the first pass without it.
+//! The matrix sends no key events while any fault is set.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ChainFault {
+    /// The selection token does not reach the terminator, a module is missing or badly seated
+    BrokenChain = 1 << 0,
+    /// `input` reads high while nothing is selected
+    StuckHigh = 1 << 1,
+    /// `input` reads low while `any_not` is low and the last scan read keys pressed
+    StuckLow = 1 << 2,
+    /// `input` stays high after `any_not` is released
+    AnyNotStuck = 1 << 3,
+    /// Every fitted position reads pressed at once, on chains with more positions than fingers
+    AllPressed = 1 << 4,
+}
+
+impl ChainFault {
+    pub const ALL: [ChainFault; 5] = [
+        ChainFault::BrokenChain,
+        ChainFault::StuckHigh,
+        ChainFault::StuckLow,
+        ChainFault::AnyNotStuck,
+        ChainFault::AllPressed,
+    ];
+}
+
+/// Set of [ChainFault]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChainFaults(u8);
+
+impl ChainFaults {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn insert(&mut self, fault: ChainFault) {
+        self.0 |= fault as u8;
+    }
+
+    pub fn contains(&self, fault: ChainFault) -> bool {
+        self.0 & fault as u8 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn union(&self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ChainFault> + '_ {
+        ChainFault::ALL.into_iter().filter(|fault| self.contains(*fault))
+    }
+}
+
+/// Number of diagnostics passes in a row a fault must be seen in before it is set
+pub const FAULT_CONFIRM_PASSES: u8 = 3;
+
+/// Counts the passes in a row each fault was seen in
+pub(crate) struct FaultFilter {
+    passes: [u8; ChainFault::ALL.len()],
+}
+
+impl FaultFilter {
+    pub(crate) const fn new() -> Self {
+        Self {
+            passes: [0; ChainFault::ALL.len()],
+        }
+    }
+
+    /// Add a diagnostics pass which saw `faults`, and return the confirmed ones
+    pub(crate) fn update(&mut self, faults: ChainFaults) -> ChainFaults {
+        let mut confirmed = ChainFaults::empty();
+        for (fault, passes) in ChainFault::ALL.into_iter().zip(self.passes.iter_mut()) {
+            *passes = if faults.contains(fault) {
+                passes.saturating_add(1)
+            } else {
+                0
+            };
+            if *passes >= FAULT_CONFIRM_PASSES {
+                confirmed.insert(fault);
+            }
+        }
+        confirmed
+    }
+}
+
+/// Confirmed faults of the chain, only the matrix task sets them
+static CHAIN_FAULTS: AtomicU8 = AtomicU8::new(0);
+
+/// Faults set by the last diagnostics pass
+pub fn chain_faults() -> ChainFaults {
+    ChainFaults(CHAIN_FAULTS.load(Ordering::Relaxed))
+}
+
+/// Replace the confirmed faults, logging the ones set and cleared
+pub(crate) fn set_chain_faults(faults: ChainFaults) {
+    let old = chain_faults();
+    for fault in faults.iter().filter(|fault| !old.contains(*fault)) {
+        error!("Chain fault: {:?}", fault);
+    }
+    for fault in old.iter().filter(|fault| !faults.contains(*fault)) {
+        info!("Chain fault cleared: {:?}", fault);
+    }
+    CHAIN_FAULTS.store(faults.0, Ordering::Relaxed);
+}
@@ -0,0 +1,187 @@
+//! Keys wired each to its own GPIO, such as thumb clusters or encoder push buttons.
+//!
+//! Unlike the direct-pin matrix of RMK, this one applies a [KeyRemap] to the events it sends,
+//! so it can share a keymap with other matrices in a [CompositeMatrix](crate::composite::CompositeMatrix).
+use embassy_time::{Instant, Timer};
+use embedded_hal::digital::InputPin;
+use rmk::{
+    debounce::{DebounceState, DebouncerTrait},
+    event::KeyEvent,
+    keyboard::KEY_EVENT_CHANNEL,
+    matrix::{KeyState, MatrixTrait},
+};
+
+use crate::bitmap::KeyBitmap;
+use crate::composite::MappedMatrix;
+use crate::remap::KeyRemap;
+
+pub struct DirectPinMatrix<
+    In: InputPin,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> {
+    /// Pin of each position, `None` for unused positions
+    pins: [[Option<In>; COL]; ROW],
+    /// Whether a pressed key pulls its pin low
+    low_active: bool,
+    /// Mapping of pin positions to keymap positions
+    remap: R,
+    /// Debouncer
+    debouncer: D,
+    /// Key state matrix
+    key_states: [[KeyState; COL]; ROW],
+    /// Pressed state of `key_states`, packed
+    pressed: KeyBitmap<ROW, COL>,
+    /// Start scanning
+    #[allow(dead_code)]
+    scan_start: Option<Instant>,
+}
+
+impl<
+    In: InputPin,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> DirectPinMatrix<In, D, ROW, COL, R> {
+    pub fn new(
+        pins: [[Option<In>; COL]; ROW],
+        low_active: bool,
+        debouncer: D,
+        remap: R,
+    ) -> Self {
+        Self {
+            pins,
+            low_active,
+            remap,
+            debouncer,
+            key_states: [[KeyState::new(); COL]; ROW],
+            pressed: KeyBitmap::new(),
+            scan_start: None,
+        }
+    }
+
+    fn read(&mut self, row: usize, col: usize) -> bool {
+        let low_active = self.low_active;
+        self.pins[row][col]
+            .as_mut()
+            .and_then(|pin| pin.is_high().ok())
+            .is_some_and(|high| high != low_active)
+    }
+
+    fn any_pressed(&mut self) -> bool {
+        (0..ROW).any(|row| (0..COL).any(|col| self.read(row, col)))
+    }
+
+    fn physical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.remap
+            .to_physical(row, col)
+            .filter(|(row, col)| *row < ROW && *col < COL)
+    }
+}
+
+impl<
+    In: InputPin,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> MatrixTrait for DirectPinMatrix<In, D, ROW, COL, R> {
+    const ROW: usize = ROW;
+    const COL: usize = COL;
+
+    /// Direct pins are few, so while idle they are polled every millisecond instead of waiting on each of them
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {
+        if let Some(start_time) = self.scan_start {
+            // Keep scanning for 1ms after the last wake-up, past it poll the pins again
+            if start_time.elapsed().as_millis() <= 1 {
+                return;
+            } else {
+                self.scan_start = None;
+            }
+        }
+        while !self.any_pressed() {
+            Timer::after_millis(1).await;
+        }
+        self.scan_start = Some(Instant::now());
+    }
+
+    async fn scan(&mut self) {
+        info!("Direct pin matrix scanning");
+        loop {
+            #[cfg(feature = "async_matrix")]
+            self.wait_for_key().await;
+
+            for row in 0..ROW {
+                for col in 0..COL {
+                    let pin_state = self.read(row, col);
+                    let debounce_state = self.debouncer.detect_change_with_debounce(
+                        row,
+                        col,
+                        pin_state,
+                        &self.key_states[row][col],
+                    );
+
+                    if let DebounceState::Debounced = debounce_state {
+                        self.key_states[row][col].toggle_pressed();
+                        let pressed = self.key_states[row][col].pressed;
+                        self.pressed.set(row, col, pressed);
+                        if let Some((row, col)) = self.remap.to_logical(row, col) {
+                            KEY_EVENT_CHANNEL
+                                .send(KeyEvent {
+                                    row: row as u8,
+                                    col: col as u8,
+                                    pressed,
+                                })
+                                .await;
+                        }
+                    }
+                }
+            }
+
+            // If there's key still pressed, always refresh the self.scan_start
+            #[cfg(feature = "async_matrix")]
+            if !self.pressed.is_empty() {
+                self.scan_start = Some(Instant::now());
+            }
+
+            Timer::after_micros(100).await;
+        }
+    }
+
+    /// Read key state at keymap position (row, col), positions off this matrix read as released
+    fn get_key_state(&mut self, row: usize, col: usize) -> KeyState {
+        match self.physical(row, col) {
+            Some((row, col)) => self.key_states[row][col],
+            None => KeyState::new(),
+        }
+    }
+
+    /// Update key state at keymap position (row, col), positions off this matrix are ignored
+    fn update_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
+        if let Some((row, col)) = self.physical(row, col) {
+            f(&mut self.key_states[row][col]);
+            self.pressed.set(row, col, self.key_states[row][col].pressed);
+        }
+    }
+}
+
+impl<
+    In: InputPin,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> MappedMatrix for DirectPinMatrix<In, D, ROW, COL, R> {
+    fn contains(&self, row: usize, col: usize) -> bool {
+        self.physical(row, col).is_some()
+    }
+
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.pins.get(row)?.get(col)?.as_ref()?;
+        self.remap.to_logical(row, col)
+    }
+}
@@ -0,0 +1,56 @@
+//! Flash shared between RMK's storage and the settings of this crate.
+//!
+//! RMK takes its flash by value, so it is handed a [SharedFlash] and the other users get clones of it.
+//! Each user must keep to its own sectors.
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+
+pub struct SharedFlash<'a, M: RawMutex, F: NorFlash> {
+    flash: &'a Mutex<M, F>,
+    capacity: usize,
+}
+
+impl<'a, M: RawMutex, F: NorFlash> SharedFlash<'a, M, F> {
+    pub async fn new(flash: &'a Mutex<M, F>) -> Self {
+        let capacity = flash.lock().await.capacity();
+        Self { flash, capacity }
+    }
+}
+
+impl<'a, M: RawMutex, F: NorFlash> Clone for SharedFlash<'a, M, F> {
+    fn clone(&self) -> Self {
+        Self {
+            flash: self.flash,
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<'a, M: RawMutex, F: NorFlash> ErrorType for SharedFlash<'a, M, F> {
+    type Error = F::Error;
+}
+
+impl<'a, M: RawMutex, F: NorFlash> ReadNorFlash for SharedFlash<'a, M, F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.lock().await.read(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<'a, M: RawMutex, F: NorFlash> NorFlash for SharedFlash<'a, M, F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.flash.lock().await.erase(from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.flash.lock().await.write(offset, bytes).await
+    }
+}
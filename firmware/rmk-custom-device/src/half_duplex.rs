@@ -0,0 +1,126 @@
+//! Receive side of a half-duplex serial line, where a half hears its own bytes on the wire.
+//!
+//! The wire is wired-AND: when both halves send at once, the byte on it is the AND of both.
+//! The half whose byte reads back as sent has won the wire, it goes on sending, while the other one
+//! backs off. The byte the loser heard is the first byte of the winner's message, so it is queued for
+//! the next read along with the rest, instead of being dropped with the loser's write.
+//! When neither byte survives, both halves queue what the wire held and the framing above resyncs.
+//!
+//! The receiver's FIFO only holds a few bytes and stalls once full, so a half waiting to send, for the wire to
+//! go quiet or after a collision, keeps moving it to the queue, see [RxQueue::drain_until].
+
+use core::future::Future;
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Instant, Timer};
+
+/// Bytes received while waiting to send or sending, kept for the next read
+pub const RX_QUEUE_SIZE: usize = 32;
+
+/// Receive FIFO of the line
+pub trait RxFifo {
+    /// Oldest byte received, without waiting
+    fn try_pull(&mut self) -> Option<u8>;
+    /// Wait for a byte, cancel safe
+    fn wait_pull(&mut self) -> impl Future<Output = u8>;
+}
+
+/// Bytes of the other half received outside of a read
+pub struct RxQueue {
+    bytes: [u8; RX_QUEUE_SIZE],
+    len: usize,
+}
+
+impl RxQueue {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; RX_QUEUE_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Queue a byte of the other half, returns false when the queue is full and the byte dropped
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == RX_QUEUE_SIZE {
+            return false;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Queue a byte received from the FIFO, warns when it is dropped
+    fn keep(&mut self, byte: u8) {
+        if !self.push(byte) {
+            warn!("Half duplex receive buffer full, byte dropped");
+        }
+    }
+
+    /// Move everything in `fifo` to the queue, returns whether anything was received
+    pub fn drain(&mut self, fifo: &mut impl RxFifo) -> bool {
+        let mut received = false;
+        while let Some(byte) = fifo.try_pull() {
+            self.keep(byte);
+            received = true;
+        }
+        received
+    }
+
+    /// Move everything `fifo` receives to the queue until `deadline`, so it never stalls while the half waits.
+    /// Returns the time of the last byte received, if any
+    pub async fn drain_until(
+        &mut self,
+        fifo: &mut impl RxFifo,
+        deadline: Instant,
+    ) -> Option<Instant> {
+        let mut last = None;
+        loop {
+            if self.drain(fifo) {
+                last = Some(Instant::now());
+            }
+            match select(fifo.wait_pull(), Timer::at(deadline)).await {
+                Either::First(byte) => {
+                    self.keep(byte);
+                    last = Some(Instant::now());
+                }
+                Either::Second(()) => return last,
+            }
+        }
+    }
+
+    /// Move the oldest bytes into `buf`, returns how many
+    pub fn take(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.len);
+        buf[..n].copy_from_slice(&self.bytes[..n]);
+        self.bytes.copy_within(n..self.len, 0);
+        self.len -= n;
+        n
+    }
+
+    /// Check the byte `heard` on the wire while sending `sent`, returns whether it read back as sent.
+    /// A byte that differs is the other half's, it is queued. `None` is a silent wire, held low or too slow.
+    pub fn check_echo(&mut self, sent: u8, heard: Option<u8>) -> bool {
+        match heard {
+            Some(byte) if byte == sent => true,
+            Some(byte) => {
+                self.keep(byte);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for RxQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
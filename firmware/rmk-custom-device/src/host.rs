@@ -0,0 +1,106 @@
+//! Commands from the host, for the settings and status that have no place in RMK's Vial protocol.
+//!
+//! A request is a command byte followed by its arguments. A response starts with the command and a [HostStatus],
+//! followed by the result. Both fit in a report of [HOST_REPORT_SIZE] bytes, the size of a raw HID report,
+//! and the firmware picks the transport that carries them: `rmk-chain-keyboard` serves them in Vial's reports.
+use crate::debounce::{debounce_settings, set_debounce_settings, DebounceSettings};
+use crate::key_stats::{clear_key_stats, key_stats, KeyStats};
+use crate::link::{link_status, LinkStatus};
+
+pub const HOST_REPORT_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum HostCommand {
+    /// Returns the [DebounceSettings] as in `DebounceSettings::to_bytes`
+    GetDebounce = 0x01,
+    /// Takes the [DebounceSettings] as in `DebounceSettings::to_bytes`, returns them once applied
+    SetDebounce = 0x02,
+    /// Takes a link id, returns its [LinkStatus] as in `LinkStatus::to_bytes`
+    GetLinkStatus = 0x03,
+    /// Takes a slot of the key statistics as a little endian `u16`, see [key_stats](crate::key_stats),
+    /// returns its [KeyStats] as in `KeyStats::to_bytes`
+    GetKeyStats = 0x04,
+    /// Resets every [KeyStats]
+    ClearKeyStats = 0x05,
+}
+
+impl HostCommand {
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(Self::GetDebounce),
+            0x02 => Some(Self::SetDebounce),
+            0x03 => Some(Self::GetLinkStatus),
+            0x04 => Some(Self::GetKeyStats),
+            0x05 => Some(Self::ClearKeyStats),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum HostStatus {
+    Ok = 0,
+    UnknownCommand = 1,
+    InvalidArgument = 2,
+}
+
+/// Serve `request`, filling `response`
+pub fn handle_host_command(request: &[u8], response: &mut [u8; HOST_REPORT_SIZE]) {
+    response.fill(0);
+    let Some((&command, args)) = request.split_first() else {
+        response[1] = HostStatus::UnknownCommand as u8;
+        return;
+    };
+    response[0] = command;
+    let result = &mut response[2..];
+    let status = match HostCommand::from_u8(command) {
+        None => HostStatus::UnknownCommand,
+        Some(HostCommand::GetDebounce) => {
+            result[..3].copy_from_slice(&debounce_settings().to_bytes());
+            HostStatus::Ok
+        }
+        Some(HostCommand::SetDebounce) => {
+            match args
+                .first_chunk::<3>()
+                .and_then(|bytes| DebounceSettings::from_bytes(*bytes))
+            {
+                Some(settings) => {
+                    set_debounce_settings(settings);
+                    result[..3].copy_from_slice(&settings.to_bytes());
+                    HostStatus::Ok
+                }
+                None => HostStatus::InvalidArgument,
+            }
+        }
+        Some(HostCommand::GetLinkStatus) => {
+            match args.first().and_then(|id| link_status(*id as usize)) {
+                Some(status) => {
+                    result[..LinkStatus::REPORT_SIZE].copy_from_slice(&status.to_bytes());
+                    HostStatus::Ok
+                }
+                None => HostStatus::InvalidArgument,
+            }
+        }
+        Some(HostCommand::GetKeyStats) => {
+            match args
+                .first_chunk::<2>()
+                .and_then(|slot| key_stats(u16::from_le_bytes(*slot) as usize))
+            {
+                Some(stats) => {
+                    result[..KeyStats::REPORT_SIZE].copy_from_slice(&stats.to_bytes());
+                    HostStatus::Ok
+                }
+                None => HostStatus::InvalidArgument,
+            }
+        }
+        Some(HostCommand::ClearKeyStats) => {
+            clear_key_stats();
+            HostStatus::Ok
+        }
+    };
+    response[1] = status as u8;
+}
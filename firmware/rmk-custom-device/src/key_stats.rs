@@ -0,0 +1,229 @@
+//! Per-key counters of switch health, to spot worn switches before they type double letters.
+//!
+//! [SequentialMatrix](crate::matrix::SequentialMatrix) counts, for each chain position:
+//! * actuations: debounced presses
+//! * rejections: debounces which never completed, because the input went back to the key state first
+//! * chatter: debounced presses released again within [CHATTER_MS]
+//!
+//! The counters of all matrices of the MCU are kept in one table, so that they are stored and queried together.
+//! Each matrix takes a block of `ROW` x `COL` slots of it when built, in the order the matrices are built,
+//! and counts its positions in chain order in its block. Positions past [MAX_STATS_KEYS] are not counted.
+//! [persist_key_stats] keeps them in a flash sector of their own, so they add up across power cycles.
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Instant, Timer};
+use embedded_storage_async::nor_flash::NorFlash;
+
+/// Slots of the table, positions past it are not counted
+pub const MAX_STATS_KEYS: usize = 256;
+/// A press released within this time counts as chatter
+pub const CHATTER_MS: u32 = 30;
+/// Interval between stores of the counters, if they changed
+const PERSIST_INTERVAL_SECS: u64 = 600;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyStats {
+    pub actuations: u32,
+    pub rejections: u16,
+    pub chatter: u16,
+}
+
+impl KeyStats {
+    pub const REPORT_SIZE: usize = 8;
+
+    /// Little endian `actuations`, `rejections` and `chatter`, as sent to the host and stored in flash
+    pub fn to_bytes(self) -> [u8; Self::REPORT_SIZE] {
+        let mut bytes = [0; Self::REPORT_SIZE];
+        bytes[..4].copy_from_slice(&self.actuations.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.rejections.to_le_bytes());
+        bytes[6..].copy_from_slice(&self.chatter.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::REPORT_SIZE]) -> Self {
+        Self {
+            actuations: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            rejections: u16::from_le_bytes([bytes[4], bytes[5]]),
+            chatter: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+}
+
+struct StatsTable {
+    stats: [KeyStats; MAX_STATS_KEYS],
+    /// Time in ms of the last debounced press of each position
+    pressed_at: [u32; MAX_STATS_KEYS],
+    /// Slots taken by the matrices built so far
+    reserved: usize,
+    /// Whether the counters changed since they were stored
+    dirty: bool,
+}
+
+impl StatsTable {
+    const fn new() -> Self {
+        Self {
+            stats: [KeyStats {
+                actuations: 0,
+                rejections: 0,
+                chatter: 0,
+            }; MAX_STATS_KEYS],
+            pressed_at: [0; MAX_STATS_KEYS],
+            reserved: 0,
+            dirty: false,
+        }
+    }
+
+    fn update(&mut self, slot: usize, f: impl FnOnce(&mut KeyStats, &mut u32)) {
+        if slot < MAX_STATS_KEYS {
+            f(&mut self.stats[slot], &mut self.pressed_at[slot]);
+            self.dirty = true;
+        }
+    }
+}
+
+static TABLE: Mutex<CriticalSectionRawMutex, RefCell<StatsTable>> =
+    Mutex::new(RefCell::new(StatsTable::new()));
+
+/// Counters of `slot`, `None` if no matrix counts in it
+pub fn key_stats(slot: usize) -> Option<KeyStats> {
+    TABLE.lock(|table| {
+        let table = table.borrow();
+        (slot < table.reserved.min(MAX_STATS_KEYS)).then(|| table.stats[slot])
+    })
+}
+
+/// Reset every counter, for example after replacing a switch
+pub fn clear_key_stats() {
+    TABLE.lock(|table| {
+        let mut table = table.borrow_mut();
+        table.stats = [KeyStats::default(); MAX_STATS_KEYS];
+        table.dirty = true;
+    });
+}
+
+/// Take a block of `keys` slots for a matrix, returns its first slot
+pub(crate) fn reserve_key_stats(keys: usize) -> usize {
+    TABLE.lock(|table| {
+        let mut table = table.borrow_mut();
+        let base = table.reserved;
+        table.reserved += keys;
+        base
+    })
+}
+
+pub(crate) fn record_press(slot: usize) {
+    let now = Instant::now().as_millis() as u32;
+    TABLE.lock(|table| {
+        table.borrow_mut().update(slot, |stats, pressed_at| {
+            stats.actuations = stats.actuations.saturating_add(1);
+            *pressed_at = now;
+        })
+    });
+}
+
+pub(crate) fn record_release(slot: usize) {
+    let now = Instant::now().as_millis() as u32;
+    TABLE.lock(|table| {
+        table.borrow_mut().update(slot, |stats, pressed_at| {
+            if now.wrapping_sub(*pressed_at) < CHATTER_MS {
+                stats.chatter = stats.chatter.saturating_add(1);
+            }
+        })
+    });
+}
+
+pub(crate) fn record_rejection(slot: usize) {
+    TABLE.lock(|table| {
+        table.borrow_mut().update(slot, |stats, _| {
+            stats.rejections = stats.rejections.saturating_add(1);
+        })
+    });
+}
+
+/// Marks a sector holding key statistics, written last so an interrupted store is not loaded
+const MAGIC: [u8; 4] = *b"KSTA";
+/// Magic and number of positions, followed by the counters of each position
+const HEADER_SIZE: usize = 8;
+/// Positions written at once
+const CHUNK_KEYS: usize = 32;
+
+const _: () = assert!(MAX_STATS_KEYS % CHUNK_KEYS == 0);
+
+/// Load the counters stored in the sector at `offset`, returns whether a valid record was there
+pub async fn load_key_stats<F: NorFlash>(flash: &mut F, offset: u32) -> bool {
+    let mut header = [0; HEADER_SIZE];
+    if flash.read(offset, &mut header).await.is_err() || header[..4] != MAGIC {
+        return false;
+    }
+    let count = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if count != MAX_STATS_KEYS {
+        return false;
+    }
+    let mut bytes = [0; KeyStats::REPORT_SIZE];
+    for index in 0..count {
+        let address = offset + (HEADER_SIZE + index * KeyStats::REPORT_SIZE) as u32;
+        if flash.read(address, &mut bytes).await.is_err() {
+            return false;
+        }
+        TABLE.lock(|table| table.borrow_mut().stats[index] = KeyStats::from_bytes(bytes));
+    }
+    true
+}
+
+/// Store the counters in the sector at `offset`, which nothing else may use.
+/// They stay marked as changed until the store succeeds, so a failed one is retried.
+pub async fn store_key_stats<F: NorFlash>(flash: &mut F, offset: u32) -> Result<(), F::Error> {
+    // Changes made while storing mark the counters again
+    TABLE.lock(|table| table.borrow_mut().dirty = false);
+    let stored = write_key_stats(flash, offset).await;
+    if stored.is_err() {
+        TABLE.lock(|table| table.borrow_mut().dirty = true);
+    }
+    stored
+}
+
+async fn write_key_stats<F: NorFlash>(flash: &mut F, offset: u32) -> Result<(), F::Error> {
+    const CHUNK_SIZE: usize = CHUNK_KEYS * KeyStats::REPORT_SIZE;
+    assert!(
+        HEADER_SIZE % F::WRITE_SIZE == 0 && CHUNK_SIZE % F::WRITE_SIZE == 0,
+        "Key statistics record is not a multiple of the flash write size"
+    );
+    assert!(
+        HEADER_SIZE + MAX_STATS_KEYS * KeyStats::REPORT_SIZE <= F::ERASE_SIZE,
+        "Key statistics do not fit a flash sector"
+    );
+    flash.erase(offset, offset + F::ERASE_SIZE as u32).await?;
+
+    let mut chunk = [0; CHUNK_SIZE];
+    for first in (0..MAX_STATS_KEYS).step_by(CHUNK_KEYS) {
+        TABLE.lock(|table| {
+            let table = table.borrow();
+            for (i, stats) in table.stats[first..first + CHUNK_KEYS].iter().enumerate() {
+                chunk[i * KeyStats::REPORT_SIZE..(i + 1) * KeyStats::REPORT_SIZE]
+                    .copy_from_slice(&stats.to_bytes());
+            }
+        });
+        let address = offset + (HEADER_SIZE + first * KeyStats::REPORT_SIZE) as u32;
+        flash.write(address, &chunk).await?;
+    }
+
+    let mut header = [0; HEADER_SIZE];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..].copy_from_slice(&(MAX_STATS_KEYS as u32).to_le_bytes());
+    flash.write(offset, &header).await
+}
+
+/// Store the counters in the sector at `offset` every ten minutes, if they changed. This function never returns.
+pub async fn persist_key_stats<F: NorFlash>(mut flash: F, offset: u32) -> ! {
+    loop {
+        Timer::after_secs(PERSIST_INTERVAL_SECS).await;
+        if TABLE.lock(|table| table.borrow().dirty) {
+            debug!("Storing key statistics");
+            if store_key_stats(&mut flash, offset).await.is_err() {
+                warn!("Failed to store key statistics");
+            }
+        }
+    }
+}
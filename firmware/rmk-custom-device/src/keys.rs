@@ -0,0 +1,225 @@
+//! Key states of a chain, debounced from the raw bitmaps its matrix captures.
+//!
+//! Shared by [SequentialMatrix](crate::matrix::SequentialMatrix) and the PIO backend in `rp_pio`,
+//! which differ only in how they capture the chain and run its diagnostics.
+use embassy_time::{Duration, Instant};
+use rmk::{
+    debounce::{DebounceState, DebouncerTrait},
+    event::KeyEvent,
+    keyboard::KEY_EVENT_CHANNEL,
+    matrix::KeyState,
+};
+
+use crate::bitmap::KeyBitmap;
+use crate::debounce::{debounce_settings, DebounceSettings};
+use crate::diagnostics::chain_faults;
+use crate::key_stats::{record_press, record_rejection, record_release, reserve_key_stats};
+use crate::remap::KeyRemap;
+
+/// Interval of the diagnostics pass while scanning
+pub(crate) const DIAGNOSTICS_INTERVAL_MS: u64 = 1000;
+
+pub(crate) struct ChainKeys<D: DebouncerTrait, const ROW: usize, const COL: usize, R: KeyRemap> {
+    /// Mapping of chain positions to keymap positions
+    remap: R,
+    /// Debouncer
+    debouncer: D,
+    /// Key state matrix
+    key_states: [[KeyState; COL]; ROW],
+    /// First slot of this chain in the key statistics, see [key_stats](crate::key_stats)
+    stats_base: usize,
+    /// Raw input of the last scan
+    pub(crate) snapshot: KeyBitmap<ROW, COL>,
+    /// Pressed state of `key_states`, packed
+    pub(crate) pressed: KeyBitmap<ROW, COL>,
+    /// Positions being debounced, a return to their key state before completing is a rejected bounce
+    pub(crate) settling: KeyBitmap<ROW, COL>,
+    /// Positions debounced until `revisit_until` even when unchanged: rejected bounces, which debouncers such as
+    /// RMK's count back down while stable, and keys released on a faulty chain, pressed again if still held
+    revisit: KeyBitmap<ROW, COL>,
+    revisit_until: Instant,
+    /// Positions read pressed right after waking up, kept until their press is sent
+    pub(crate) wake_keys: KeyBitmap<ROW, COL>,
+}
+
+impl<D: DebouncerTrait, const ROW: usize, const COL: usize, R: KeyRemap> ChainKeys<D, ROW, COL, R> {
+    /// Chains with fewer positions may legitimately have all of them pressed at once
+    const ALL_PRESSED_MIN_POSITIONS: usize = 11;
+
+    pub(crate) fn new(debouncer: D, remap: R) -> Self {
+        Self {
+            remap,
+            debouncer,
+            key_states: [[KeyState::new(); COL]; ROW],
+            stats_base: reserve_key_stats(ROW * COL),
+            snapshot: KeyBitmap::new(),
+            pressed: KeyBitmap::new(),
+            settling: KeyBitmap::new(),
+            revisit: KeyBitmap::new(),
+            revisit_until: Instant::MIN,
+            wake_keys: KeyBitmap::new(),
+        }
+    }
+
+    /// Take in a capture of the chain: debounce it and send the events while the chain is healthy,
+    /// else release every key, see [Self::release_all]
+    pub(crate) async fn process(&mut self, raw: KeyBitmap<ROW, COL>) {
+        if chain_faults().is_empty() {
+            self.debounce(raw).await;
+            if !self.wake_keys.is_empty() {
+                self.flush_wake_keys(raw).await;
+            }
+        } else {
+            // Keys held on a faulty chain would never be released
+            self.snapshot = raw;
+            self.release_all().await;
+        }
+    }
+
+    /// Debounce the positions of the captured chain which changed since the last scan, and send their events.
+    ///
+    /// Positions being debounced or to revisit are debounced too, until their debounce completes or gives up.
+    /// The outcome of each debounce is counted in [key_stats](crate::key_stats).
+    async fn debounce(&mut self, raw: KeyBitmap<ROW, COL>) {
+        let changed = raw.xor(&self.snapshot).or(&self.settling).or(&self.revisit);
+        self.snapshot = raw;
+
+        for (row, col) in changed.iter_ones() {
+            let debounce_state = self.debouncer.detect_change_with_debounce(
+                row,
+                col,
+                raw.get(row, col),
+                &self.key_states[row][col],
+            );
+
+            match debounce_state {
+                DebounceState::Debounced => {
+                    self.settling.set(row, col, false);
+                    self.revisit.set(row, col, false);
+                    self.key_states[row][col].toggle_pressed();
+                    let key_state = self.key_states[row][col];
+                    self.pressed.set(row, col, key_state.pressed);
+                    if key_state.pressed {
+                        record_press(self.stats_slot(row, col));
+                    } else {
+                        record_release(self.stats_slot(row, col));
+                    }
+                    self.send_event(row, col, key_state.pressed).await;
+                }
+                DebounceState::InProgress => {
+                    self.settling.set(row, col, true);
+                    self.revisit.set(row, col, false);
+                }
+                DebounceState::Ignored => {
+                    if self.settling.get(row, col) {
+                        self.settling.set(row, col, false);
+                        record_rejection(self.stats_slot(row, col));
+                        self.revisit_later(row, col);
+                    }
+                }
+            }
+        }
+
+        if Instant::now() >= self.revisit_until {
+            self.revisit.clear();
+        }
+    }
+
+    /// Debounce the position for the longest debounce time, whether it changes or not
+    fn revisit_later(&mut self, row: usize, col: usize) {
+        let settings = debounce_settings();
+        let window_ms = settings
+            .press_ms
+            .max(settings.release_ms)
+            .max(DebounceSettings::DEFAULT.press_ms);
+        self.revisit.set(row, col, true);
+        self.revisit_until = Instant::now() + Duration::from_millis(window_ms as u64);
+    }
+
+    /// Make sure the key which woke the MCU up is sent, even if it was released before its debounce completed
+    async fn flush_wake_keys(&mut self, raw: KeyBitmap<ROW, COL>) {
+        let wake_keys = self.wake_keys;
+        for (row, col) in wake_keys.iter_ones() {
+            if self.pressed.get(row, col) {
+                // Debounced as usual
+                self.wake_keys.set(row, col, false);
+            } else if !raw.get(row, col) {
+                self.send_event(row, col, true).await;
+                self.send_event(row, col, false).await;
+                self.wake_keys.set(row, col, false);
+            }
+        }
+    }
+
+    /// Release every held key and drop the debounces in progress, used when the chain stops being trustworthy.
+    /// The keys released are debounced again on the first scan processed, so those still held are pressed again.
+    async fn release_all(&mut self) {
+        self.settling.clear();
+        let pressed = self.pressed;
+        for (row, col) in pressed.iter_ones() {
+            self.set_key_state(row, col, |state| state.pressed = false);
+            self.revisit.set(row, col, true);
+            self.send_event(row, col, false).await;
+        }
+    }
+
+    /// Whether the last scan read every one of the `positions` fitted pressed, on a chain long enough for it to be a fault
+    pub(crate) fn all_pressed(&self, positions: usize) -> bool {
+        positions >= Self::ALL_PRESSED_MIN_POSITIONS && self.snapshot.count_ones() == positions
+    }
+
+    /// Send the event of the chain position (row, col) at its keymap position, unmapped positions are dropped
+    async fn send_event(&self, row: usize, col: usize, pressed: bool) {
+        if let Some((row, col)) = self.to_logical(row, col) {
+            KEY_EVENT_CHANNEL
+                .send(KeyEvent {
+                    row: row as u8,
+                    col: col as u8,
+                    pressed,
+                })
+                .await;
+        }
+    }
+
+    /// Keymap position of the chain position (row, col), if it is mapped
+    pub(crate) fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.remap.to_logical(row, col)
+    }
+
+    /// Chain position of the keymap position (row, col), if it is on this chain
+    fn physical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.remap
+            .to_physical(row, col)
+            .filter(|(row, col)| *row < ROW && *col < COL)
+    }
+
+    /// Whether the keymap position (row, col) is on this chain
+    pub(crate) fn contains(&self, row: usize, col: usize) -> bool {
+        self.physical(row, col).is_some()
+    }
+
+    /// Slot of the key statistics of the chain position (row, col)
+    fn stats_slot(&self, row: usize, col: usize) -> usize {
+        self.stats_base + row * COL + col
+    }
+
+    fn set_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
+        f(&mut self.key_states[row][col]);
+        self.pressed.set(row, col, self.key_states[row][col].pressed);
+    }
+
+    /// Key state at keymap position (row, col), positions off this chain read as released
+    pub(crate) fn get_key_state(&self, row: usize, col: usize) -> KeyState {
+        match self.physical(row, col) {
+            Some((row, col)) => self.key_states[row][col],
+            None => KeyState::new(),
+        }
+    }
+
+    /// Update key state at keymap position (row, col), positions off this chain are ignored
+    pub(crate) fn update_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
+        if let Some((row, col)) = self.physical(row, col) {
+            self.set_key_state(row, col, f);
+        }
+    }
+}
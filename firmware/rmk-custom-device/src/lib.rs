@@ -1,3 +1,35 @@
 #![no_std]
 
+#[macro_use]
+mod fmt;
+
+pub mod bitmap;
+pub mod composite;
+pub mod debounce;
+pub mod diagnostics;
+pub mod direct_pin;
+pub mod flash;
+pub mod half_duplex;
+pub mod host;
+pub mod key_stats;
+mod keys;
+pub mod link;
 pub mod matrix;
+pub mod packed_debounce;
+pub mod remap;
+pub mod role;
+#[cfg(feature = "rp2040")]
+pub mod rp_half_duplex;
+#[cfg(feature = "rp2040")]
+pub mod rp_pio;
+#[cfg(feature = "rp2040")]
+pub mod rp_sleep;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "sim")]
+pub mod sim_serial;
+pub mod sleep;
+pub mod split;
+pub mod timing;
+pub mod trace;
+pub mod usb;
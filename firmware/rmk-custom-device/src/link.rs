@@ -0,0 +1,489 @@
+//! Health of the serial links between split halves.
+//!
+//! [SplitLink] sits between RMK's split driver and the UART on both halves. It frames the bytes RMK sends,
+//! sends heartbeats while the link is idle and notices when the other half stops talking.
+//! The central keeps which keys of the peripheral are held, from the [SplitMessage]s the link carries, and
+//! releases them when it loses the peripheral, so no key stays stuck and no other key sees a stray release.
+//! Frames are found again after garbage or a reconnect by hunting for the sync byte, and a frame left unfinished
+//! for [LinkConfig::frame_timeout_ms] is dropped, so a corrupted length does not hold up the frames after it.
+//!
+//! Frame: `SYNC`, payload length, payload, CRC-8 of length and payload. A frame without payload is a heartbeat.
+//! The central's heartbeats carry its [DebounceSettings] instead, flagged by [SETTINGS_FLAG] in the length,
+//! so that settings changed on the central apply to the whole keyboard.
+//!
+//! Heartbeats and timeouts are handled while RMK waits in `read`, which both RMK's central monitor and
+//! peripheral do all the time. RMK interrupts that `read` whenever it has something to send, so the work is
+//! kept as state which the next `read` or `write` resumes: a frame being sent is finished before any other,
+//! and the keys of a lost half still to be released are released.
+//! This holds as long as the inner `read` and `write` are cancel safe, like those of embassy's `BufferedUart`.
+//!
+//! [SplitLink] needs the `split` feature, the counters of [link_status] are there without it.
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+#[cfg(feature = "split")]
+use embassy_futures::select::{select, Either};
+#[cfg(feature = "split")]
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(feature = "split")]
+use embedded_io_async::{ErrorType, Read, Write};
+#[cfg(feature = "split")]
+use rmk::{event::KeyEvent, keyboard::KEY_EVENT_CHANNEL, split::SplitMessage};
+
+#[cfg(feature = "split")]
+use crate::bitmap::KeyBitmap;
+#[cfg(feature = "split")]
+use crate::debounce::{debounce_settings, set_debounce_settings, DebounceSettings};
+#[cfg(feature = "split")]
+use crate::split::HalfPlacement;
+
+#[cfg(feature = "split")]
+const SYNC: u8 = 0xA5;
+/// Longest payload of a frame, longer writes are split
+#[cfg(feature = "split")]
+const MAX_PAYLOAD: usize = 64;
+/// Set in the length of a heartbeat carrying settings
+#[cfg(feature = "split")]
+const SETTINGS_FLAG: u8 = 0x80;
+/// Sync, length and CRC
+#[cfg(feature = "split")]
+const FRAME_OVERHEAD: usize = 3;
+/// Rows of a peripheral whose held keys the central keeps, keys of the rows below are never released
+#[cfg(feature = "split")]
+const MAX_HALF_ROWS: usize = 16;
+/// Number of links whose counters are kept, see [link_status]
+pub const MAX_LINKS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkConfig {
+    /// Longest time without sending, a heartbeat is sent after it
+    pub heartbeat_ms: u64,
+    /// Longest time without receiving before the link counts as lost
+    pub timeout_ms: u64,
+    /// Longest time a frame may stay unfinished, at least the time to send the longest frame
+    pub frame_timeout_ms: u64,
+}
+
+impl LinkConfig {
+    pub const DEFAULT: Self = Self {
+        heartbeat_ms: 100,
+        timeout_ms: 500,
+        frame_timeout_ms: 20,
+    };
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Counters of a link since boot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkStatus {
+    /// Whether a frame was received within the timeout
+    pub connected: bool,
+    /// Valid frames received, heartbeats included
+    pub frames: u32,
+    pub heartbeats: u32,
+    /// Frames with a bad length or CRC, or left unfinished
+    pub framing_errors: u32,
+    /// Bytes skipped while hunting for the start of a frame
+    pub dropped_bytes: u32,
+    /// Times the link was lost
+    pub losses: u32,
+}
+
+/// Counters shared with [link_status], only the link task writes them
+struct LinkCounters {
+    connected: AtomicBool,
+    frames: AtomicU32,
+    heartbeats: AtomicU32,
+    framing_errors: AtomicU32,
+    dropped_bytes: AtomicU32,
+    losses: AtomicU32,
+}
+
+impl LinkCounters {
+    const fn new() -> Self {
+        Self {
+            connected: AtomicBool::new(false),
+            frames: AtomicU32::new(0),
+            heartbeats: AtomicU32::new(0),
+            framing_errors: AtomicU32::new(0),
+            dropped_bytes: AtomicU32::new(0),
+            losses: AtomicU32::new(0),
+        }
+    }
+}
+
+impl LinkStatus {
+    pub const REPORT_SIZE: usize = 21;
+
+    /// Report for the host: `connected`, then each counter as a little endian `u32` in declaration order
+    pub fn to_bytes(self) -> [u8; Self::REPORT_SIZE] {
+        let mut report = [0; Self::REPORT_SIZE];
+        report[0] = self.connected as u8;
+        let counters = [
+            self.frames,
+            self.heartbeats,
+            self.framing_errors,
+            self.dropped_bytes,
+            self.losses,
+        ];
+        for (i, counter) in counters.iter().enumerate() {
+            report[1 + 4 * i..5 + 4 * i].copy_from_slice(&counter.to_le_bytes());
+        }
+        report
+    }
+}
+
+static LINKS: [LinkCounters; MAX_LINKS] = [const { LinkCounters::new() }; MAX_LINKS];
+
+/// Counters of link `id`, `None` if `id` is not below [MAX_LINKS]
+pub fn link_status(id: usize) -> Option<LinkStatus> {
+    let link = LINKS.get(id)?;
+    Some(LinkStatus {
+        connected: link.connected.load(Ordering::Relaxed),
+        frames: link.frames.load(Ordering::Relaxed),
+        heartbeats: link.heartbeats.load(Ordering::Relaxed),
+        framing_errors: link.framing_errors.load(Ordering::Relaxed),
+        dropped_bytes: link.dropped_bytes.load(Ordering::Relaxed),
+        losses: link.losses.load(Ordering::Relaxed),
+    })
+}
+
+/// Add to a counter, without read-modify-write atomics which thumbv6m lacks
+#[cfg(feature = "split")]
+fn add(counter: &AtomicU32, n: u32) {
+    counter.store(
+        counter.load(Ordering::Relaxed).wrapping_add(n),
+        Ordering::Relaxed,
+    );
+}
+
+/// CRC-8 with polynomial 0x07
+#[cfg(feature = "split")]
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(feature = "split")]
+pub struct SplitLink<S: Read + Write> {
+    serial: S,
+    /// Index of the counters in [link_status], the peripheral id on the central
+    id: usize,
+    /// Placement of the peripheral whose keys are released when the link is lost, set on the central
+    half: Option<HalfPlacement>,
+    /// Keys of the peripheral held, at their position in the peripheral's chain
+    held: KeyBitmap<MAX_HALF_ROWS, 64>,
+    config: LinkConfig,
+    /// Bytes received and not parsed yet
+    rx: [u8; MAX_PAYLOAD + FRAME_OVERHEAD],
+    rx_len: usize,
+    /// Time bytes were last received
+    last_byte: Instant,
+    /// Payload of the last frame, from `payload_pos` not read yet
+    payload: [u8; MAX_PAYLOAD],
+    payload_pos: usize,
+    payload_len: usize,
+    /// Frame being sent, from `tx_pos` not written yet
+    tx: [u8; MAX_PAYLOAD + FRAME_OVERHEAD],
+    tx_pos: usize,
+    tx_len: usize,
+    /// Whether the held keys are still to be released after the link was lost
+    releasing: bool,
+    last_rx: Instant,
+    last_tx: Instant,
+    connected: bool,
+}
+
+#[cfg(feature = "split")]
+impl<S: Read + Write> SplitLink<S> {
+    /// Link over `serial`, `half` is the placement of the peripheral on the central and `None` on the peripheral
+    pub fn new(serial: S, id: usize, half: Option<HalfPlacement>) -> Self {
+        Self {
+            serial,
+            id,
+            half,
+            held: KeyBitmap::new(),
+            config: LinkConfig::DEFAULT,
+            rx: [0; MAX_PAYLOAD + FRAME_OVERHEAD],
+            rx_len: 0,
+            last_byte: Instant::now(),
+            payload: [0; MAX_PAYLOAD],
+            payload_pos: 0,
+            payload_len: 0,
+            tx: [0; MAX_PAYLOAD + FRAME_OVERHEAD],
+            tx_pos: 0,
+            tx_len: 0,
+            releasing: false,
+            last_rx: Instant::now(),
+            last_tx: Instant::now(),
+            connected: false,
+        }
+    }
+
+    /// Both halves should use the same heartbeat, with a timeout of several heartbeats
+    pub fn with_config(mut self, config: LinkConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    fn counters(&self) -> Option<&'static LinkCounters> {
+        LINKS.get(self.id)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.rx.copy_within(n..self.rx_len, 0);
+        self.rx_len -= n;
+    }
+
+    /// Parse what starts `rx`, returns whether any byte was consumed
+    fn parse_frame(&mut self) -> bool {
+        let counters = self.counters();
+
+        // Hunt for the start of a frame
+        let skip = self.rx[..self.rx_len]
+            .iter()
+            .position(|byte| *byte == SYNC)
+            .unwrap_or(self.rx_len);
+        if skip > 0 {
+            if let Some(counters) = counters {
+                add(&counters.dropped_bytes, skip as u32);
+            }
+            self.consume(skip);
+            return true;
+        }
+        if self.rx_len < 2 {
+            return false;
+        }
+
+        let settings = self.rx[1] & SETTINGS_FLAG != 0;
+        let len = (self.rx[1] & !SETTINGS_FLAG) as usize;
+        if len <= MAX_PAYLOAD && self.rx_len < len + FRAME_OVERHEAD {
+            return false;
+        }
+        if len > MAX_PAYLOAD || crc8(&self.rx[1..len + 2]) != self.rx[len + 2] {
+            // Not a frame, look for the next sync byte
+            debug!("Split link {} framing error", self.id);
+            if let Some(counters) = counters {
+                add(&counters.framing_errors, 1);
+            }
+            self.consume(1);
+            return true;
+        }
+
+        self.last_rx = Instant::now();
+        if let Some(counters) = counters {
+            add(&counters.frames, 1);
+            if len == 0 || settings {
+                add(&counters.heartbeats, 1);
+            }
+        }
+        if !self.connected {
+            self.connected = true;
+            if let Some(counters) = counters {
+                counters.connected.store(true, Ordering::Relaxed);
+            }
+            info!("Split link {} up", self.id);
+        }
+        if settings {
+            // Only the central sends settings, a peripheral applies them
+            let received = self.rx[2..len + 2]
+                .try_into()
+                .ok()
+                .and_then(DebounceSettings::from_bytes);
+            if let (None, Some(settings)) = (self.half, received) {
+                set_debounce_settings(settings);
+            }
+        } else {
+            self.payload[..len].copy_from_slice(&self.rx[2..len + 2]);
+            self.payload_pos = 0;
+            self.payload_len = len;
+            self.track_key();
+        }
+        self.consume(len + FRAME_OVERHEAD);
+        true
+    }
+
+    /// Keep the key of the payload when it is a key of the peripheral, on the central
+    fn track_key(&mut self) {
+        let Some(half) = self.half else {
+            return;
+        };
+        // RMK sends a message per write, which is a frame as long as it fits in one
+        let Ok(SplitMessage::Key(event)) = postcard::from_bytes(&self.payload[..self.payload_len])
+        else {
+            return;
+        };
+        let (row, col) = (event.row as usize, event.col as usize);
+        if row < half.rows.min(MAX_HALF_ROWS) && col < half.cols.min(64) {
+            self.held.set(row, col, event.pressed);
+        }
+    }
+
+    /// Start sending a frame, the last one must have been written
+    fn queue_frame(&mut self, payload: &[u8], flags: u8) {
+        let len = payload.len();
+        self.tx[0] = SYNC;
+        self.tx[1] = len as u8 | flags;
+        self.tx[2..len + 2].copy_from_slice(payload);
+        self.tx[len + 2] = crc8(&self.tx[1..len + 2]);
+        self.tx_pos = 0;
+        self.tx_len = len + FRAME_OVERHEAD;
+        self.last_tx = Instant::now();
+    }
+
+    /// Write the rest of the frame being sent. Cancel safe, the frame is finished by the next call.
+    async fn flush_frame(&mut self) -> Result<(), S::Error> {
+        while self.tx_pos < self.tx_len {
+            self.tx_pos += self
+                .serial
+                .write(&self.tx[self.tx_pos..self.tx_len])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Time after which the unfinished frame in `rx` is dropped
+    fn frame_deadline(&self) -> Instant {
+        self.last_byte + Duration::from_millis(self.config.frame_timeout_ms)
+    }
+
+    /// Time of the next heartbeat or timeout
+    fn deadline(&self) -> Instant {
+        let mut deadline = self.last_tx + Duration::from_millis(self.config.heartbeat_ms);
+        if self.connected {
+            deadline = deadline.min(self.last_rx + Duration::from_millis(self.config.timeout_ms));
+        }
+        if self.rx_len > 0 {
+            deadline = deadline.min(self.frame_deadline());
+        }
+        deadline
+    }
+
+    /// Drop an unfinished frame, notice a lost link and queue a heartbeat when due, the work is done by [Self::resume]
+    fn on_deadline(&mut self) {
+        let now = Instant::now();
+        if self.rx_len > 0 && now >= self.frame_deadline() {
+            // Most likely a corrupted length, hunt for the next sync byte
+            debug!("Split link {} unfinished frame", self.id);
+            if let Some(counters) = self.counters() {
+                add(&counters.framing_errors, 1);
+            }
+            self.consume(1);
+        }
+        if self.connected && now >= self.last_rx + Duration::from_millis(self.config.timeout_ms) {
+            self.connected = false;
+            // Drop a partial frame, the next one starts after the reconnect
+            self.rx_len = 0;
+            if let Some(counters) = self.counters() {
+                counters.connected.store(false, Ordering::Relaxed);
+                add(&counters.losses, 1);
+            }
+            warn!("Split link {} lost: {:?}", self.id, link_status(self.id));
+            self.releasing = self.half.is_some();
+        }
+        if now >= self.last_tx + Duration::from_millis(self.config.heartbeat_ms) {
+            if self.half.is_some() {
+                self.queue_frame(&debounce_settings().to_bytes(), SETTINGS_FLAG);
+            } else {
+                self.queue_frame(&[], 0);
+            }
+        }
+    }
+
+    /// Finish the frame being sent and the release of a lost half. Cancel safe.
+    async fn resume(&mut self) -> Result<(), S::Error> {
+        self.flush_frame().await?;
+        self.release_half().await;
+        Ok(())
+    }
+
+    /// Release the keys of the lost half that are held.
+    /// Cancel safe, a key is no longer held once its release is sent.
+    async fn release_half(&mut self) {
+        let Some(half) = self.half.filter(|_| self.releasing) else {
+            return;
+        };
+        loop {
+            let Some((row, col)) = self.held.iter_ones().next() else {
+                break;
+            };
+            KEY_EVENT_CHANNEL
+                .send(KeyEvent {
+                    row: (half.row_offset + row) as u8,
+                    col: (half.col_offset + col) as u8,
+                    pressed: false,
+                })
+                .await;
+            self.held.set(row, col, false);
+        }
+        self.releasing = false;
+    }
+}
+
+#[cfg(feature = "split")]
+impl<S: Read + Write> ErrorType for SplitLink<S> {
+    type Error = S::Error;
+}
+
+#[cfg(feature = "split")]
+impl<S: Read + Write> Read for SplitLink<S> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            self.resume().await?;
+            if self.payload_pos < self.payload_len {
+                let n = buf.len().min(self.payload_len - self.payload_pos);
+                buf[..n].copy_from_slice(&self.payload[self.payload_pos..self.payload_pos + n]);
+                self.payload_pos += n;
+                return Ok(n);
+            }
+            if self.parse_frame() {
+                continue;
+            }
+
+            let deadline = self.deadline();
+            match select(
+                self.serial.read(&mut self.rx[self.rx_len..]),
+                Timer::at(deadline),
+            )
+            .await
+            {
+                Either::First(n) => {
+                    self.rx_len += n?;
+                    self.last_byte = Instant::now();
+                }
+                Either::Second(()) => self.on_deadline(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "split")]
+impl<S: Read + Write> Write for SplitLink<S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.resume().await?;
+        let n = buf.len().min(MAX_PAYLOAD);
+        self.queue_frame(&buf[..n], 0);
+        self.flush_frame().await?;
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.serial.flush().await
+    }
+}
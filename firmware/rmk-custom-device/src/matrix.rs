@@ -1,14 +1,24 @@
 use rmk::{
-  debounce::{DebounceState, DebouncerTrait},
-  keyboard::KEY_EVENT_CHANNEL,
-  event::KeyEvent,
+  debounce::DebouncerTrait,
   matrix::{MatrixTrait, KeyState},
 };
 use embassy_time::{Instant, Timer};
 use embedded_hal::digital::{InputPin, OutputPin};
 #[cfg(feature = "async_matrix")]
+use embassy_futures::select::{select, Either};
+#[cfg(feature = "async_matrix")]
 use embedded_hal_async::digital::Wait;
 
+use crate::bitmap::KeyBitmap;
+use crate::composite::MappedMatrix;
+use crate::keys::{ChainKeys, DIAGNOSTICS_INTERVAL_MS};
+use crate::remap::{Identity, KeyRemap};
+#[cfg(feature = "async_matrix")]
+use crate::sleep::{DeepSleep, IdleConfig};
+use crate::timing::{ChainTiming, DelayMode};
+use crate::diagnostics::{chain_faults, set_chain_faults, ChainFault, ChainFaults, FaultFilter};
+use crate::trace::{PinTracer, TraceSignal, TraceSink, TracedInputPin, TracedOutputPin};
+
 
 pub struct SequentialMatrixPins<
     #[cfg(feature = "async_matrix")] In: Wait + InputPin,
@@ -20,6 +30,8 @@ pub struct SequentialMatrixPins<
     any_not: Out,
     reset_not: Out,
     input: In,
+    /// Whether `input` reads low for a pressed switch
+    input_active_low: bool,
 }
 
 impl <
@@ -40,11 +52,59 @@ impl <
             any_not,
             reset_not,
             input,
+            input_active_low: false,
+        }
+    }
+
+    /// Read `input` as pressed when low, for boards with an inverting buffer on the chain output
+    pub fn with_input_active_low(mut self, active_low: bool) -> Self {
+        self.input_active_low = active_low;
+        self
+    }
+
+    /// Record every drive and read of the pins through `tracer`, see [trace](crate::trace)
+    pub fn traced<S: TraceSink>(
+        self,
+        tracer: &PinTracer<S>,
+    ) -> SequentialMatrixPins<TracedInputPin<'_, In, S>, TracedOutputPin<'_, Out, S>> {
+        SequentialMatrixPins {
+            row_clock: TracedOutputPin::new(self.row_clock, TraceSignal::RowClock, tracer),
+            col_clock: TracedOutputPin::new(self.col_clock, TraceSignal::ColClock, tracer),
+            any_not: TracedOutputPin::new(self.any_not, TraceSignal::AnyNot, tracer),
+            reset_not: TracedOutputPin::new(self.reset_not, TraceSignal::ResetNot, tracer),
+            input: TracedInputPin::new(self.input, tracer),
+            input_active_low: self.input_active_low,
+        }
+    }
+
+    fn input_active(&mut self) -> bool {
+        self.input.is_high().ok().unwrap_or_default() != self.input_active_low
+    }
+
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_input_active(&mut self) {
+        if self.input_active_low {
+            let _ = self.input.wait_for_low().await;
+        } else {
+            let _ = self.input.wait_for_high().await;
         }
     }
 }
 
 
+/// Rows and columns actually fitted on the chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChainTopology {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+
+/// Matrix of switches on the daisy-chained flip-flops.
+///
+/// Each scan captures the chain into a bitmap first, then debounces and sends events, so the clock timing does not depend on the event channel.
+/// A row holds up to 64 columns.
 pub struct SequentialMatrix<
     #[cfg(feature = "async_matrix")] In: Wait + InputPin,
     #[cfg(not(feature = "async_matrix"))] In: InputPin,
@@ -52,14 +112,31 @@ pub struct SequentialMatrix<
     D: DebouncerTrait,
     const ROW: usize,
     const COL: usize,
+    R: KeyRemap = Identity,
 > {
     pins: SequentialMatrixPins<In, Out>,
-    /// Debouncer
-    debouncer: D,
-    /// Key state matrix
-    key_states: [[KeyState; COL]; ROW],
+    /// Key states, debounced from the captures
+    keys: ChainKeys<D, ROW, COL, R>,
+    /// Positions fitted on the chain, within ROW x COL
+    topology: ChainTopology,
+    /// Whether the terminator was found at the end of `topology`, never without the `chain_terminator` feature
+    terminated: bool,
+    /// Last time the diagnostics pass was run
+    last_diagnostics: Instant,
+    /// Passes in a row each fault was seen in
+    fault_filter: FaultFilter,
+    /// Timing of the chain signals
+    timing: ChainTiming,
+    /// Whether to run [Self::calibrate_timing] when scanning starts
+    calibrate_on_boot: bool,
+    /// Scan window and deep sleep timeout
+    #[cfg(feature = "async_matrix")]
+    idle: IdleConfig,
+    /// Deep sleep entered after `idle.sleep_timeout_ms`
+    #[cfg(feature = "async_matrix")]
+    sleep: Option<&'static mut dyn DeepSleep>,
     /// Start scanning
-    #[allow(dead_code)]
+    #[cfg(feature = "async_matrix")]
     scan_start: Option<Instant>,
 }
 
@@ -70,20 +147,324 @@ impl<
     D: DebouncerTrait,
     const ROW: usize,
     const COL: usize,
-> SequentialMatrix<In, Out, D, ROW, COL> {
-    const PROPAGATION_DELAY: u64 = 50;
+    R: KeyRemap,
+> SequentialMatrix<In, Out, D, ROW, COL, R> {
+    /// Number of attempts to find the chain idle before giving up probing
+    #[cfg(feature = "chain_terminator")]
+    const PROBE_ATTEMPTS: usize = 100;
+    /// Number of error-free reads required from each candidate timing while calibrating
+    const CALIBRATION_PASSES: usize = 16;
 
+    /// Matrix mapping chain positions to keymap positions through `remap`, use [Identity] to keep them as they are
     pub fn new(
         pins: SequentialMatrixPins<In, Out>,
         debouncer: D,
+        remap: R,
+    ) -> Self {
+        Self::new_with_timing(pins, debouncer, remap, ChainTiming::DEFAULT)
+    }
+
+    pub fn new_with_timing(
+        pins: SequentialMatrixPins<In, Out>,
+        debouncer: D,
+        remap: R,
+        timing: ChainTiming,
     ) -> Self {
         Self {
             pins,
-            debouncer,
-            key_states: [[KeyState::new(); COL]; ROW],
+            keys: ChainKeys::new(debouncer, remap),
+            topology: ChainTopology { rows: ROW, cols: COL },
+            terminated: false,
+            last_diagnostics: Instant::MIN,
+            fault_filter: FaultFilter::new(),
+            timing,
+            calibrate_on_boot: false,
+            #[cfg(feature = "async_matrix")]
+            idle: IdleConfig::DEFAULT,
+            #[cfg(feature = "async_matrix")]
+            sleep: None,
+            #[cfg(feature = "async_matrix")]
             scan_start: None,
         }
     }
+
+    /// Topology being scanned, ROW x COL until [Self::detect_topology] is run
+    pub fn topology(&self) -> ChainTopology {
+        self.topology
+    }
+
+    pub fn timing(&self) -> ChainTiming {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: ChainTiming) {
+        self.timing = timing;
+    }
+
+    /// Put the MCU into deep sleep through `sleep` after `idle.sleep_timeout_ms` without key presses
+    #[cfg(feature = "async_matrix")]
+    pub fn with_deep_sleep(mut self, sleep: &'static mut dyn DeepSleep, idle: IdleConfig) -> Self {
+        self.sleep = Some(sleep);
+        self.idle = idle;
+        self
+    }
+
+    #[cfg(feature = "async_matrix")]
+    pub fn set_idle_config(&mut self, idle: IdleConfig) {
+        self.idle = idle;
+    }
+
+    /// Calibrate the timing against the attached chain when scanning starts, see [Self::calibrate_timing].
+    /// Without a terminator, a key must be held while the keyboard boots.
+    pub fn with_boot_calibration(mut self) -> Self {
+        self.calibrate_on_boot = true;
+        self
+    }
+
+    /// Select the first position of the chain
+    async fn reset_chain(&mut self) {
+        let timing = self.timing;
+        self.pins.row_clock.set_low().ok();
+        self.pins.col_clock.set_low().ok();
+        self.pins.any_not.set_high().ok();
+        self.pins.reset_not.set_low().ok();
+        timing.delay_ns(timing.reset_pulse_ns).await;
+        self.pins.reset_not.set_high().ok();
+        timing.delay_ns(timing.settle_ns).await;
+    }
+
+    /// Shift the selection to the next column
+    async fn clock_col(&mut self) {
+        let timing = self.timing;
+        self.pins.col_clock.set_high().ok();
+        timing.delay_ns(timing.clock_high_ns).await;
+        self.pins.col_clock.set_low().ok();
+        timing.delay_ns(timing.clock_low_ns.max(timing.settle_ns)).await;
+    }
+
+    /// Shift the selection to the first column of the next row
+    async fn clock_row(&mut self) {
+        let timing = self.timing;
+        self.pins.row_clock.set_high().ok();
+        timing.delay_ns(timing.clock_high_ns).await;
+        self.pins.row_clock.set_low().ok();
+        timing.delay_ns(timing.clock_low_ns.max(timing.settle_ns)).await;
+    }
+
+    fn read_input(&mut self) -> bool {
+        self.pins.input_active()
+    }
+
+    /// Clock the whole chain into a bitmap in one pass, without any other work in between
+    async fn capture(&mut self) -> KeyBitmap<ROW, COL> {
+        let mut bitmap = KeyBitmap::new();
+        let ChainTopology { rows, cols } = self.topology;
+        self.reset_chain().await;
+        for row in 0..rows {
+            let mut bits = 0;
+            for col in 0..cols {
+                if self.read_input() {
+                    bits |= 1 << col;
+                }
+                self.clock_col().await;
+            }
+            bitmap.set_row(row, bits);
+            self.clock_row().await;
+        }
+        bitmap
+    }
+
+    /// Whether any switch on the chain is pressed, sensed through `any_not`
+    async fn any_pressed(&mut self) -> bool {
+        let timing = self.timing;
+        self.pins.any_not.set_low().ok();
+        timing.delay_ns(timing.settle_ns).await;
+        let pressed = self.read_input();
+        self.pins.any_not.set_high().ok();
+        timing.delay_ns(timing.settle_ns).await;
+        pressed
+    }
+
+    /// Measure how many rows and columns are fitted, and scan only those positions from now on.
+    ///
+    /// The preset token is shifted along the chain from reset, one clock at a time.
+    /// The terminator after the last fitted module feeds the token back to `input`, so the number of clocks until `input` rises is the length.
+    /// Probing needs an idle chain, so it waits for every switch to be released first.
+    /// If the chain never becomes idle, or no terminator is found within ROW x COL, the compile-time size is kept.
+    ///
+    /// The terminator is a hardware change: the `Out+` of the last module, unconnected on the boards in `circuit/`,
+    /// must be wired-OR'ed into `input`, e.g. through a diode. An empty position otherwise reads like a released switch,
+    /// so without the `chain_terminator` feature nothing is probed and the compile-time size is kept.
+    pub async fn detect_topology(&mut self) -> ChainTopology {
+        #[cfg(not(feature = "chain_terminator"))]
+        {
+            info!("Chain has no terminator, scanning {} rows, {} columns", ROW, COL);
+            self.topology
+        }
+        #[cfg(feature = "chain_terminator")]
+        self.probe_topology().await
+    }
+
+    #[cfg(feature = "chain_terminator")]
+    async fn probe_topology(&mut self) -> ChainTopology {
+        let mut idle = false;
+        for _ in 0..Self::PROBE_ATTEMPTS {
+            if !self.any_pressed().await {
+                idle = true;
+                break;
+            }
+            Timer::after_millis(10).await;
+        }
+        if !idle {
+            warn!("Chain is not idle, keeping {} rows, {} columns", ROW, COL);
+            return self.topology;
+        }
+
+        self.reset_chain().await;
+        let mut cols = None;
+        for col in 0..=COL {
+            if self.read_input() {
+                cols = Some(col);
+                break;
+            }
+            self.clock_col().await;
+        }
+
+        self.reset_chain().await;
+        let mut rows = None;
+        for row in 0..=ROW {
+            if self.read_input() {
+                rows = Some(row);
+                break;
+            }
+            self.clock_row().await;
+        }
+
+        self.terminated = rows.is_some() && cols.is_some();
+        let (rows, cols) = (rows.unwrap_or(ROW), cols.unwrap_or(COL));
+        self.topology = ChainTopology { rows, cols };
+        info!("Detected chain topology: {} rows, {} columns (max {} x {})", rows, cols, ROW, COL);
+        self.topology
+    }
+
+    /// Check the chain wiring with known clock sequences, and return the faults confirmed, see [diagnostics](crate::diagnostics).
+    ///
+    /// * stuck `input`: with the selection shifted out of the chain, `input` must read low
+    /// * stuck `any_not`: the same must hold after pulsing `any_not`
+    /// * broken chain: the token must still arrive at the terminator found by [Self::detect_topology]
+    /// * stuck low `input`: while the last scan read keys pressed, `any_not` must pull `input` high
+    /// * all pressed: a chain with more positions than fingers cannot have every one of them pressed
+    ///
+    /// A pass while a debounce is in progress is not counted, the last scan may not match the switches then.
+    pub async fn run_diagnostics(&mut self) -> ChainFaults {
+        self.last_diagnostics = Instant::now();
+        if !self.keys.settling.is_empty() {
+            return chain_faults();
+        }
+
+        let mut faults = ChainFaults::empty();
+        let ChainTopology { rows, cols } = self.topology;
+
+        // Shift the token out of the chain, past the terminator
+        self.reset_chain().await;
+        for _ in 0..=rows {
+            self.clock_row().await;
+        }
+        if self.read_input() {
+            faults.insert(ChainFault::StuckHigh);
+        } else {
+            let pressed = self.any_pressed().await;
+            if self.read_input() {
+                faults.insert(ChainFault::AnyNotStuck);
+            } else if !pressed && !self.keys.snapshot.is_empty() {
+                faults.insert(ChainFault::StuckLow);
+            }
+        }
+
+        if self.keys.all_pressed(rows * cols) {
+            faults.insert(ChainFault::AllPressed);
+        }
+
+        if self.terminated && !faults.contains(ChainFault::StuckHigh) && !self.reads_terminator().await {
+            faults.insert(ChainFault::BrokenChain);
+        }
+
+        let confirmed = self.fault_filter.update(faults);
+        set_chain_faults(confirmed);
+        confirmed
+    }
+
+    /// Whether the token reaches the terminator right after the detected end of the first row and column
+    async fn reads_terminator(&mut self) -> bool {
+        let ChainTopology { rows, cols } = self.topology;
+        self.reset_chain().await;
+        for _ in 0..cols {
+            self.clock_col().await;
+        }
+        let col_terminated = self.read_input();
+        self.reset_chain().await;
+        for _ in 0..rows {
+            self.clock_row().await;
+        }
+        col_terminated && self.read_input()
+    }
+
+    /// Whether the chain reads as `reference`, and the terminator if there is one, `passes` times in a row
+    async fn reads_reliably(&mut self, reference: KeyBitmap<ROW, COL>, passes: usize) -> bool {
+        for _ in 0..passes {
+            if self.capture().await != reference {
+                return false;
+            }
+            if self.terminated && !self.reads_terminator().await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Find the fastest clock rate which reads the attached chain reliably, and scan with it from now on.
+    ///
+    /// The chain is captured at the current timing as a reference, then every sub-scan delay is halved
+    /// as long as the chain reads back the same, down to a CPU cycle. The result is backed off by one step for margin.
+    /// Without a terminator the reference is the keys held, so a key must be held during calibration.
+    /// Timer delays are rounded up to the timer tick and cannot be calibrated, use [DelayMode::Cycles].
+    /// If calibration is not possible the timing is left as it was and `None` is returned.
+    pub async fn calibrate_timing(&mut self) -> Option<ChainTiming> {
+        let DelayMode::Cycles { cpu_hz } = self.timing.delay else {
+            warn!("Cannot calibrate chain timing with timer delays");
+            return None;
+        };
+        let reference = self.capture().await;
+        if reference.is_empty() && !self.terminated {
+            warn!("Cannot calibrate chain timing without a terminator unless a key is held");
+            return None;
+        }
+
+        // Halving a delay shorter than two cycles would not make it any shorter
+        let min_ns = 2_000_000_000u64.div_ceil(cpu_hz.max(1) as u64);
+        let original = self.timing;
+        let mut fastest = None;
+        while self.reads_reliably(reference, Self::CALIBRATION_PASSES).await {
+            fastest = Some(self.timing);
+            if (self.timing.clock_high_ns.min(self.timing.clock_low_ns) as u64) < min_ns {
+                break;
+            }
+            self.timing = self.timing.scaled(1, 2);
+        }
+
+        match fastest {
+            Some(fastest) => {
+                self.timing = fastest.scaled(2, 1);
+                info!("Calibrated chain clock: {} Hz", self.timing.clock_hz());
+                Some(self.timing)
+            }
+            None => {
+                self.timing = original;
+                warn!("Chain does not read reliably even at {} Hz", original.clock_hz());
+                None
+            }
+        }
+    }
 }
 
 impl<
@@ -93,15 +474,16 @@ impl<
     D: DebouncerTrait,
     const ROW: usize,
     const COL: usize,
-> MatrixTrait for SequentialMatrix<In, Out, D, ROW, COL> {
+    R: KeyRemap,
+> MatrixTrait for SequentialMatrix<In, Out, D, ROW, COL, R> {
     const ROW: usize = ROW;
     const COL: usize = COL;
 
     #[cfg(feature = "async_matrix")]
     async fn wait_for_key(&mut self) {
         if let Some(start_time) = self.scan_start {
-            // If no key press over 1ms, stop scanning and wait for interupt
-            if start_time.elapsed().as_millis() <= 1 {
+            // If no key press within the scan window, stop scanning and wait for interupt
+            if start_time.elapsed().as_millis() <= self.idle.scan_window_ms {
                 return;
             } else {
                 self.scan_start = None;
@@ -110,9 +492,37 @@ impl<
         // First, set any_not to low
         self.pins.reset_not.set_high().ok();
         self.pins.any_not.set_low().ok();
-        Timer::after_nanos(Self::PROPAGATION_DELAY).await;
+        self.timing.delay_ns(self.timing.settle_ns).await;
 
-        let _ = self.pins.input.wait_for_high().await;
+        match self.idle.sleep_timeout_ms.filter(|_| self.sleep.is_some()) {
+            Some(timeout) => {
+                let woken = select(
+                    self.pins.wait_for_input_active(),
+                    Timer::after_millis(timeout),
+                )
+                .await;
+                if let Either::Second(()) = woken {
+                    info!("Idle for {} ms, entering deep sleep", timeout);
+                    // Park the chain, only any_not stays active
+                    self.pins.row_clock.set_low().ok();
+                    self.pins.col_clock.set_low().ok();
+                    if let Some(sleep) = self.sleep.as_deref_mut() {
+                        sleep.enter();
+                    }
+                    self.pins.wait_for_input_active().await;
+                    if let Some(sleep) = self.sleep.as_deref_mut() {
+                        sleep.exit();
+                    }
+                    info!("Woken up from deep sleep");
+
+                    // Catch the key which woke us up before it can be released
+                    self.keys.wake_keys = self.capture().await;
+                }
+            }
+            None => {
+                self.pins.wait_for_input_active().await;
+            }
+        }
 
         // Set any_not pin back to high
         self.pins.any_not.set_high().ok();
@@ -121,132 +531,58 @@ impl<
     }
 
     async fn scan(&mut self) {
-        defmt::info!("Matrix scanning");
+        self.detect_topology().await;
+        if self.calibrate_on_boot {
+            self.calibrate_timing().await;
+        }
+        self.run_diagnostics().await;
+        info!("Matrix scanning");
         loop {
             #[cfg(feature = "async_matrix")]
             self.wait_for_key().await;
 
-            // Reset
-            self.pins.row_clock.set_low().ok();
-            self.pins.col_clock.set_low().ok();
-            self.pins.any_not.set_high().ok();
-            self.pins.reset_not.set_low().ok();
-            Timer::after_nanos(Self::PROPAGATION_DELAY).await;
-            self.pins.reset_not.set_high().ok();
-            Timer::after_nanos(Self::PROPAGATION_DELAY).await;
-
-            // Scan matrix and send report
-            for row in 0..ROW {
-                for col in 0..COL {
-                    // Check input pins and debounce
-                    let debounce_state = self.debouncer.detect_change_with_debounce(
-                        row,
-                        col,
-                        self.pins.input.is_high().ok().unwrap_or_default(),
-                        &self.key_states[row][col],
-                    );
-
-                    match debounce_state {
-                        DebounceState::Debounced => {
-                            self.key_states[row][col].toggle_pressed();
-                            let key_state = self.key_states[row][col];
-
-                            KEY_EVENT_CHANNEL
-                                .send(KeyEvent {
-                                    row: row as u8,
-                                    col: col as u8,
-                                    pressed: key_state.pressed,
-                                })
-                                .await;
-                        }
-                        _ => (),
-                    }
+            if self.last_diagnostics.elapsed().as_millis() >= DIAGNOSTICS_INTERVAL_MS {
+                self.run_diagnostics().await;
+            }
 
-                    // If there's key still pressed, always refresh the self.scan_start
-                    #[cfg(feature = "async_matrix")]
-                    if self.key_states[row][col].pressed {
-                        self.scan_start = Some(Instant::now());
-                    }
+            let raw = self.capture().await;
+            self.keys.process(raw).await;
 
-                    // Clock
-                    self.pins.col_clock.set_high().ok();
-                    Timer::after_nanos(Self::PROPAGATION_DELAY).await;
-                    self.pins.col_clock.set_low().ok();
-                    Timer::after_nanos(Self::PROPAGATION_DELAY).await;
-                }
-                self.pins.row_clock.set_high().ok();
-                Timer::after_nanos(Self::PROPAGATION_DELAY).await;
-                self.pins.row_clock.set_low().ok();
-                Timer::after_nanos(Self::PROPAGATION_DELAY).await;
+            // If there's key still pressed, always refresh the self.scan_start
+            #[cfg(feature = "async_matrix")]
+            if !self.keys.pressed.is_empty() {
+                self.scan_start = Some(Instant::now());
             }
 
-            embassy_time::Timer::after_micros(100).await;
+            Timer::after_micros(self.timing.scan_gap_us as u64).await;
         }
     }
 
-    /// Read key state at position (row, col)
+    /// Read key state at keymap position (row, col), positions off this chain read as released
     fn get_key_state(&mut self, row: usize, col: usize) -> KeyState {
-        return self.key_states[row][col];
+        self.keys.get_key_state(row, col)
     }
 
+    /// Update key state at keymap position (row, col), positions off this chain are ignored
     fn update_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
-        f(&mut self.key_states[row][col]);
+        self.keys.update_key_state(row, col, f);
     }
 }
 
-
-
-pub struct OffsettedMatrix<
-    M: MatrixTrait,
-    const ROW_OFFSET: usize,
-    const COL_OFFSET: usize,
-    const ROW: usize,
-    const COL: usize,
-> {
-    matrix: M,
-}
-
 impl<
-    M: MatrixTrait,
-    const ROW_OFFSET: usize,
-    const COL_OFFSET: usize,
-    const ROW: usize,
-    const COL: usize,
-> OffsettedMatrix<M, ROW_OFFSET, COL_OFFSET, ROW, COL> {
-    pub fn new(
-        matrix: M
-    ) -> Self {
-        Self {
-            matrix,
-        }
-    }
-}
-
-impl<
-    M: MatrixTrait,
-    const ROW_OFFSET: usize,
-    const COL_OFFSET: usize,
+    #[cfg(feature = "async_matrix")] In: Wait + InputPin,
+    #[cfg(not(feature = "async_matrix"))] In: InputPin,
+    Out: OutputPin,
+    D: DebouncerTrait,
     const ROW: usize,
     const COL: usize,
-> MatrixTrait for OffsettedMatrix<M, ROW_OFFSET, COL_OFFSET, ROW, COL> {
-    const ROW: usize = ROW;
-    const COL: usize = COL;
-
-    #[cfg(feature = "async_matrix")]
-    async fn wait_for_key(&mut self) {
-        self.matrix.wait_for_key().await
-    }
-
-    async fn scan(&mut self) {
-        self.matrix.scan().await
+    R: KeyRemap,
+> MappedMatrix for SequentialMatrix<In, Out, D, ROW, COL, R> {
+    fn contains(&self, row: usize, col: usize) -> bool {
+        self.keys.contains(row, col)
     }
 
-    /// Read key state at position (row, col)
-    fn get_key_state(&mut self, row: usize, col: usize) -> KeyState {
-        return self.matrix.get_key_state(row - ROW_OFFSET, col - COL_OFFSET);
-    }
-
-    fn update_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
-        self.matrix.update_key_state(row - ROW_OFFSET, col - COL_OFFSET, f);
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.keys.to_logical(row, col)
     }
 }
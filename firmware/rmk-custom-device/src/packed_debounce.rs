@@ -0,0 +1,135 @@
+//! Debouncer keeping its state in bit-planes, for long chains.
+//!
+//! The settling time of every position is a vertical counter: bit `n` of each position's time lives in plane `n`,
+//! a [KeyBitmap] with one word per row. Time is added to a whole row at once with bitwise ripple carries,
+//! so the state is a few bits per position whatever the size of the chain, instead of a counter each.
+//!
+//! Both edges are sent once the input has been stable for [DebounceSettings::DEFAULT]'s `press_ms`,
+//! the same latency as [RuntimeDebouncer](crate::debounce::RuntimeDebouncer) with its default settings.
+use embassy_time::Instant;
+use rmk::{
+    debounce::{DebounceState, DebouncerTrait},
+    matrix::KeyState,
+};
+
+use crate::bitmap::KeyBitmap;
+use crate::debounce::DebounceSettings;
+
+/// Bits of the settling time, which saturates at `2^PLANES - 1` ms
+const PLANES: usize = 4;
+/// Stable time in ms before an edge is sent
+const THRESHOLD_MS: u32 = DebounceSettings::DEFAULT.press_ms as u32;
+
+const _: () = assert!(
+    THRESHOLD_MS < 1 << PLANES,
+    "Debounce threshold does not fit the bit-planes"
+);
+
+/// Debouncer of `ROW` rows of up to 64 columns, indexed (row, col) as the matrices of this crate call it
+pub struct PackedDebouncer<const ROW: usize, const COL: usize> {
+    /// Positions whose input disagrees with their key state, waiting to be stable
+    settling: KeyBitmap<ROW, COL>,
+    /// Settling time in ms of each position, least significant plane first
+    planes: [KeyBitmap<ROW, COL>; PLANES],
+    /// Time in ms up to which the settling times are counted
+    last_ms: u32,
+}
+
+impl<const ROW: usize, const COL: usize> PackedDebouncer<ROW, COL> {
+    pub const fn new() -> Self {
+        Self {
+            settling: KeyBitmap::new(),
+            planes: [KeyBitmap::new(); PLANES],
+            last_ms: 0,
+        }
+    }
+
+    /// Add the time elapsed since the last call to every settling position
+    fn advance(&mut self, now: u32) {
+        let elapsed = now.wrapping_sub(self.last_ms);
+        self.last_ms = now;
+        if elapsed == 0 || self.settling.is_empty() {
+            return;
+        }
+        for row in 0..ROW {
+            let settling = self.settling.row(row);
+            if settling == 0 {
+                continue;
+            }
+            if elapsed >= (1 << PLANES) - 1 {
+                self.planes
+                    .iter_mut()
+                    .for_each(|plane| plane.set_row(row, plane.row(row) | settling));
+                continue;
+            }
+            for _ in 0..elapsed {
+                self.increment(row, settling);
+            }
+        }
+    }
+
+    /// Add 1 ms to the positions of `mask` in `row`, saturating
+    fn increment(&mut self, row: usize, mask: u64) {
+        let saturated = self
+            .planes
+            .iter()
+            .fold(mask, |bits, plane| bits & plane.row(row));
+        let mut carry = mask & !saturated;
+        for plane in self.planes.iter_mut() {
+            let bits = plane.row(row);
+            plane.set_row(row, bits ^ carry);
+            carry &= bits;
+        }
+    }
+
+    fn elapsed(&self, row: usize, col: usize) -> u32 {
+        self.planes
+            .iter()
+            .enumerate()
+            .map(|(n, plane)| (plane.get(row, col) as u32) << n)
+            .sum()
+    }
+
+    fn stop_settling(&mut self, row: usize, col: usize) {
+        self.settling.set(row, col, false);
+        self.planes
+            .iter_mut()
+            .for_each(|plane| plane.set(row, col, false));
+    }
+}
+
+impl<const ROW: usize, const COL: usize> Default for PackedDebouncer<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> DebouncerTrait for PackedDebouncer<ROW, COL> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn detect_change_with_debounce(
+        &mut self,
+        row: usize,
+        col: usize,
+        pin_state: bool,
+        key_state: &KeyState,
+    ) -> DebounceState {
+        self.advance(Instant::now().as_millis() as u32);
+
+        if pin_state == key_state.pressed {
+            self.stop_settling(row, col);
+            return DebounceState::Ignored;
+        }
+        if !self.settling.get(row, col) {
+            self.settling.set(row, col, true);
+            return DebounceState::InProgress;
+        }
+        if self.elapsed(row, col) < THRESHOLD_MS {
+            return DebounceState::InProgress;
+        }
+        self.stop_settling(row, col);
+        DebounceState::Debounced
+    }
+}
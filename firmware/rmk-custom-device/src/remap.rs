@@ -0,0 +1,152 @@
+//! Mapping between physical chain positions and logical positions in the keymap.
+//!
+//! A matrix applies its [KeyRemap] both to the key events it sends and to the state lookups it serves,
+//! so the two always agree. Positions without a mapping are dropped from events and read as released.
+
+pub trait KeyRemap {
+    /// Logical position of the physical position (row, col), if mapped
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)>;
+    /// Physical position of the logical position (row, col), if mapped
+    fn to_physical(&self, row: usize, col: usize) -> Option<(usize, usize)>;
+}
+
+/// Logical positions are the physical ones
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl KeyRemap for Identity {
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        Some((row, col))
+    }
+
+    fn to_physical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        Some((row, col))
+    }
+}
+
+/// Geometric transform of a `ROW` x `COL` chain: mirroring, then transposition, then offset.
+///
+/// Rotations are built from mirroring and transposition, see [Transform::rotate_cw].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Transform<const ROW: usize, const COL: usize> {
+    pub row_offset: usize,
+    pub col_offset: usize,
+    pub mirror_rows: bool,
+    pub mirror_cols: bool,
+    pub transpose: bool,
+}
+
+impl<const ROW: usize, const COL: usize> Transform<ROW, COL> {
+    pub const fn new() -> Self {
+        Self {
+            row_offset: 0,
+            col_offset: 0,
+            mirror_rows: false,
+            mirror_cols: false,
+            transpose: false,
+        }
+    }
+
+    /// Place the chain at (row_offset, col_offset) of the keymap
+    pub const fn offset(row_offset: usize, col_offset: usize) -> Self {
+        Self {
+            row_offset,
+            col_offset,
+            ..Self::new()
+        }
+    }
+
+    pub const fn with_offset(self, row_offset: usize, col_offset: usize) -> Self {
+        Self {
+            row_offset,
+            col_offset,
+            ..self
+        }
+    }
+
+    /// Reverse the order of rows, e.g. for a half whose chain runs bottom to top
+    pub const fn mirrored_rows(self) -> Self {
+        Self {
+            mirror_rows: !self.mirror_rows,
+            ..self
+        }
+    }
+
+    /// Reverse the order of columns, e.g. for a half whose chain runs right to left
+    pub const fn mirrored_cols(self) -> Self {
+        Self {
+            mirror_cols: !self.mirror_cols,
+            ..self
+        }
+    }
+
+    pub const fn transposed(self) -> Self {
+        Self {
+            transpose: !self.transpose,
+            ..self
+        }
+    }
+
+    /// Rotation by 90 degrees clockwise, physical (row, col) lands at (col, ROW - 1 - row)
+    pub const fn rotate_cw() -> Self {
+        Self::new().mirrored_rows().transposed()
+    }
+
+    /// Rotation by 90 degrees counterclockwise, physical (row, col) lands at (COL - 1 - col, row)
+    pub const fn rotate_ccw() -> Self {
+        Self::new().mirrored_cols().transposed()
+    }
+}
+
+impl<const ROW: usize, const COL: usize> KeyRemap for Transform<ROW, COL> {
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        if row >= ROW || col >= COL {
+            return None;
+        }
+        let row = if self.mirror_rows { ROW - 1 - row } else { row };
+        let col = if self.mirror_cols { COL - 1 - col } else { col };
+        let (row, col) = if self.transpose { (col, row) } else { (row, col) };
+        Some((row + self.row_offset, col + self.col_offset))
+    }
+
+    fn to_physical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let row = row.checked_sub(self.row_offset)?;
+        let col = col.checked_sub(self.col_offset)?;
+        let (row, col) = if self.transpose { (col, row) } else { (row, col) };
+        if row >= ROW || col >= COL {
+            return None;
+        }
+        let row = if self.mirror_rows { ROW - 1 - row } else { row };
+        let col = if self.mirror_cols { COL - 1 - col } else { col };
+        Some((row, col))
+    }
+}
+
+/// Arbitrary mapping from each physical position of a `ROW` x `COL` chain
+#[derive(Clone, Copy, Debug)]
+pub struct LookupTable<const ROW: usize, const COL: usize> {
+    table: [[Option<(u8, u8)>; COL]; ROW],
+}
+
+impl<const ROW: usize, const COL: usize> LookupTable<ROW, COL> {
+    /// `table[row][col]` is the logical position of physical (row, col), `None` leaves it unmapped
+    pub const fn new(table: [[Option<(u8, u8)>; COL]; ROW]) -> Self {
+        Self { table }
+    }
+}
+
+impl<const ROW: usize, const COL: usize> KeyRemap for LookupTable<ROW, COL> {
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let (row, col) = (*self.table.get(row)?.get(col)?)?;
+        Some((row as usize, col as usize))
+    }
+
+    fn to_physical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let key = Some((u8::try_from(row).ok()?, u8::try_from(col).ok()?));
+        self.table.iter().enumerate().find_map(|(p_row, cols)| {
+            cols.iter()
+                .position(|entry| *entry == key)
+                .map(|p_col| (p_row, p_col))
+        })
+    }
+}
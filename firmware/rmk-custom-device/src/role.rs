@@ -0,0 +1,98 @@
+//! Role of a half running the single split firmware image.
+//!
+//! The role is decided at boot and kept in a flash sector of its own, so later boots agree with it,
+//! also once a strap is removed. See [resolve_role] for the precedence.
+use embedded_storage_async::nor_flash::NorFlash;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SplitRole {
+    Central,
+    /// Peripheral of the given id
+    Peripheral(u8),
+}
+
+/// Marks a sector holding a role record
+const MAGIC: [u8; 4] = *b"ROLE";
+/// Magic, role, peripheral id and the complement of both
+const RECORD_SIZE: usize = 8;
+
+impl SplitRole {
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let (role, id) = match self {
+            SplitRole::Central => (0, 0),
+            SplitRole::Peripheral(id) => (1, *id),
+        };
+        let mut record = [0; RECORD_SIZE];
+        record[..4].copy_from_slice(&MAGIC);
+        record[4..].copy_from_slice(&[role, id, !role, !id]);
+        record
+    }
+
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let [role, id, not_role, not_id] = [record[4], record[5], record[6], record[7]];
+        if record[..4] != MAGIC || role != !not_role || id != !not_id {
+            return None;
+        }
+        match role {
+            0 => Some(SplitRole::Central),
+            1 => Some(SplitRole::Peripheral(id)),
+            _ => None,
+        }
+    }
+}
+
+/// Role stored in the sector at `offset`, `None` if no valid record is there
+pub async fn load_role<F: NorFlash>(flash: &mut F, offset: u32) -> Option<SplitRole> {
+    let mut record = [0; RECORD_SIZE];
+    flash.read(offset, &mut record).await.ok()?;
+    SplitRole::decode(&record)
+}
+
+/// Store `role` in the sector at `offset`, which nothing else may use
+pub async fn store_role<F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+    role: SplitRole,
+) -> Result<(), F::Error> {
+    assert!(
+        RECORD_SIZE % F::WRITE_SIZE == 0,
+        "Role record is not a multiple of the flash write size"
+    );
+    flash.erase(offset, offset + F::ERASE_SIZE as u32).await?;
+    flash.write(offset, &role.encode()).await
+}
+
+/// Forget the stored role, so the next boot detects it again
+pub async fn clear_role<F: NorFlash>(flash: &mut F, offset: u32) -> Result<(), F::Error> {
+    flash.erase(offset, offset + F::ERASE_SIZE as u32).await
+}
+
+/// Role of this half: the `strap` if one is fitted, else a peripheral if USB does not power it,
+/// else the stored role, else the central. The role found is stored.
+///
+/// Only the central is powered by USB in use, so a half booting without `vbus` is a peripheral whatever is stored.
+/// This corrects a peripheral which stored the central while a UF2 image was flashed onto it over USB.
+/// Once stored, a peripheral stays one when USB is plugged into it. To make it the central, fit its strap once.
+pub async fn resolve_role<F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+    strap: Option<SplitRole>,
+    vbus: bool,
+) -> SplitRole {
+    let stored = load_role(flash, offset).await;
+    let role = match (strap, vbus, stored) {
+        (Some(role), _, _) => role,
+        (None, false, Some(SplitRole::Peripheral(id))) => SplitRole::Peripheral(id),
+        (None, false, _) => SplitRole::Peripheral(0),
+        (None, true, Some(role)) => role,
+        (None, true, None) => SplitRole::Central,
+    };
+    if stored != Some(role) {
+        info!("Storing split role {:?}", role);
+        if store_role(flash, offset, role).await.is_err() {
+            warn!("Failed to store split role");
+        }
+    }
+    role
+}
@@ -0,0 +1,270 @@
+//! RP2040 half-duplex serial over a single wire, for split halves joined by a 3-pole cable.
+//!
+//! Two state machines share the pin: one sends 8N1 frames, the other receives them.
+//! The pin is only ever driven low, a high level comes from the pull-ups, so both halves
+//! may drive the wire at once without harm. Fit an external pull-up for long cables or fast rates.
+//!
+//! The receiver hears everything on the wire, including what this half sends, which is how
+//! collisions are found: a byte that does not read back as sent was overwritten by the other half.
+//! That byte is the other half's and is kept for the next read, see [crate::half_duplex].
+//! The whole write is then sent again after a random backoff. Before sending, the wire must have been
+//! quiet for a turnaround time, so the other half has finished its message. While waiting, for the quiet
+//! wire or after a collision, the receiver's FIFO is kept drained, it only holds 4 bytes.
+use embassy_futures::select::{select, Either};
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    gpio::{Level, Pull},
+    pio::{
+        Common, Config, Direction, Instance, PioPin, ShiftConfig, ShiftDirection, StateMachine,
+        StateMachineRx,
+    },
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Error, ErrorKind, ErrorType, Read, Write};
+use fixed::{traits::ToFixed, types::U56F8};
+
+use crate::half_duplex::{RxFifo, RxQueue};
+
+/// PIO cycles per bit, in both programs
+const CYCLES_PER_BIT: u32 = 8;
+/// Attempts at sending a write before giving up on it
+const MAX_ATTEMPTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HalfDuplexError {
+    /// Every attempt at sending collided with the other half
+    Collision,
+}
+
+impl Error for HalfDuplexError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+pub struct PioHalfDuplexUart<'d, PIO: Instance, const TX: usize, const RX: usize> {
+    tx: StateMachine<'d, PIO, TX>,
+    rx: StateMachine<'d, PIO, RX>,
+    /// Time of a byte on the wire, start and stop bits included
+    byte_time: Duration,
+    /// Quiet time on the wire before sending
+    turnaround: Duration,
+    /// Last time a byte was on the wire
+    last_activity: Instant,
+    /// Bytes of the other half received while waiting to send or sending
+    pending: RxQueue,
+    /// State of the backoff's random numbers
+    seed: u32,
+    collisions: u32,
+}
+
+impl<'d, PIO: Instance, const TX: usize, const RX: usize> PioHalfDuplexUart<'d, PIO, TX, RX> {
+    /// Load both programs and take over `pin`, at `baud` bits per second
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut tx: StateMachine<'d, PIO, TX>,
+        mut rx: StateMachine<'d, PIO, RX>,
+        pin: impl PioPin,
+        baud: u32,
+    ) -> Self {
+        // Side-set and `out` drive the pin direction: output drives low, input lets the pull-ups raise it.
+        // The bits are inverted in the FIFO, so a 0 bit switches the pin to output.
+        let tx_prg = pio_proc::pio_asm!(
+            ".side_set 1 opt pindirs",
+            ".wrap_target",
+            "    pull block          side 0 [7]",
+            "    set x, 7            side 1 [7]",
+            "bitloop:",
+            "    out pindirs, 1",
+            "    jmp x-- bitloop     [6]",
+            ".wrap",
+        );
+        // Samples the middle of each bit, bytes with a bad stop bit are dropped
+        let rx_prg = pio_proc::pio_asm!(
+            ".wrap_target",
+            "start:",
+            "    wait 0 pin 0",
+            "    set x, 7            [10]",
+            "bitloop:",
+            "    in pins, 1",
+            "    jmp x-- bitloop     [6]",
+            "    jmp pin good_stop",
+            "    wait 1 pin 0",
+            "    jmp start",
+            "good_stop:",
+            "    push block",
+            ".wrap",
+        );
+
+        let mut pin = common.make_pio_pin(pin);
+        pin.set_pull(Pull::Up);
+        tx.set_pins(Level::Low, &[&pin]);
+        tx.set_pin_dirs(Direction::In, &[&pin]);
+        rx.set_pin_dirs(Direction::In, &[&pin]);
+
+        let clock_divider =
+            (U56F8::from_num(clk_sys_freq()) / U56F8::from_num(baud * CYCLES_PER_BIT)).to_fixed();
+
+        let mut cfg = Config::default();
+        cfg.use_program(&common.load_program(&tx_prg.program), &[&pin]);
+        cfg.set_out_pins(&[&pin]);
+        cfg.shift_out = ShiftConfig {
+            auto_fill: false,
+            threshold: 32,
+            direction: ShiftDirection::Right,
+        };
+        cfg.clock_divider = clock_divider;
+        tx.set_config(&cfg);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&common.load_program(&rx_prg.program), &[]);
+        cfg.set_in_pins(&[&pin]);
+        cfg.set_jmp_pin(&pin);
+        cfg.shift_in = ShiftConfig {
+            auto_fill: false,
+            threshold: 32,
+            direction: ShiftDirection::Right,
+        };
+        cfg.clock_divider = clock_divider;
+        rx.set_config(&cfg);
+
+        tx.set_enable(true);
+        rx.set_enable(true);
+
+        let byte_time = Duration::from_micros(10 * 1_000_000 / baud as u64 + 1);
+        Self {
+            tx,
+            rx,
+            byte_time,
+            turnaround: byte_time * 2,
+            last_activity: Instant::now(),
+            pending: RxQueue::new(),
+            seed: Instant::now().as_ticks() as u32,
+            collisions: 0,
+        }
+    }
+
+    /// Quiet time on the wire before sending, at least two byte times so a byte being received lands first
+    pub fn with_turnaround(mut self, turnaround: Duration) -> Self {
+        self.turnaround = turnaround.max(self.byte_time * 2);
+        self
+    }
+
+    /// Number of collisions since boot, each cost a resend
+    pub fn collisions(&self) -> u32 {
+        self.collisions
+    }
+
+    /// Move everything received to `pending`
+    fn drain(&mut self) {
+        if self.pending.drain(self.rx.rx()) {
+            self.last_activity = Instant::now();
+        }
+    }
+
+    /// Move everything received to `pending` until `deadline`
+    async fn receive_until(&mut self, deadline: Instant) {
+        if let Some(at) = self.pending.drain_until(self.rx.rx(), deadline).await {
+            self.last_activity = at;
+        }
+    }
+
+    /// Wait until the wire has been quiet for the turnaround time
+    async fn wait_quiet(&mut self) {
+        loop {
+            self.drain();
+            let quiet_until = self.last_activity + self.turnaround;
+            if Instant::now() >= quiet_until {
+                return;
+            }
+            self.receive_until(quiet_until).await;
+        }
+    }
+
+    /// Send `buf` and read each byte back, returns whether all of them read back as sent.
+    /// A byte overwritten by the other half is kept in `pending`, it starts the other half's message
+    async fn send(&mut self, buf: &[u8]) -> bool {
+        for &byte in buf {
+            self.tx.tx().wait_push(u32::from(!byte)).await;
+            let echo = select(self.rx.rx().wait_pull(), Timer::after(self.byte_time * 2)).await;
+            self.last_activity = Instant::now();
+            let heard = match echo {
+                Either::First(word) => Some((word >> 24) as u8),
+                // The wire is held low
+                Either::Second(()) => None,
+            };
+            if !self.pending.check_echo(byte, heard) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Random backoff of 1 to 16 byte times, so both halves do not resend at the same time again
+    fn backoff(&mut self) -> Duration {
+        self.seed = (self.seed ^ Instant::now().as_ticks() as u32)
+            .wrapping_mul(1_664_525)
+            .wrapping_add(1_013_904_223);
+        self.byte_time * (1 + (self.seed >> 28))
+    }
+}
+
+/// Received bytes sit in the top byte of the word
+impl<'d, PIO: Instance, const SM: usize> RxFifo for StateMachineRx<'d, PIO, SM> {
+    fn try_pull(&mut self) -> Option<u8> {
+        StateMachineRx::try_pull(self).map(|word| (word >> 24) as u8)
+    }
+
+    async fn wait_pull(&mut self) -> u8 {
+        (StateMachineRx::wait_pull(self).await >> 24) as u8
+    }
+}
+
+impl<'d, PIO: Instance, const TX: usize, const RX: usize> ErrorType
+    for PioHalfDuplexUart<'d, PIO, TX, RX>
+{
+    type Error = HalfDuplexError;
+}
+
+impl<'d, PIO: Instance, const TX: usize, const RX: usize> Read
+    for PioHalfDuplexUart<'d, PIO, TX, RX>
+{
+    /// Cancel safe, nothing is taken from the FIFO before the read completes
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() {
+            let byte = RxFifo::wait_pull(self.rx.rx()).await;
+            self.last_activity = Instant::now();
+            // Cannot fail, the queue is empty
+            self.pending.push(byte);
+            self.drain();
+        }
+        Ok(self.pending.take(buf))
+    }
+}
+
+impl<'d, PIO: Instance, const TX: usize, const RX: usize> Write
+    for PioHalfDuplexUart<'d, PIO, TX, RX>
+{
+    /// Send all of `buf`, resending it from the start after a collision
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for attempt in 1..=MAX_ATTEMPTS {
+            self.wait_quiet().await;
+            if self.send(buf).await {
+                return Ok(buf.len());
+            }
+            self.collisions = self.collisions.wrapping_add(1);
+            debug!("Half duplex collision, attempt {}", attempt);
+            let backoff = self.backoff();
+            self.receive_until(Instant::now() + backoff).await;
+        }
+        warn!(
+            "Half duplex write dropped after {} collisions",
+            MAX_ATTEMPTS
+        );
+        Err(HalfDuplexError::Collision)
+    }
+}
@@ -0,0 +1,488 @@
+//! RP2040 backend of the sequential matrix, clocking the chain from a PIO state machine.
+//!
+//! The state machine generates the clocks and the reset pulse, samples `input` once per position,
+//! and streams the packed bitmap to memory through DMA.
+//! The CPU only wakes up once per scan, to run the debouncer over the bitmap.
+//!
+//! Each scan ends with the samples of the diagnostics, see [PioSequentialMatrix::run_diagnostics].
+//! The chain is scanned at its compile-time size, the terminator is not probed.
+//!
+//! PIO addresses pins by ranges, so the chain must be wired to consecutive GPIOs:
+//! `row_clock`, `col_clock` on side-set pins and `any_not`, `reset_not` on set pins.
+//! The default wiring of PIN_9 to PIN_13 satisfies this.
+#[cfg(feature = "async_matrix")]
+use core::pin::pin;
+
+#[cfg(feature = "async_matrix")]
+use embassy_futures::select::{select, Either};
+use embassy_rp::{
+    clocks::clk_sys_freq,
+    dma::Channel,
+    gpio::Pull,
+    into_ref,
+    pio::{
+        Common, Config, Direction, Instance, Pin, PioPin, ShiftConfig, ShiftDirection,
+        StateMachine,
+    },
+    Peripheral, PeripheralRef,
+};
+use embassy_time::{Instant, Timer};
+use fixed::{traits::ToFixed, types::U56F8};
+use rmk::{
+    debounce::DebouncerTrait,
+    matrix::{KeyState, MatrixTrait},
+};
+
+use crate::bitmap::KeyBitmap;
+use crate::composite::MappedMatrix;
+use crate::diagnostics::{chain_faults, set_chain_faults, ChainFault, ChainFaults, FaultFilter};
+use crate::keys::{ChainKeys, DIAGNOSTICS_INTERVAL_MS};
+use crate::remap::{Identity, KeyRemap};
+#[cfg(feature = "async_matrix")]
+use crate::sleep::{DeepSleep, IdleConfig};
+use crate::timing::ChainTiming;
+
+/// PIO cycles spent on each chain position, see the program in [PioSequentialMatrix::new]
+const CYCLES_PER_POSITION: u32 = 12;
+
+/// Samples taken after the positions: `input` shifted out, with `any_not` low, and with `any_not` high again
+const DIAGNOSTIC_SAMPLES: usize = 3;
+
+/// Number of words the state machine pushes for a scan of `row` x `col` positions.
+///
+/// Full words are pushed automatically and the program flushes the rest at the end of the scan,
+/// which yields one word more than the full ones even when the last word was complete.
+pub const fn bitmap_words(row: usize, col: usize) -> usize {
+    (row * col + DIAGNOSTIC_SAMPLES) / 32 + 1
+}
+
+/// Chain pins taken over by a PIO state machine, for [PioSequentialMatrix]
+pub struct PioChainPins<'d, PIO: Instance, const SM: usize, C: Channel> {
+    common: Common<'d, PIO>,
+    sm: StateMachine<'d, PIO, SM>,
+    dma: PeripheralRef<'d, C>,
+    /// Buffer of the scans, at least [bitmap_words] long
+    samples: &'d mut [u32],
+    row_clock: Pin<'d, PIO>,
+    col_clock: Pin<'d, PIO>,
+    any_not: Pin<'d, PIO>,
+    reset_not: Pin<'d, PIO>,
+    input: Pin<'d, PIO>,
+    /// Whether `input` reads low for a pressed switch
+    input_active_low: bool,
+}
+
+impl<'d, PIO: Instance, const SM: usize, C: Channel> PioChainPins<'d, PIO, SM, C> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mut common: Common<'d, PIO>,
+        sm: StateMachine<'d, PIO, SM>,
+        dma: impl Peripheral<P = C> + 'd,
+        samples: &'d mut [u32],
+        row_clock: impl PioPin,
+        col_clock: impl PioPin,
+        any_not: impl PioPin,
+        reset_not: impl PioPin,
+        input: impl PioPin,
+        input_pull: Pull,
+    ) -> Self {
+        into_ref!(dma);
+        let row_clock = common.make_pio_pin(row_clock);
+        let col_clock = common.make_pio_pin(col_clock);
+        let any_not = common.make_pio_pin(any_not);
+        let reset_not = common.make_pio_pin(reset_not);
+        let mut input = common.make_pio_pin(input);
+        input.set_pull(input_pull);
+        Self {
+            common,
+            sm,
+            dma,
+            samples,
+            row_clock,
+            col_clock,
+            any_not,
+            reset_not,
+            input,
+            input_active_low: false,
+        }
+    }
+
+    /// Read `input` as pressed when low, for boards with an inverting buffer on the chain output
+    pub fn with_input_active_low(mut self, active_low: bool) -> Self {
+        self.input_active_low = active_low;
+        self
+    }
+}
+
+/// Matrix of switches on the daisy-chained flip-flops, scanned by a PIO state machine
+pub struct PioSequentialMatrix<
+    'd,
+    PIO: Instance,
+    const SM: usize,
+    C: Channel,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap = Identity,
+> {
+    /// Owner of the loaded program
+    _common: Common<'d, PIO>,
+    sm: StateMachine<'d, PIO, SM>,
+    dma: PeripheralRef<'d, C>,
+    /// Samples of the last scan, one bit per position in chain order, then the diagnostics
+    samples: &'d mut [u32],
+    input_active_low: bool,
+    /// Key states, debounced from the captures
+    keys: ChainKeys<D, ROW, COL, R>,
+    /// Last time the diagnostics samples were checked
+    last_diagnostics: Instant,
+    /// Passes in a row each fault was seen in
+    fault_filter: FaultFilter,
+    /// Gap between the end of a scan and the start of the next one
+    scan_gap_us: u32,
+    /// Scan window and deep sleep timeout
+    #[cfg(feature = "async_matrix")]
+    idle: IdleConfig,
+    /// Deep sleep entered after `idle.sleep_timeout_ms`
+    #[cfg(feature = "async_matrix")]
+    sleep: Option<&'static mut dyn DeepSleep>,
+    /// Start scanning
+    #[cfg(feature = "async_matrix")]
+    scan_start: Option<Instant>,
+    /// Whether the next scan should wait on `any_not` for a key press
+    #[cfg(feature = "async_matrix")]
+    wait_armed: bool,
+}
+
+impl<
+    'd,
+    PIO: Instance,
+    const SM: usize,
+    C: Channel,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> PioSequentialMatrix<'d, PIO, SM, C, D, ROW, COL, R> {
+    /// Load the scanner program on `pins` and start its state machine,
+    /// mapping chain positions to keymap positions through `remap`.
+    ///
+    /// Each position takes [CYCLES_PER_POSITION] PIO cycles at `timing.clock_hz()`, which sets the clock divider.
+    /// The other delays are fixed by the program, only `timing.scan_gap_us` is kept as is.
+    pub fn new(
+        pins: PioChainPins<'d, PIO, SM, C>,
+        debouncer: D,
+        remap: R,
+        timing: ChainTiming,
+    ) -> Self {
+        let PioChainPins {
+            mut common,
+            mut sm,
+            dma,
+            samples,
+            row_clock,
+            col_clock,
+            any_not,
+            reset_not,
+            input,
+            input_active_low,
+        } = pins;
+        assert!(samples.len() >= bitmap_words(ROW, COL));
+        assert!(ROW > 0 && ROW <= 1 << 15 && COL > 0 && COL <= 1 << 16);
+
+        // Command word: bit 0 waits for a key press before scanning,
+        // bits 1..16 hold ROW - 1 and bits 16..32 hold COL - 1.
+        // Side-set bit 0 is row_clock, bit 1 is col_clock.
+        // Set bit 0 is any_not, bit 1 is reset_not.
+        // After the positions, one more row clock shifts the selection out of the chain,
+        // then `input` is sampled as is, with `any_not` low and with `any_not` high again.
+        let prg = pio_proc::pio_asm!(
+            ".side_set 2",
+            ".wrap_target",
+            "    pull block          side 0b00",
+            "    out x, 1            side 0b00",
+            "    jmp !x start        side 0b00",
+            "    set pins, 0b10      side 0b00",
+            "public wait_key:",
+            "    wait 1 pin 0        side 0b00",
+            "start:",
+            "    set pins, 0b01      side 0b00 [7]",
+            "    set pins, 0b11      side 0b00 [7]",
+            "    out y, 15           side 0b00",
+            "row_loop:",
+            "    mov x, osr          side 0b00",
+            "col_loop:",
+            "    in pins, 1          side 0b00 [3]",
+            "    nop                 side 0b10 [3]",
+            "    jmp x-- col_loop    side 0b00 [3]",
+            "    nop                 side 0b01 [3]",
+            "    jmp y-- row_loop    side 0b00 [3]",
+            "    nop                 side 0b01 [3]",
+            "    nop                 side 0b00 [3]",
+            "    in pins, 1          side 0b00",
+            "    set pins, 0b10      side 0b00 [7]",
+            "    in pins, 1          side 0b00",
+            "    set pins, 0b11      side 0b00 [7]",
+            "    in pins, 1          side 0b00",
+            "    push block          side 0b00",
+            ".wrap",
+        );
+        let mut program = prg.program;
+        if input_active_low {
+            // Polarity bit of the WAIT instruction, a key press pulls `input` low
+            program.code[prg.public_defines.wait_key as usize] &= !(1 << 7);
+        }
+
+        sm.set_pin_dirs(Direction::Out, &[&row_clock, &col_clock, &any_not, &reset_not]);
+        sm.set_pin_dirs(Direction::In, &[&input]);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&common.load_program(&program), &[&row_clock, &col_clock]);
+        cfg.set_set_pins(&[&any_not, &reset_not]);
+        cfg.set_in_pins(&[&input]);
+        cfg.shift_in = ShiftConfig {
+            auto_fill: true,
+            threshold: 32,
+            direction: ShiftDirection::Right,
+        };
+        cfg.shift_out = ShiftConfig {
+            auto_fill: false,
+            threshold: 32,
+            direction: ShiftDirection::Right,
+        };
+        // The PIO cycle rate does not fit in a u32 above 357 MHz of chain clock, so it is computed in fixed point
+        let cycle_hz = U56F8::from_num(timing.clock_hz().max(1)) * U56F8::from_num(CYCLES_PER_POSITION);
+        cfg.clock_divider = (U56F8::from_num(clk_sys_freq()) / cycle_hz)
+            .clamp(U56F8::ONE, U56F8::from_num(u16::MAX))
+            .to_fixed();
+        sm.set_config(&cfg);
+        sm.set_enable(true);
+
+        Self {
+            _common: common,
+            sm,
+            dma,
+            samples,
+            input_active_low,
+            keys: ChainKeys::new(debouncer, remap),
+            last_diagnostics: Instant::MIN,
+            fault_filter: FaultFilter::new(),
+            scan_gap_us: timing.scan_gap_us,
+            #[cfg(feature = "async_matrix")]
+            idle: IdleConfig::DEFAULT,
+            #[cfg(feature = "async_matrix")]
+            sleep: None,
+            #[cfg(feature = "async_matrix")]
+            scan_start: None,
+            #[cfg(feature = "async_matrix")]
+            wait_armed: false,
+        }
+    }
+
+    /// Put the MCU into deep sleep through `sleep` after `idle.sleep_timeout_ms` without key presses.
+    /// The state machine and DMA wake the MCU up, so `sleep` must keep them clocked.
+    #[cfg(feature = "async_matrix")]
+    pub fn with_deep_sleep(mut self, sleep: &'static mut dyn DeepSleep, idle: IdleConfig) -> Self {
+        self.sleep = Some(sleep);
+        self.idle = idle;
+        self
+    }
+
+    #[cfg(feature = "async_matrix")]
+    pub fn set_idle_config(&mut self, idle: IdleConfig) {
+        self.idle = idle;
+    }
+
+    /// Run one scan of the chain, leaving the samples in `self.samples`.
+    ///
+    /// With `wait_for_key` the state machine first waits on `any_not` for a key press,
+    /// in deep sleep after `idle.sleep_timeout_ms`. Returns whether it slept.
+    async fn capture(&mut self, wait_for_key: bool) -> bool {
+        let command = wait_for_key as u32 | ((ROW as u32 - 1) << 1) | ((COL as u32 - 1) << 16);
+        self.sm.tx().wait_push(command).await;
+        let words = bitmap_words(ROW, COL);
+        let transfer = self
+            .sm
+            .rx()
+            .dma_pull(self.dma.reborrow(), &mut self.samples[..words]);
+
+        #[cfg(feature = "async_matrix")]
+        let slept = match (wait_for_key, self.idle.sleep_timeout_ms, self.sleep.as_deref_mut()) {
+            (true, Some(timeout), Some(sleep)) => {
+                let mut transfer = pin!(transfer);
+                match select(transfer.as_mut(), Timer::after_millis(timeout)).await {
+                    Either::First(()) => false,
+                    Either::Second(()) => {
+                        info!("Idle for {} ms, entering deep sleep", timeout);
+                        sleep.enter();
+                        transfer.await;
+                        sleep.exit();
+                        info!("Woken up from deep sleep");
+                        true
+                    }
+                }
+            }
+            _ => {
+                transfer.await;
+                false
+            }
+        };
+        #[cfg(not(feature = "async_matrix"))]
+        let slept = {
+            transfer.await;
+            false
+        };
+
+        // Samples are shifted in from the MSB, so the partial last word holds them at the top
+        let rem = (ROW * COL + DIAGNOSTIC_SAMPLES) % 32;
+        if rem != 0 {
+            self.samples[words - 1] >>= 32 - rem;
+        }
+        slept
+    }
+
+    /// Whether the `index`th sample of the last scan reads active
+    fn sample(&self, index: usize) -> bool {
+        (self.samples[index / 32] & (1 << (index % 32)) != 0) != self.input_active_low
+    }
+
+    /// Positions read pressed by the last scan
+    fn bitmap(&self) -> KeyBitmap<ROW, COL> {
+        let mut bitmap = KeyBitmap::new();
+        for row in 0..ROW {
+            let mut bits = 0;
+            for col in 0..COL {
+                if self.sample(row * COL + col) {
+                    bits |= 1 << col;
+                }
+            }
+            bitmap.set_row(row, bits);
+        }
+        bitmap
+    }
+
+    /// Check the diagnostics samples of the last scan, and return the faults confirmed, see [diagnostics](crate::diagnostics).
+    ///
+    /// These are the checks of [SequentialMatrix::run_diagnostics](crate::matrix::SequentialMatrix::run_diagnostics)
+    /// but the broken chain, which needs a terminator:
+    ///
+    /// * stuck `input`: with the selection shifted out of the chain, `input` must read low
+    /// * stuck `any_not`: the same must hold after pulsing `any_not`
+    /// * stuck low `input`: while the scan read keys pressed, `any_not` must pull `input` high
+    /// * all pressed: a chain with more positions than fingers cannot have every one of them pressed
+    ///
+    /// A pass while a debounce is in progress is not counted, the scan may not match the switches then.
+    fn run_diagnostics(&mut self) -> ChainFaults {
+        self.last_diagnostics = Instant::now();
+        if !self.keys.settling.is_empty() {
+            return chain_faults();
+        }
+
+        let mut faults = ChainFaults::empty();
+        let diagnostics = ROW * COL;
+        if self.sample(diagnostics) {
+            faults.insert(ChainFault::StuckHigh);
+        } else if self.sample(diagnostics + 2) {
+            faults.insert(ChainFault::AnyNotStuck);
+        } else if !self.sample(diagnostics + 1) && !self.keys.snapshot.is_empty() {
+            faults.insert(ChainFault::StuckLow);
+        }
+
+        if self.keys.all_pressed(ROW * COL) {
+            faults.insert(ChainFault::AllPressed);
+        }
+
+        let confirmed = self.fault_filter.update(faults);
+        set_chain_faults(confirmed);
+        confirmed
+    }
+}
+
+impl<
+    'd,
+    PIO: Instance,
+    const SM: usize,
+    C: Channel,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> MatrixTrait for PioSequentialMatrix<'d, PIO, SM, C, D, ROW, COL, R> {
+    const ROW: usize = ROW;
+    const COL: usize = COL;
+
+    /// The state machine waits on `any_not` itself, so this only arms the next scan to do so
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_key(&mut self) {
+        if let Some(start_time) = self.scan_start {
+            // Keep scanning within the scan window, past it the next scan waits for a key press
+            if start_time.elapsed().as_millis() <= self.idle.scan_window_ms {
+                return;
+            } else {
+                self.scan_start = None;
+            }
+        }
+        self.wait_armed = true;
+    }
+
+    async fn scan(&mut self) {
+        info!("PIO matrix scanning");
+        loop {
+            #[cfg(feature = "async_matrix")]
+            self.wait_for_key().await;
+
+            #[cfg(feature = "async_matrix")]
+            let wait_for_key = core::mem::take(&mut self.wait_armed);
+            #[cfg(not(feature = "async_matrix"))]
+            let wait_for_key = false;
+            let slept = self.capture(wait_for_key).await;
+            let raw = self.bitmap();
+            if slept {
+                // Catch the key which woke us up before it can be released
+                self.keys.wake_keys = raw;
+            }
+
+            self.keys.process(raw).await;
+            if self.last_diagnostics.elapsed().as_millis() >= DIAGNOSTICS_INTERVAL_MS {
+                self.run_diagnostics();
+            }
+
+            // Scan on after a key press, and while keys are still pressed
+            #[cfg(feature = "async_matrix")]
+            if wait_for_key || !self.keys.pressed.is_empty() {
+                self.scan_start = Some(Instant::now());
+            }
+
+            Timer::after_micros(self.scan_gap_us as u64).await;
+        }
+    }
+
+    /// Read key state at keymap position (row, col), positions off this chain read as released
+    fn get_key_state(&mut self, row: usize, col: usize) -> KeyState {
+        self.keys.get_key_state(row, col)
+    }
+
+    /// Update key state at keymap position (row, col), positions off this chain are ignored
+    fn update_key_state(&mut self, row: usize, col: usize, f: impl FnOnce(&mut KeyState)) {
+        self.keys.update_key_state(row, col, f);
+    }
+}
+
+impl<
+    'd,
+    PIO: Instance,
+    const SM: usize,
+    C: Channel,
+    D: DebouncerTrait,
+    const ROW: usize,
+    const COL: usize,
+    R: KeyRemap,
+> MappedMatrix for PioSequentialMatrix<'d, PIO, SM, C, D, ROW, COL, R> {
+    fn contains(&self, row: usize, col: usize) -> bool {
+        self.keys.contains(row, col)
+    }
+
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        self.keys.to_logical(row, col)
+    }
+}
@@ -0,0 +1,114 @@
+//! Clock-gated sleep of the RP2040 while the chain is parked.
+//!
+//! The executor already sleeps while the matrix waits on `input`.
+//! This additionally sets SLEEPDEEP and gates every clock not needed to take the GPIO interrupt,
+//! keep SRAM and the timer alive and, optionally, let the host resume a suspended USB link,
+//! keep receiving on a split link, finish flash operations in flight and let a PIO matrix wait on `any_not`.
+//!
+//! This is not the chip's DORMANT state: the crystal and PLLs keep running, so wake-up is immediate and
+//! nothing needs restoring but the clock gates. DORMANT stops every clock, the timer, USB and split link with it.
+use embassy_rp::pac::{
+    self,
+    clocks::regs::{SleepEn0, SleepEn1},
+};
+
+use crate::sleep::DeepSleep;
+
+pub struct RpClockGatedSleep {
+    /// Keep the USB controller clocked, so the host can resume the keyboard
+    keep_usb: bool,
+    /// Keep the UARTs and PIOs clocked, so a split link keeps receiving
+    keep_serial_link: bool,
+    /// Keep DMA and XIP clocked, so an async flash operation is not stalled halfway
+    keep_flash: bool,
+    /// Keep the PIOs and DMA clocked, so a PIO matrix waiting on `any_not` wakes the MCU up
+    keep_pio_matrix: bool,
+    /// Clock gates to restore on wake-up
+    saved: Option<(SleepEn0, SleepEn1)>,
+}
+
+impl RpClockGatedSleep {
+    pub const fn new(keep_usb: bool) -> Self {
+        Self {
+            keep_usb,
+            keep_serial_link: false,
+            keep_flash: false,
+            keep_pio_matrix: false,
+            saved: None,
+        }
+    }
+
+    /// Keep the clocks of a split link, UART or PIO
+    pub const fn with_serial_link(self) -> Self {
+        Self {
+            keep_serial_link: true,
+            ..self
+        }
+    }
+
+    /// Keep the clocks of async flash operations, for keyboards storing settings in flash
+    pub const fn with_flash(self) -> Self {
+        Self {
+            keep_flash: true,
+            ..self
+        }
+    }
+
+    /// Keep the clocks of the PIO scanner in `rp_pio`, which wakes the MCU up instead of a GPIO interrupt
+    pub const fn with_pio_matrix(self) -> Self {
+        Self {
+            keep_pio_matrix: true,
+            ..self
+        }
+    }
+}
+
+impl DeepSleep for RpClockGatedSleep {
+    fn enter(&mut self) {
+        self.saved = Some((pac::CLOCKS.sleep_en0().read(), pac::CLOCKS.sleep_en1().read()));
+
+        let mut en0 = SleepEn0(0);
+        en0.set_clk_sys_clocks(true);
+        en0.set_clk_sys_busfabric(true);
+        en0.set_clk_sys_busctrl(true);
+        en0.set_clk_sys_io(true);
+        en0.set_clk_sys_pads(true);
+        en0.set_clk_sys_sio(true);
+        en0.set_clk_sys_pll_sys(true);
+        en0.set_clk_sys_sram0(true);
+        en0.set_clk_sys_sram1(true);
+        en0.set_clk_sys_sram2(true);
+        en0.set_clk_sys_sram3(true);
+        en0.set_clk_sys_pll_usb(self.keep_usb);
+        en0.set_clk_sys_pio0(self.keep_serial_link || self.keep_pio_matrix);
+        en0.set_clk_sys_pio1(self.keep_serial_link || self.keep_pio_matrix);
+        en0.set_clk_sys_dma(self.keep_flash || self.keep_pio_matrix);
+
+        let mut en1 = SleepEn1(0);
+        en1.set_clk_sys_sram4(true);
+        en1.set_clk_sys_sram5(true);
+        en1.set_clk_sys_timer(true);
+        en1.set_clk_sys_xosc(true);
+        en1.set_clk_sys_usbctrl(self.keep_usb);
+        en1.set_clk_usb_usbctrl(self.keep_usb);
+        en1.set_clk_sys_uart0(self.keep_serial_link);
+        en1.set_clk_peri_uart0(self.keep_serial_link);
+        en1.set_clk_sys_uart1(self.keep_serial_link);
+        en1.set_clk_peri_uart1(self.keep_serial_link);
+        en1.set_clk_sys_xip(self.keep_flash);
+
+        pac::CLOCKS.sleep_en0().write_value(en0);
+        pac::CLOCKS.sleep_en1().write_value(en1);
+        // SAFETY: only the SLEEPDEEP bit of SCR is touched, nothing else owns it
+        unsafe { cortex_m::Peripherals::steal() }.SCB.set_sleepdeep();
+    }
+
+    fn exit(&mut self) {
+        // SAFETY: see `enter`
+        unsafe { cortex_m::Peripherals::steal() }.SCB.clear_sleepdeep();
+        if let Some((en0, en1)) = self.saved.take() {
+            pac::CLOCKS.sleep_en0().write_value(en0);
+            pac::CLOCKS.sleep_en1().write_value(en1);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Software model of the daisy-chained D flip-flop switch chain.
+//!
+//! The model follows the behaviour drawn in `circuit/ChainSimulation/sim.html`:
+//! * `reset_not` low clears every flip-flop and presets the first one, selecting position (0, 0)
+//! * a rising edge of `col_clock` shifts the selection to the next column
+//! * a rising edge of `row_clock` shifts the selection to the next row and resets the columns
+//! * `input` is high when the selected switch is pressed, or when `any_not` is low and any switch is pressed
+//!
+//! Shifting past the end of the chain selects nothing, so `input` reads low there.
+//! A chain built with [ChainModel::with_topology] has fewer modules fitted, followed by a terminator
+//! which feeds the selection token back to `input` right after the last fitted row and column.
+//! The boards in `circuit/` have no terminator, it models the hardware of the `chain_terminator` feature.
+use core::{cell::RefCell, convert::Infallible, task::Waker};
+#[cfg(feature = "async_matrix")]
+use core::{future::poll_fn, task::Poll};
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+#[cfg(feature = "async_matrix")]
+use embedded_hal_async::digital::Wait;
+
+use crate::matrix::SequentialMatrixPins;
+
+/// Output signals of the MCU driven into the chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainSignal {
+    RowClock,
+    ColClock,
+    AnyNot,
+    ResetNot,
+}
+
+struct ChainState<const ROW: usize, const COL: usize> {
+    switches: [[bool; COL]; ROW],
+    /// Fitted rows and columns
+    rows: usize,
+    cols: usize,
+    /// Whether a terminator follows the last fitted module
+    terminated: bool,
+    row_clock: bool,
+    col_clock: bool,
+    any_not: bool,
+    reset_not: bool,
+    /// Selected row, at least `rows` when the selection has been shifted out
+    row: usize,
+    /// Selected column, at least `cols` when the selection has been shifted out
+    col: usize,
+    waker: Option<Waker>,
+}
+
+impl<const ROW: usize, const COL: usize> ChainState<ROW, COL> {
+    fn output(&self) -> bool {
+        if !self.any_not && self.any_fitted_pressed() {
+            return true;
+        }
+        if self.row < self.rows && self.col < self.cols {
+            return self.switches[self.row][self.col];
+        }
+        self.terminated
+            && ((self.row < self.rows && self.col == self.cols) || (self.row == self.rows && self.col == 0))
+    }
+
+    fn any_fitted_pressed(&self) -> bool {
+        self.switches[..self.rows]
+            .iter()
+            .any(|row| row[..self.cols].iter().any(|pressed| *pressed))
+    }
+
+    fn drive(&mut self, signal: ChainSignal, level: bool) {
+        match signal {
+            ChainSignal::RowClock => {
+                if !self.row_clock && level && self.reset_not {
+                    self.row = (self.row + 1).min(ROW + 1);
+                    self.col = 0;
+                }
+                self.row_clock = level;
+            }
+            ChainSignal::ColClock => {
+                if !self.col_clock && level && self.reset_not {
+                    self.col = (self.col + 1).min(COL + 1);
+                }
+                self.col_clock = level;
+            }
+            ChainSignal::AnyNot => self.any_not = level,
+            ChainSignal::ResetNot => self.reset_not = level,
+        }
+        if !self.reset_not {
+            self.row = 0;
+            self.col = 0;
+        }
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Simulated chain of `ROW` x `COL` switches.
+///
+/// Pins returned from [ChainModel::pins] borrow the model, so a test can keep pressing and releasing switches while the matrix is scanning.
+pub struct ChainModel<const ROW: usize, const COL: usize> {
+    state: RefCell<ChainState<ROW, COL>>,
+}
+
+impl<const ROW: usize, const COL: usize> ChainModel<ROW, COL> {
+    /// Chain with all ROW x COL positions fitted and no terminator
+    pub const fn new() -> Self {
+        Self::build(ROW, COL, false)
+    }
+
+    /// Chain with only `rows` x `cols` positions fitted, followed by a terminator
+    pub const fn with_topology(rows: usize, cols: usize) -> Self {
+        assert!(rows <= ROW && cols <= COL);
+        Self::build(rows, cols, true)
+    }
+
+    const fn build(rows: usize, cols: usize, terminated: bool) -> Self {
+        Self {
+            state: RefCell::new(ChainState {
+                switches: [[false; COL]; ROW],
+                rows,
+                cols,
+                terminated,
+                row_clock: false,
+                col_clock: false,
+                any_not: true,
+                reset_not: true,
+                row: ROW,
+                col: COL,
+                waker: None,
+            }),
+        }
+    }
+
+    /// Create the pins of the chain, in the form the matrix expects
+    pub fn pins(&self) -> SequentialMatrixPins<SimInputPin<'_, ROW, COL>, SimOutputPin<'_, ROW, COL>> {
+        SequentialMatrixPins::new(
+            SimOutputPin::new(self, ChainSignal::RowClock),
+            SimOutputPin::new(self, ChainSignal::ColClock),
+            SimOutputPin::new(self, ChainSignal::AnyNot),
+            SimOutputPin::new(self, ChainSignal::ResetNot),
+            SimInputPin::new(self),
+        )
+    }
+
+    /// Set the state of the switch at chain position (row, col).
+    /// Switches outside the fitted modules are accepted but never read.
+    pub fn set_switch(&self, row: usize, col: usize, pressed: bool) {
+        let mut state = self.state.borrow_mut();
+        state.switches[row][col] = pressed;
+        state.wake();
+    }
+
+    pub fn press(&self, row: usize, col: usize) {
+        self.set_switch(row, col, true);
+    }
+
+    pub fn release(&self, row: usize, col: usize) {
+        self.set_switch(row, col, false);
+    }
+
+    pub fn is_pressed(&self, row: usize, col: usize) -> bool {
+        self.state.borrow().switches[row][col]
+    }
+
+    /// Chain position currently selected by the flip-flops, if any
+    pub fn selected(&self) -> Option<(usize, usize)> {
+        let state = self.state.borrow();
+        (state.row < state.rows && state.col < state.cols).then_some((state.row, state.col))
+    }
+
+    /// Level of the `input` line
+    pub fn output(&self) -> bool {
+        self.state.borrow().output()
+    }
+
+    fn drive(&self, signal: ChainSignal, level: bool) {
+        self.state.borrow_mut().drive(signal, level);
+    }
+
+    #[cfg(feature = "async_matrix")]
+    async fn wait_for_level(&self, level: bool) {
+        poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            if state.output() == level {
+                Poll::Ready(())
+            } else {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl<const ROW: usize, const COL: usize> Default for ChainModel<ROW, COL> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Output pin of the MCU, connected to one of the chain's control signals
+pub struct SimOutputPin<'a, const ROW: usize, const COL: usize> {
+    model: &'a ChainModel<ROW, COL>,
+    signal: ChainSignal,
+}
+
+impl<'a, const ROW: usize, const COL: usize> SimOutputPin<'a, ROW, COL> {
+    pub fn new(model: &'a ChainModel<ROW, COL>, signal: ChainSignal) -> Self {
+        Self { model, signal }
+    }
+}
+
+impl<const ROW: usize, const COL: usize> ErrorType for SimOutputPin<'_, ROW, COL> {
+    type Error = Infallible;
+}
+
+impl<const ROW: usize, const COL: usize> OutputPin for SimOutputPin<'_, ROW, COL> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.model.drive(self.signal, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.model.drive(self.signal, true);
+        Ok(())
+    }
+}
+
+/// Input pin of the MCU, connected to the chain's output
+pub struct SimInputPin<'a, const ROW: usize, const COL: usize> {
+    model: &'a ChainModel<ROW, COL>,
+}
+
+impl<'a, const ROW: usize, const COL: usize> SimInputPin<'a, ROW, COL> {
+    pub fn new(model: &'a ChainModel<ROW, COL>) -> Self {
+        Self { model }
+    }
+}
+
+impl<const ROW: usize, const COL: usize> ErrorType for SimInputPin<'_, ROW, COL> {
+    type Error = Infallible;
+}
+
+impl<const ROW: usize, const COL: usize> InputPin for SimInputPin<'_, ROW, COL> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.model.output())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.model.output())
+    }
+}
+
+#[cfg(feature = "async_matrix")]
+impl<const ROW: usize, const COL: usize> Wait for SimInputPin<'_, ROW, COL> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.model.wait_for_level(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.model.wait_for_level(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.model.wait_for_level(false).await;
+        self.model.wait_for_level(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.model.wait_for_level(true).await;
+        self.model.wait_for_level(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        let level = self.model.output();
+        self.model.wait_for_level(!level).await;
+        Ok(())
+    }
+}
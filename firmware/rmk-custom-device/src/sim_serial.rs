@@ -0,0 +1,290 @@
+//! In-memory serial cable between two halves of a split keyboard, for tests of the split link on the host.
+//!
+//! [SimPipe] carries bytes both ways, each end implements `embedded_io_async` [Read] and [Write] like a UART.
+//! [PipeFaults] make the cable misbehave: every byte is delayed by a latency, and may be dropped or corrupted
+//! on the way, at random with a fixed seed so a failing test replays the same way.
+//! [SimPipe::set_connected] unplugs the cable, bytes written meanwhile are lost.
+//!
+//! Reads are cancel safe: no byte is taken from the pipe until `read` returns.
+use core::{cell::RefCell, convert::Infallible, future::poll_fn, task::Poll, task::Waker};
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{ErrorType, Read, Write};
+
+/// Bytes in flight in each direction, a writer waits for room beyond it
+pub const PIPE_CAPACITY: usize = 256;
+
+/// Misbehaviour of the cable, the same both ways
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PipeFaults {
+    /// Time from the write of a byte to it being readable
+    pub latency_us: u32,
+    /// Chance of each byte to be lost, out of 1000
+    pub drop_per_mille: u16,
+    /// Chance of each byte to arrive with flipped bits, out of 1000
+    pub corrupt_per_mille: u16,
+    /// Seed of the random faults
+    pub seed: u32,
+}
+
+impl PipeFaults {
+    pub const NONE: Self = Self {
+        latency_us: 0,
+        drop_per_mille: 0,
+        corrupt_per_mille: 0,
+        seed: 1,
+    };
+
+    pub const fn with_latency_us(self, latency_us: u32) -> Self {
+        Self { latency_us, ..self }
+    }
+
+    pub const fn with_drops(self, drop_per_mille: u16) -> Self {
+        Self {
+            drop_per_mille,
+            ..self
+        }
+    }
+
+    pub const fn with_corruption(self, corrupt_per_mille: u16) -> Self {
+        Self {
+            corrupt_per_mille,
+            ..self
+        }
+    }
+
+    pub const fn with_seed(self, seed: u32) -> Self {
+        Self { seed, ..self }
+    }
+}
+
+impl Default for PipeFaults {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Counters of the faults injected so far, both ways
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PipeStats {
+    /// Bytes written by either end
+    pub written: u32,
+    /// Bytes lost to [PipeFaults::drop_per_mille] or to an unplugged cable
+    pub dropped: u32,
+    pub corrupted: u32,
+}
+
+/// One direction of the cable, a ring of bytes with the time each one can be read
+struct Lane {
+    bytes: [(u8, Instant); PIPE_CAPACITY],
+    head: usize,
+    len: usize,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Lane {
+    const fn new() -> Self {
+        Self {
+            bytes: [(0, Instant::from_ticks(0)); PIPE_CAPACITY],
+            head: 0,
+            len: 0,
+            reader: None,
+            writer: None,
+        }
+    }
+
+    fn push(&mut self, byte: u8, due: Instant) {
+        self.bytes[(self.head + self.len) % PIPE_CAPACITY] = (byte, due);
+        self.len += 1;
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+
+    fn front_due(&self) -> Option<Instant> {
+        (self.len > 0).then(|| self.bytes[self.head].1)
+    }
+
+    /// Take the bytes due by `now` into `buf`, returns how many
+    fn pop_due(&mut self, buf: &mut [u8], now: Instant) -> usize {
+        let mut n = 0;
+        while n < buf.len() && self.len > 0 && self.bytes[self.head].1 <= now {
+            buf[n] = self.bytes[self.head].0;
+            self.head = (self.head + 1) % PIPE_CAPACITY;
+            self.len -= 1;
+            n += 1;
+        }
+        if n > 0 {
+            if let Some(waker) = self.writer.take() {
+                waker.wake();
+            }
+        }
+        n
+    }
+}
+
+struct PipeState {
+    lanes: [Lane; 2],
+    faults: PipeFaults,
+    connected: bool,
+    /// xorshift32 state of the random faults
+    rng: u32,
+    stats: PipeStats,
+}
+
+impl PipeState {
+    fn random_per_mille(&mut self) -> u16 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng % 1000) as u16
+    }
+
+    /// Byte arriving at the other end, if any
+    fn transmit(&mut self, byte: u8) -> Option<u8> {
+        self.stats.written += 1;
+        if !self.connected || self.random_per_mille() < self.faults.drop_per_mille {
+            self.stats.dropped += 1;
+            return None;
+        }
+        if self.random_per_mille() < self.faults.corrupt_per_mille {
+            self.stats.corrupted += 1;
+            // Any bit pattern but zero, so the byte always changes
+            let flips = (self.rng >> 8) as u8 | 1;
+            return Some(byte ^ flips);
+        }
+        Some(byte)
+    }
+}
+
+/// Duplex cable, whose two ends are taken with [SimPipe::ends]
+pub struct SimPipe {
+    state: RefCell<PipeState>,
+}
+
+impl SimPipe {
+    pub const fn new(faults: PipeFaults) -> Self {
+        Self {
+            state: RefCell::new(PipeState {
+                lanes: [Lane::new(), Lane::new()],
+                faults,
+                connected: true,
+                // xorshift never leaves zero
+                rng: if faults.seed == 0 { 1 } else { faults.seed },
+                stats: PipeStats {
+                    written: 0,
+                    dropped: 0,
+                    corrupted: 0,
+                },
+            }),
+        }
+    }
+
+    /// Both ends of the cable, what one writes the other reads
+    pub fn ends(&self) -> (PipeEnd<'_>, PipeEnd<'_>) {
+        (
+            PipeEnd {
+                pipe: self,
+                tx: 0,
+                rx: 1,
+            },
+            PipeEnd {
+                pipe: self,
+                tx: 1,
+                rx: 0,
+            },
+        )
+    }
+
+    /// Change the faults of bytes written from now on, the random sequence goes on
+    pub fn set_faults(&self, faults: PipeFaults) {
+        self.state.borrow_mut().faults = faults;
+    }
+
+    /// Plug or unplug the cable, bytes already in flight still arrive
+    pub fn set_connected(&self, connected: bool) {
+        self.state.borrow_mut().connected = connected;
+    }
+
+    pub fn stats(&self) -> PipeStats {
+        self.state.borrow().stats
+    }
+}
+
+/// End of a [SimPipe], behaving as a UART with buffers of [PIPE_CAPACITY] bytes
+pub struct PipeEnd<'a> {
+    pipe: &'a SimPipe,
+    tx: usize,
+    rx: usize,
+}
+
+impl ErrorType for PipeEnd<'_> {
+    type Error = Infallible;
+}
+
+impl Read for PipeEnd<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let due = poll_fn(|cx| {
+                let mut state = self.pipe.state.borrow_mut();
+                let lane = &mut state.lanes[self.rx];
+                match lane.front_due() {
+                    Some(due) => Poll::Ready(due),
+                    None => {
+                        lane.reader = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                }
+            })
+            .await;
+            if due > Instant::now() {
+                Timer::at(due).await;
+                continue;
+            }
+            let n = self.pipe.state.borrow_mut().lanes[self.rx].pop_due(buf, Instant::now());
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl Write for PipeEnd<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        poll_fn(|cx| {
+            let mut state = self.pipe.state.borrow_mut();
+            let lane = &mut state.lanes[self.tx];
+            if lane.len < PIPE_CAPACITY {
+                Poll::Ready(())
+            } else {
+                lane.writer = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await;
+
+        let mut state = self.pipe.state.borrow_mut();
+        let due = Instant::now() + Duration::from_micros(state.faults.latency_us as u64);
+        let room = PIPE_CAPACITY - state.lanes[self.tx].len;
+        let n = buf.len().min(room);
+        for byte in &buf[..n] {
+            if let Some(byte) = state.transmit(*byte) {
+                state.lanes[self.tx].push(byte, due);
+            }
+        }
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
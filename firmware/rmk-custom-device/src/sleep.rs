@@ -0,0 +1,38 @@
+//! Low-power sleep of the MCU while the keyboard is idle.
+//!
+//! When no key has been pressed for [IdleConfig::sleep_timeout_ms], the matrix parks the chain with `any_not` low,
+//! so any key press raises `input`, and hands over to a [DeepSleep] implementation until then.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IdleConfig {
+    /// How long the matrix keeps scanning after the last key was released, before waiting on `any_not` again
+    pub scan_window_ms: u64,
+    /// How long the matrix waits on `any_not` before putting the MCU into deep sleep, `None` never sleeps
+    pub sleep_timeout_ms: Option<u64>,
+}
+
+impl IdleConfig {
+    pub const DEFAULT: Self = Self {
+        scan_window_ms: 1,
+        sleep_timeout_ms: None,
+    };
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Chip-specific low-power state, such as the RP2040's clock-gated sleep in `rp_sleep` or nRF system-off,
+/// entered with the chain parked and left on the rising edge of `input`
+pub trait DeepSleep {
+    /// Lower the power state before the matrix waits on `input`.
+    ///
+    /// Implementations which power off and wake up through a reset, like nRF system-off, do not return.
+    /// The key that woke them up is then still held when the matrix starts scanning after boot.
+    fn enter(&mut self);
+    /// Restore the power state after `input` has risen
+    fn exit(&mut self);
+}
@@ -0,0 +1,326 @@
+//! Placement of the halves of a split keyboard in the keymap.
+//!
+//! [SplitTopology] is built in a `const`, so a misplaced half fails the build, as does a keymap position
+//! no half holds, unless it is listed as unused:
+//!
+//! ```ignore
+//! const SPLIT: SplitTopology<2> = SplitTopology::new(4, 6)
+//!     .peripheral(4, 6)
+//!     .peripheral_at(1, 10, 4, 0)
+//!     .check(5, 12, &[(4, 10), (4, 11)]);
+//! ```
+//!
+//! The const generics of the runners are then taken from it, e.g. `{ SPLIT.peripherals[0].rows }`.
+use crate::remap::KeyRemap;
+
+/// Chain size of a half and its position in the keymap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HalfPlacement {
+    pub rows: usize,
+    pub cols: usize,
+    pub row_offset: usize,
+    pub col_offset: usize,
+}
+
+impl HalfPlacement {
+    const EMPTY: Self = Self {
+        rows: 0,
+        cols: 0,
+        row_offset: 0,
+        col_offset: 0,
+    };
+
+    /// Placement right of `self`, sharing its first row
+    const fn next(&self, rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            row_offset: self.row_offset,
+            col_offset: self.col_offset + self.cols,
+        }
+    }
+
+    const fn fits_in(&self, rows: usize, cols: usize) -> bool {
+        self.rows > 0
+            && self.cols > 0
+            && self.row_offset + self.rows <= rows
+            && self.col_offset + self.cols <= cols
+    }
+
+    pub const fn contains(&self, row: usize, col: usize) -> bool {
+        row >= self.row_offset
+            && row < self.row_offset + self.rows
+            && col >= self.col_offset
+            && col < self.col_offset + self.cols
+    }
+
+    const fn overlaps(&self, other: &Self) -> bool {
+        self.row_offset < other.row_offset + other.rows
+            && other.row_offset < self.row_offset + self.rows
+            && self.col_offset < other.col_offset + other.cols
+            && other.col_offset < self.col_offset + self.cols
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Half {
+    Central,
+    /// Peripheral of the given id
+    Peripheral(usize),
+}
+
+/// Central and `N` peripherals, peripheral `i` is the one monitored with id `i`
+#[derive(Clone, Copy, Debug)]
+pub struct SplitTopology<const N: usize> {
+    pub central: HalfPlacement,
+    pub peripherals: [HalfPlacement; N],
+    /// Number of peripherals placed so far
+    placed: usize,
+}
+
+impl<const N: usize> SplitTopology<N> {
+    /// Central with a `rows` x `cols` chain at (0, 0), peripherals are added in order
+    pub const fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            central: HalfPlacement {
+                rows,
+                cols,
+                row_offset: 0,
+                col_offset: 0,
+            },
+            peripherals: [HalfPlacement::EMPTY; N],
+            placed: 0,
+        }
+    }
+
+    pub const fn central_at(mut self, row_offset: usize, col_offset: usize) -> Self {
+        self.central.row_offset = row_offset;
+        self.central.col_offset = col_offset;
+        self
+    }
+
+    /// Add a peripheral with a `rows` x `cols` chain, right of the previous half
+    pub const fn peripheral(self, rows: usize, cols: usize) -> Self {
+        let placement = match self.placed {
+            0 => self.central.next(rows, cols),
+            i => self.peripherals[i - 1].next(rows, cols),
+        };
+        self.place(placement)
+    }
+
+    /// Add a peripheral with a `rows` x `cols` chain at (row_offset, col_offset)
+    pub const fn peripheral_at(
+        self,
+        rows: usize,
+        cols: usize,
+        row_offset: usize,
+        col_offset: usize,
+    ) -> Self {
+        self.place(HalfPlacement {
+            rows,
+            cols,
+            row_offset,
+            col_offset,
+        })
+    }
+
+    const fn place(mut self, placement: HalfPlacement) -> Self {
+        assert!(
+            self.placed < N,
+            "More peripherals placed than the split topology holds"
+        );
+        self.peripherals[self.placed] = placement;
+        self.placed += 1;
+        self
+    }
+
+    /// Check that every peripheral is placed, that the halves fit in a `rows` x `cols` keymap without overlapping,
+    /// and that they hold every position of it but the `unused` ones, which must be `No` on every layer
+    pub const fn check(self, rows: usize, cols: usize, unused: &[(usize, usize)]) -> Self {
+        assert!(
+            self.placed == N,
+            "Fewer peripherals placed than the split topology holds"
+        );
+        assert!(
+            self.central.fits_in(rows, cols),
+            "Central does not fit in the keymap"
+        );
+        let mut i = 0;
+        while i < N {
+            assert!(
+                self.peripherals[i].fits_in(rows, cols),
+                "Peripheral does not fit in the keymap"
+            );
+            assert!(
+                !self.peripherals[i].overlaps(&self.central),
+                "Peripheral overlaps the central"
+            );
+            let mut j = 0;
+            while j < i {
+                assert!(
+                    !self.peripherals[i].overlaps(&self.peripherals[j]),
+                    "Peripherals overlap"
+                );
+                j += 1;
+            }
+            i += 1;
+        }
+        let mut row = 0;
+        while row < rows {
+            let mut col = 0;
+            while col < cols {
+                if self.holds(row, col) {
+                    assert!(
+                        !Self::listed(unused, row, col),
+                        "Keymap position listed as unused is held by a half"
+                    );
+                } else {
+                    assert!(
+                        Self::listed(unused, row, col),
+                        "Keymap position held by no half, list it as unused if it is No on every layer"
+                    );
+                }
+                col += 1;
+            }
+            row += 1;
+        }
+        self
+    }
+
+    const fn holds(&self, row: usize, col: usize) -> bool {
+        if self.central.contains(row, col) {
+            return true;
+        }
+        let mut i = 0;
+        while i < N {
+            if self.peripherals[i].contains(row, col) {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    const fn listed(positions: &[(usize, usize)], row: usize, col: usize) -> bool {
+        let mut i = 0;
+        while i < positions.len() {
+            if positions[i].0 == row && positions[i].1 == col {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// Half holding keymap position (row, col)
+    pub fn half_of(&self, row: usize, col: usize) -> Option<Half> {
+        if self.central.contains(row, col) {
+            return Some(Half::Central);
+        }
+        self.peripherals
+            .iter()
+            .position(|half| half.contains(row, col))
+            .map(Half::Peripheral)
+    }
+}
+
+/// Direction in which the peripheral's modules continue the central's chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExtendAlong {
+    /// Rows past the central's are the peripheral's
+    Rows,
+    /// Columns past the central's are the peripheral's
+    Cols,
+}
+
+/// Remap of a single chain clocked through both halves, for split keyboards without a serial link.
+///
+/// The chain cable carries the chain signals to the peripheral's modules, so the central scans both halves
+/// and the peripheral needs no MCU. The chain is [Self::chain_rows] x [Self::chain_cols], positions past the
+/// central's map into the peripheral's placement.
+#[derive(Clone, Copy, Debug)]
+pub struct ChainExtension {
+    pub central: HalfPlacement,
+    pub peripheral: HalfPlacement,
+    pub along: ExtendAlong,
+}
+
+impl ChainExtension {
+    pub const fn new(
+        central: HalfPlacement,
+        peripheral: HalfPlacement,
+        along: ExtendAlong,
+    ) -> Self {
+        Self {
+            central,
+            peripheral,
+            along,
+        }
+    }
+
+    pub const fn chain_rows(&self) -> usize {
+        match self.along {
+            ExtendAlong::Rows => self.central.rows + self.peripheral.rows,
+            ExtendAlong::Cols => max(self.central.rows, self.peripheral.rows),
+        }
+    }
+
+    pub const fn chain_cols(&self) -> usize {
+        match self.along {
+            ExtendAlong::Rows => max(self.central.cols, self.peripheral.cols),
+            ExtendAlong::Cols => self.central.cols + self.peripheral.cols,
+        }
+    }
+
+    /// Chain position where the peripheral's modules start
+    const fn peripheral_start(&self) -> (usize, usize) {
+        match self.along {
+            ExtendAlong::Rows => (self.central.rows, 0),
+            ExtendAlong::Cols => (0, self.central.cols),
+        }
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+impl KeyRemap for ChainExtension {
+    fn to_logical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let in_peripheral = match self.along {
+            ExtendAlong::Rows => row >= self.central.rows,
+            ExtendAlong::Cols => col >= self.central.cols,
+        };
+        let (half, row, col) = if in_peripheral {
+            let (start_row, start_col) = self.peripheral_start();
+            (&self.peripheral, row - start_row, col - start_col)
+        } else {
+            (&self.central, row, col)
+        };
+        if row >= half.rows || col >= half.cols {
+            return None;
+        }
+        Some((row + half.row_offset, col + half.col_offset))
+    }
+
+    fn to_physical(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        let (start_row, start_col) = self.peripheral_start();
+        if self.central.contains(row, col) {
+            Some((row - self.central.row_offset, col - self.central.col_offset))
+        } else if self.peripheral.contains(row, col) {
+            Some((
+                row - self.peripheral.row_offset + start_row,
+                col - self.peripheral.col_offset + start_col,
+            ))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Timing profile of the signals driving the flip-flop chain.
+
+use embassy_time::Timer;
+
+/// How delays shorter than a scan are waited out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DelayMode {
+    /// Await embassy timers. The delay is rounded up to the timer tick, so it is usually much longer than requested.
+    Timer,
+    /// Busy-wait for the number of CPU cycles the delay takes at `cpu_hz`, blocking the executor meanwhile
+    Cycles { cpu_hz: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChainTiming {
+    /// High time of `row_clock` and `col_clock`
+    pub clock_high_ns: u32,
+    /// Low time of `row_clock` and `col_clock`
+    pub clock_low_ns: u32,
+    /// Width of the `reset_not` pulse
+    pub reset_pulse_ns: u32,
+    /// Minimum time from a clock edge, a reset or an `any_not` change to sampling `input`
+    pub settle_ns: u32,
+    /// Gap between the end of a scan and the start of the next one
+    pub scan_gap_us: u32,
+    pub delay: DelayMode,
+}
+
+impl ChainTiming {
+    pub const DEFAULT: Self = Self {
+        clock_high_ns: 50,
+        clock_low_ns: 50,
+        reset_pulse_ns: 50,
+        settle_ns: 50,
+        scan_gap_us: 100,
+        delay: DelayMode::Timer,
+    };
+
+    /// Profile clocking the chain at `clock_hz` with 50% duty, other parameters from [Self::DEFAULT].
+    /// Panics unless `clock_hz` is within 1 Hz to 500 MHz, at compile time when used in a constant.
+    pub const fn from_clock_hz(clock_hz: u32) -> Self {
+        assert!(
+            clock_hz > 0 && clock_hz <= 500_000_000,
+            "Chain clock must be within 1 Hz to 500 MHz"
+        );
+        let half_period_ns = 500_000_000 / clock_hz;
+        Self {
+            clock_high_ns: half_period_ns,
+            clock_low_ns: half_period_ns,
+            reset_pulse_ns: half_period_ns,
+            settle_ns: half_period_ns,
+            ..Self::DEFAULT
+        }
+    }
+
+    pub const fn with_delay(self, delay: DelayMode) -> Self {
+        Self { delay, ..self }
+    }
+
+    pub const fn with_scan_gap_us(self, scan_gap_us: u32) -> Self {
+        Self { scan_gap_us, ..self }
+    }
+
+    /// Rate of the clocks, each period shifts the selection by one position. A zero period counts as 1 ns.
+    pub const fn clock_hz(&self) -> u32 {
+        let period_ns = self.clock_high_ns.saturating_add(self.clock_low_ns);
+        1_000_000_000 / if period_ns == 0 { 1 } else { period_ns }
+    }
+
+    /// Multiply every sub-scan delay by `num / den`, keeping the scan gap
+    pub const fn scaled(self, num: u32, den: u32) -> Self {
+        Self {
+            clock_high_ns: self.clock_high_ns * num / den,
+            clock_low_ns: self.clock_low_ns * num / den,
+            reset_pulse_ns: self.reset_pulse_ns * num / den,
+            settle_ns: self.settle_ns * num / den,
+            ..self
+        }
+    }
+
+    pub(crate) async fn delay_ns(&self, ns: u32) {
+        match self.delay {
+            DelayMode::Timer => Timer::after_nanos(ns as u64).await,
+            DelayMode::Cycles { cpu_hz } => {
+                let cycles = (ns as u64 * cpu_hz as u64).div_ceil(1_000_000_000) as u32;
+                delay_cycles(cycles);
+            }
+        }
+    }
+}
+
+impl Default for ChainTiming {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(feature = "cortex-m")]
+fn delay_cycles(cycles: u32) {
+    cortex_m::asm::delay(cycles);
+}
+
+/// Without a cycle counter each spin is assumed to take one cycle, so this only bounds the delay from below
+#[cfg(not(feature = "cortex-m"))]
+fn delay_cycles(cycles: u32) {
+    for _ in 0..cycles {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,285 @@
+//! Timestamped log of every drive and read of the chain pins, to compare against the logic simulation.
+//!
+//! [SequentialMatrixPins::traced](crate::matrix::SequentialMatrixPins::traced) wraps the pins so that each
+//! `set_high`, `set_low` and read of `input` is handed to a [TraceSink] as a [PinEvent].
+//! On the firmware, [LogSink] streams the events through defmt over RTT, one `PINTRACE` line each,
+//! and the `chain-trace` host tool turns the captured log into VCD for GTKWave with [write_vcd].
+//! The pins of the host [ChainModel](crate::sim::ChainModel) can be traced the same way, to keep golden waveforms.
+//!
+//! Tracing costs a log line per edge, which slows the scan down by orders of magnitude.
+use core::{cell::RefCell, fmt};
+
+use embassy_time::Instant;
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+#[cfg(feature = "async_matrix")]
+use embedded_hal_async::digital::Wait;
+
+/// Chain signals, named after the nets of the KeyboardBase schematic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TraceSignal {
+    RowClock,
+    ColClock,
+    AnyNot,
+    ResetNot,
+    /// Chain output read by the MCU
+    Input,
+}
+
+impl TraceSignal {
+    pub const ALL: [TraceSignal; 5] = [
+        TraceSignal::RowClock,
+        TraceSignal::ColClock,
+        TraceSignal::AnyNot,
+        TraceSignal::ResetNot,
+        TraceSignal::Input,
+    ];
+
+    /// Net name in the schematic, overbars written with `~`
+    pub const fn name(&self) -> &'static str {
+        match self {
+            TraceSignal::RowClock => "CLK_ROW",
+            TraceSignal::ColClock => "CLK_COL",
+            TraceSignal::AnyNot => "~Any",
+            TraceSignal::ResetNot => "~Reset",
+            TraceSignal::Input => "Out",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|signal| signal.name() == name)
+    }
+}
+
+/// Level driven on an output, or read from `input`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PinEvent {
+    pub time_us: u64,
+    pub signal: TraceSignal,
+    pub level: bool,
+}
+
+impl PinEvent {
+    /// Marks the lines of [LogSink]
+    pub const LOG_TAG: &'static str = "PINTRACE";
+
+    /// Event of a [LogSink] line, anything before the tag is skipped, e.g. the timestamp and level of the logger
+    pub fn parse_log_line(line: &str) -> Option<Self> {
+        let (_, rest) = line.split_once(Self::LOG_TAG)?;
+        let mut fields = rest.split_whitespace();
+        let time_us = fields.next()?.parse().ok()?;
+        let signal = TraceSignal::from_name(fields.next()?)?;
+        let level = match fields.next()? {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        Some(Self {
+            time_us,
+            signal,
+            level,
+        })
+    }
+}
+
+pub trait TraceSink {
+    fn record(&mut self, event: PinEvent);
+}
+
+impl<F: FnMut(PinEvent)> TraceSink for F {
+    fn record(&mut self, event: PinEvent) {
+        self(event)
+    }
+}
+
+/// Streams events through the log, defmt on the firmware, as `PINTRACE <time_us> <signal> <level>` lines
+pub struct LogSink;
+
+impl TraceSink for LogSink {
+    fn record(&mut self, event: PinEvent) {
+        info!(
+            "PINTRACE {} {} {}",
+            event.time_us,
+            event.signal.name(),
+            event.level as u8
+        );
+    }
+}
+
+/// Timestamps events and hands them to its sink, shared by the traced pins of a chain
+pub struct PinTracer<S: TraceSink> {
+    sink: RefCell<S>,
+}
+
+impl<S: TraceSink> PinTracer<S> {
+    pub const fn new(sink: S) -> Self {
+        Self {
+            sink: RefCell::new(sink),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink.into_inner()
+    }
+
+    fn record(&self, signal: TraceSignal, level: bool) {
+        let event = PinEvent {
+            time_us: Instant::now().as_micros(),
+            signal,
+            level,
+        };
+        self.sink.borrow_mut().record(event);
+    }
+}
+
+/// Output pin recording every level it drives
+pub struct TracedOutputPin<'a, P, S: TraceSink> {
+    pin: P,
+    signal: TraceSignal,
+    tracer: &'a PinTracer<S>,
+}
+
+impl<'a, P, S: TraceSink> TracedOutputPin<'a, P, S> {
+    pub fn new(pin: P, signal: TraceSignal, tracer: &'a PinTracer<S>) -> Self {
+        Self {
+            pin,
+            signal,
+            tracer,
+        }
+    }
+}
+
+impl<P: ErrorType, S: TraceSink> ErrorType for TracedOutputPin<'_, P, S> {
+    type Error = P::Error;
+}
+
+impl<P: OutputPin, S: TraceSink> OutputPin for TracedOutputPin<'_, P, S> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()?;
+        self.tracer.record(self.signal, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()?;
+        self.tracer.record(self.signal, true);
+        Ok(())
+    }
+}
+
+/// Input pin recording every level it reads, and the level it finds at the end of a wait
+pub struct TracedInputPin<'a, P, S: TraceSink> {
+    pin: P,
+    tracer: &'a PinTracer<S>,
+}
+
+impl<'a, P, S: TraceSink> TracedInputPin<'a, P, S> {
+    pub fn new(pin: P, tracer: &'a PinTracer<S>) -> Self {
+        Self { pin, tracer }
+    }
+}
+
+impl<P: ErrorType, S: TraceSink> ErrorType for TracedInputPin<'_, P, S> {
+    type Error = P::Error;
+}
+
+impl<P: InputPin, S: TraceSink> InputPin for TracedInputPin<'_, P, S> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        let level = self.pin.is_high()?;
+        self.tracer.record(TraceSignal::Input, level);
+        Ok(level)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+#[cfg(feature = "async_matrix")]
+impl<P: Wait + InputPin, S: TraceSink> Wait for TracedInputPin<'_, P, S> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_high().await?;
+        self.tracer.record(TraceSignal::Input, true);
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_low().await?;
+        self.tracer.record(TraceSignal::Input, false);
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_rising_edge().await?;
+        self.tracer.record(TraceSignal::Input, true);
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_falling_edge().await?;
+        self.tracer.record(TraceSignal::Input, false);
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_any_edge().await?;
+        self.is_high()?;
+        Ok(())
+    }
+}
+
+/// Write `events` as a VCD file.
+///
+/// The timescale is 1 ns, but events come with µs timestamps, and a whole scan may fall within one µs.
+/// Events sharing a timestamp are spread 1 ns apart, in the order they happened, so no pulse is lost.
+/// Only changes of the outputs are written, while every read of `Out` is also marked on the `Out_read` event variable.
+pub fn write_vcd<W: fmt::Write>(
+    out: &mut W,
+    events: impl IntoIterator<Item = PinEvent>,
+) -> fmt::Result {
+    /// VCD identifier of a signal, printable ASCII from `!`
+    fn id(signal: TraceSignal) -> char {
+        (b'!' + signal as u8) as char
+    }
+    let read_id = (b'!' + TraceSignal::ALL.len() as u8) as char;
+
+    writeln!(out, "$version rmk-custom-device pin trace $end")?;
+    writeln!(out, "$timescale 1ns $end")?;
+    writeln!(out, "$scope module chain $end")?;
+    for signal in TraceSignal::ALL {
+        writeln!(out, "$var wire 1 {} {} $end", id(signal), signal.name())?;
+    }
+    writeln!(out, "$var event 1 {} Out_read $end", read_id)?;
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$enddefinitions $end")?;
+    writeln!(out, "$dumpvars")?;
+    for signal in TraceSignal::ALL {
+        writeln!(out, "x{}", id(signal))?;
+    }
+    writeln!(out, "$end")?;
+
+    let mut levels = [None; TraceSignal::ALL.len()];
+    let mut last_ns = None;
+    for event in events {
+        let level = &mut levels[event.signal as usize];
+        let changed = *level != Some(event.level);
+        if !changed && event.signal != TraceSignal::Input {
+            continue;
+        }
+        let ns = match last_ns {
+            Some(last) if event.time_us * 1000 <= last => last + 1,
+            _ => event.time_us * 1000,
+        };
+        last_ns = Some(ns);
+        writeln!(out, "#{}", ns)?;
+        if changed {
+            *level = Some(event.level);
+            writeln!(out, "{}{}", event.level as u8, id(event.signal))?;
+        }
+        if event.signal == TraceSignal::Input {
+            writeln!(out, "1{}", read_id)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+//! USB descriptor helpers.
+
+/// Serial number made of `prefix` followed by the lowercase hex digits of `uid`, written to `buf`
+///
+/// Vial finds keyboards whose serial number contains `vial:f64c2b3c`, so keep that in `prefix`.
+/// A per-chip `uid`, such as the unique ID of the flash, tells identical boards apart.
+pub fn serial_number(buf: &'static mut [u8], prefix: &str, uid: &[u8]) -> &'static str {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let len = prefix.len() + uid.len() * 2;
+    assert!(buf.len() >= len, "Serial number buffer is too small");
+
+    buf[..prefix.len()].copy_from_slice(prefix.as_bytes());
+    for (digits, byte) in buf[prefix.len()..len].chunks_exact_mut(2).zip(uid) {
+        digits[0] = HEX[(byte >> 4) as usize];
+        digits[1] = HEX[(byte & 0xf) as usize];
+    }
+    let buf: &'static [u8] = buf;
+    // Only the prefix and ASCII digits were written, so this is valid UTF-8
+    core::str::from_utf8(&buf[..len]).unwrap_or_default()
+}
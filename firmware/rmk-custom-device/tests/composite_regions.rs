@@ -0,0 +1,49 @@
+//! Regions of the children of a [CompositeMatrix], checked when it is built in debug builds.
+use rmk_custom_device::{
+    composite::CompositeMatrix,
+    debounce::RuntimeDebouncer,
+    matrix::SequentialMatrix,
+    remap::Transform,
+    sim::ChainModel,
+};
+
+const ROW: usize = 2;
+const COL: usize = 6;
+/// Size of each chain
+const CHAIN_ROW: usize = 2;
+const CHAIN_COL: usize = 3;
+
+/// Build a composite of two 2x3 chains placed `b_col` columns apart
+fn combine(b_col: usize) {
+    let left = ChainModel::<CHAIN_ROW, CHAIN_COL>::new();
+    let right = ChainModel::<CHAIN_ROW, CHAIN_COL>::new();
+    let _ = CompositeMatrix::<_, _, ROW, COL>::new(
+        SequentialMatrix::<_, _, _, CHAIN_ROW, CHAIN_COL, _>::new(
+            left.pins(),
+            RuntimeDebouncer::<CHAIN_ROW, CHAIN_COL>::new(),
+            Transform::<CHAIN_ROW, CHAIN_COL>::new(),
+        ),
+        SequentialMatrix::<_, _, _, CHAIN_ROW, CHAIN_COL, _>::new(
+            right.pins(),
+            RuntimeDebouncer::<CHAIN_ROW, CHAIN_COL>::new(),
+            Transform::<CHAIN_ROW, CHAIN_COL>::offset(0, b_col),
+        ),
+    );
+}
+
+#[test]
+fn side_by_side() {
+    combine(CHAIN_COL);
+}
+
+#[test]
+#[should_panic(expected = "claimed by both children")]
+fn overlapping() {
+    combine(CHAIN_COL - 1);
+}
+
+#[test]
+#[should_panic(expected = "outside the 2x6 keymap")]
+fn outside() {
+    combine(CHAIN_COL + 1);
+}
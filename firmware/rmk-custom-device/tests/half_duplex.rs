@@ -0,0 +1,175 @@
+//! Collisions on the one-wire split serial, replayed on the receive queue of both halves.
+//!
+//! The wire is modelled as the AND of the bytes sent at once, as with the open-drain pins of `rp_half_duplex`.
+//! The receiver's FIFO holds 4 bytes like the PIO state machine's, bytes arriving while it is full are lost.
+//!
+//! ```sh
+//! cargo test --no-default-features --features sim --test half_duplex
+//! ```
+use core::{
+    future::{poll_fn, Future},
+    pin::pin,
+    task::{Context, Poll},
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    task::Waker,
+};
+
+use embassy_time::{Duration, Instant, MockDriver};
+use rmk_custom_device::half_duplex::{RxFifo, RxQueue, RX_QUEUE_SIZE};
+
+/// Depth of the PIO receive FIFO
+const FIFO_DEPTH: usize = 4;
+/// Byte time at 115200 baud
+const BYTE_TIME: Duration = Duration::from_micros(87);
+
+/// Receive FIFO of the half backing off, filled by the wire
+#[derive(Default)]
+struct Fifo {
+    bytes: RefCell<VecDeque<u8>>,
+    lost: Cell<usize>,
+}
+
+impl Fifo {
+    /// A byte of the other half on the wire, lost when the FIFO is full
+    fn receive(&self, byte: u8) {
+        let mut bytes = self.bytes.borrow_mut();
+        if bytes.len() == FIFO_DEPTH {
+            self.lost.set(self.lost.get() + 1);
+        } else {
+            bytes.push_back(byte);
+        }
+    }
+}
+
+impl RxFifo for &Fifo {
+    fn try_pull(&mut self) -> Option<u8> {
+        self.bytes.borrow_mut().pop_front()
+    }
+
+    async fn wait_pull(&mut self) -> u8 {
+        poll_fn(|_| match self.bytes.borrow_mut().pop_front() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending,
+        })
+        .await
+    }
+}
+
+/// Both halves start a message at once, byte by byte as `PioHalfDuplexUart::send` does.
+/// Returns whether each half's first byte read back as sent
+fn collide(a: &mut RxQueue, a_byte: u8, b: &mut RxQueue, b_byte: u8) -> (bool, bool) {
+    let wire = a_byte & b_byte;
+    (
+        a.check_echo(a_byte, Some(wire)),
+        b.check_echo(b_byte, Some(wire)),
+    )
+}
+
+/// Everything queued, in order
+fn read_all(queue: &mut RxQueue) -> Vec<u8> {
+    let mut buf = [0; RX_QUEUE_SIZE];
+    let n = queue.take(&mut buf);
+    buf[..n].to_vec()
+}
+
+#[test]
+fn loser_keeps_winner_message() {
+    let winner_frame = [0x21, 0x02, 0x03];
+    let loser_frame = [0x63, 0x04, 0x05];
+    let mut winner = RxQueue::new();
+    let mut loser = RxQueue::new();
+
+    let (won, lost) = collide(&mut winner, winner_frame[0], &mut loser, loser_frame[0]);
+    assert!(won, "the byte surviving the AND reads back as sent");
+    assert!(!lost, "the overwritten byte is a collision");
+
+    // The winner goes on alone, the loser backs off and hears the rest of the frame
+    for &byte in &winner_frame[1..] {
+        assert!(winner.check_echo(byte, Some(byte)));
+        assert!(loser.push(byte));
+    }
+
+    assert_eq!(read_all(&mut loser), winner_frame);
+    assert!(winner.is_empty(), "the winner only heard itself");
+}
+
+#[test]
+fn both_lose_when_neither_byte_survives() {
+    let mut a = RxQueue::new();
+    let mut b = RxQueue::new();
+
+    let (a_ok, b_ok) = collide(&mut a, 0x0F, &mut b, 0xF0);
+    assert!(!a_ok && !b_ok);
+    // Both resend, the framing above drops what the wire held
+    assert_eq!(read_all(&mut a), [0x00]);
+    assert_eq!(read_all(&mut b), [0x00]);
+}
+
+#[test]
+fn silent_wire_is_a_failed_send() {
+    let mut queue = RxQueue::new();
+    assert!(!queue.check_echo(0x42, None));
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn partial_reads_keep_order() {
+    let mut queue = RxQueue::new();
+    for byte in 0..5 {
+        assert!(queue.push(byte));
+    }
+    let mut buf = [0; 2];
+    assert_eq!(queue.take(&mut buf), 2);
+    assert_eq!(buf, [0, 1]);
+    assert_eq!(read_all(&mut queue), [2, 3, 4]);
+}
+
+#[test]
+fn full_queue_drops_bytes() {
+    let mut queue = RxQueue::new();
+    for byte in 0..RX_QUEUE_SIZE as u8 {
+        assert!(queue.push(byte));
+    }
+    assert!(!queue.push(0xFF));
+    assert!(!queue.check_echo(0x00, Some(0xFF)));
+    assert_eq!(queue.len(), RX_QUEUE_SIZE);
+}
+
+#[test]
+fn backoff_keeps_receiving() {
+    let frame: Vec<u8> = (1..=12).collect();
+    let fifo = Fifo::default();
+    let mut loser = RxQueue::new();
+    let mut cx = Context::from_waker(Waker::noop());
+
+    // The loser backs off while the winner sends a frame three times the FIFO's depth
+    let deadline = Instant::now() + BYTE_TIME * (frame.len() as u32 + 2);
+    let mut last_byte = None;
+    {
+        let mut rx = &fifo;
+        let mut backoff = pin!(loser.drain_until(&mut rx, deadline));
+        for &byte in &frame {
+            fifo.receive(byte);
+            last_byte = Some(Instant::now());
+            assert!(backoff.as_mut().poll(&mut cx).is_pending());
+            MockDriver::get().advance(BYTE_TIME);
+        }
+        let done = loop {
+            if let Poll::Ready(done) = backoff.as_mut().poll(&mut cx) {
+                break done;
+            }
+            MockDriver::get().advance(BYTE_TIME);
+        };
+        assert!(Instant::now() >= deadline);
+        assert_eq!(
+            done, last_byte,
+            "the time of the last byte, for the quiet wait"
+        );
+    }
+
+    assert_eq!(fifo.lost.get(), 0, "the FIFO never stalled");
+    assert_eq!(read_all(&mut loser), frame);
+}
@@ -0,0 +1,369 @@
+//! Random keystrokes with switch bounce, played on the simulated chain and scanned by [SequentialMatrix].
+//!
+//! Every keystroke must give exactly one press and one release on `KEY_EVENT_CHANNEL`, press first,
+//! each within the debounce threshold of the edge settling plus [LATENCY_SLACK_US].
+//! The suite covers every debouncer of the crate and of RMK, and the polling and `async_matrix` builds of the matrix:
+//!
+//! ```sh
+//! cargo test --no-default-features --features sim
+//! cargo test --no-default-features --features sim,async_matrix
+//! ```
+//!
+//! Time is embassy's mock driver, advanced by the test one scan gap at a time.
+//! The driver, the event channel and the debounce settings are global, so the cases run one at a time.
+use core::{future::Future, pin::pin, task::Context};
+use std::{sync::Mutex, task::Waker};
+
+use embassy_time::{Duration, Instant, MockDriver};
+use proptest::prelude::*;
+use rmk::{
+    debounce::{default_bouncer::DefaultDebouncer, fast_debouncer::RapidDebouncer, DebouncerTrait},
+    keyboard::KEY_EVENT_CHANNEL,
+    matrix::MatrixTrait,
+};
+use rmk_custom_device::{
+    debounce::{set_debounce_settings, DebounceAlgorithm, DebounceSettings, RuntimeDebouncer},
+    matrix::SequentialMatrix,
+    packed_debounce::PackedDebouncer,
+    remap::Identity,
+    sim::ChainModel,
+    timing::{ChainTiming, DelayMode},
+};
+
+/// Square, so RMK's debouncers fit whichever way round they index rows and columns
+const ROW: usize = 4;
+const COL: usize = 4;
+/// Twice the keys, so that every key may be held at once
+const MAX_KEYSTROKES: usize = 2 * ROW * COL;
+/// Sub-scan delays spin a cycle or so, only the scan gap waits on the mock driver
+const TIMING: ChainTiming = ChainTiming::DEFAULT
+    .with_delay(DelayMode::Cycles { cpu_hz: 1 })
+    .with_scan_gap_us(STEP_US as u32);
+/// Time advanced between two polls of the matrix
+const STEP_US: u64 = 100;
+/// Time given to the matrix to probe the chain before the first keystroke
+const WARMUP_US: u64 = 50_000;
+/// Scan period and ms rounding of the debouncers, on top of their threshold
+const LATENCY_SLACK_US: u64 = 2_000;
+/// Longest bounce of an edge, shorter than any threshold below so a bounce never debounces
+const MAX_BOUNCE_US: u64 = 4_000;
+/// Stable time kept between the edges of a key, beyond bounce and thresholds
+const MIN_STABLE_US: u64 = 5_000;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Debug)]
+struct Keystroke {
+    row: usize,
+    col: usize,
+    /// First contact
+    press_us: u64,
+    /// First break of the contact
+    release_us: u64,
+    /// Times after the first contact where the contact toggles, an even number so it ends closed
+    press_bounce: Vec<u64>,
+    /// Times after the first break where the contact toggles, an even number so it ends open
+    release_bounce: Vec<u64>,
+}
+
+impl Keystroke {
+    fn press_settled_us(&self) -> u64 {
+        self.press_us + self.press_bounce.last().copied().unwrap_or(0)
+    }
+
+    fn release_settled_us(&self) -> u64 {
+        self.release_us + self.release_bounce.last().copied().unwrap_or(0)
+    }
+
+    /// Switch changes of the keystroke, as (time, pressed)
+    fn edges(&self) -> impl Iterator<Item = (u64, bool)> + '_ {
+        let press = core::iter::once(0)
+            .chain(self.press_bounce.iter().copied())
+            .enumerate()
+            .map(|(i, t)| (self.press_us + t, i % 2 == 0));
+        let release = core::iter::once(0)
+            .chain(self.release_bounce.iter().copied())
+            .enumerate()
+            .map(|(i, t)| (self.release_us + t, i % 2 == 1));
+        press.chain(release)
+    }
+}
+
+/// Thresholds in µs of the debouncer under test
+#[derive(Clone, Copy, Debug)]
+struct Thresholds {
+    press_us: u64,
+    release_us: u64,
+    /// Presses ignored for this long after a release
+    lockout_us: u64,
+}
+
+impl From<DebounceSettings> for Thresholds {
+    fn from(settings: DebounceSettings) -> Self {
+        let press_us = settings.press_ms as u64 * 1000;
+        let release_us = settings.release_ms as u64 * 1000;
+        match settings.algorithm {
+            DebounceAlgorithm::Defer => Self {
+                press_us,
+                release_us: press_us,
+                lockout_us: 0,
+            },
+            DebounceAlgorithm::EagerPress => Self {
+                press_us: 0,
+                release_us,
+                lockout_us: press_us,
+            },
+            DebounceAlgorithm::Asymmetric => Self {
+                press_us,
+                release_us,
+                lockout_us: 0,
+            },
+        }
+    }
+}
+
+/// Toggle times of a bounce, sorted, of an even number, within [MAX_BOUNCE_US]
+fn bounce(max_toggles: usize) -> impl Strategy<Value = Vec<u64>> {
+    prop::collection::vec(50..MAX_BOUNCE_US, 0..=max_toggles).prop_map(|mut toggles| {
+        toggles.sort_unstable();
+        toggles.dedup();
+        toggles.truncate(toggles.len() & !1);
+        toggles
+    })
+}
+
+/// Keystrokes starting at random gaps from each other, on random keys, with up to `max_toggles` bounces per edge.
+/// Keystrokes on the same key are pushed back until the previous one has settled and debounced.
+fn keystrokes(max_toggles: usize, thresholds: Thresholds) -> impl Strategy<Value = Vec<Keystroke>> {
+    let keystroke = (
+        0..ROW,
+        0..COL,
+        0..60_000u64,
+        0..200_000u64,
+        bounce(max_toggles),
+        bounce(max_toggles),
+    );
+    prop::collection::vec(keystroke, 1..=MAX_KEYSTROKES).prop_map(move |strokes| {
+        let hold_us =
+            MAX_BOUNCE_US + thresholds.press_us.max(thresholds.release_us) + MIN_STABLE_US;
+        let rest_us = MAX_BOUNCE_US + thresholds.release_us + thresholds.lockout_us + MIN_STABLE_US;
+        let mut free_at = [[WARMUP_US; COL]; ROW];
+        let mut start = WARMUP_US;
+        strokes
+            .into_iter()
+            .map(
+                |(row, col, gap_us, extra_hold_us, press_bounce, release_bounce)| {
+                    start += gap_us;
+                    let press_us = start.max(free_at[row][col]);
+                    let release_us = press_us + hold_us + extra_hold_us;
+                    free_at[row][col] = release_us + rest_us;
+                    Keystroke {
+                        row,
+                        col,
+                        press_us,
+                        release_us,
+                        press_bounce,
+                        release_bounce,
+                    }
+                },
+            )
+            .collect()
+    })
+}
+
+/// Play `strokes` on the chain while `debouncer` scans it, and return the events sent, as (time, row, col, pressed)
+fn replay<D: DebouncerTrait>(
+    debouncer: D,
+    strokes: &[Keystroke],
+) -> Vec<(u64, usize, usize, bool)> {
+    let mut edges: Vec<_> = strokes
+        .iter()
+        .flat_map(|stroke| {
+            stroke
+                .edges()
+                .map(|(t, pressed)| (t, stroke.row, stroke.col, pressed))
+        })
+        .collect();
+    edges.sort_by_key(|(t, ..)| *t);
+    let end_us = strokes
+        .iter()
+        .map(|stroke| stroke.release_settled_us())
+        .max()
+        .unwrap_or(0)
+        + 100_000;
+
+    while KEY_EVENT_CHANNEL.try_receive().is_ok() {}
+    let chain = ChainModel::<ROW, COL>::new();
+    let mut matrix = SequentialMatrix::<_, _, _, ROW, COL, _>::new_with_timing(
+        chain.pins(),
+        debouncer,
+        Identity,
+        TIMING,
+    );
+    let mut scan = pin!(matrix.scan());
+    let mut cx = Context::from_waker(Waker::noop());
+
+    let start = Instant::now();
+    let mut events = Vec::new();
+    let mut next_edge = edges.iter().peekable();
+    loop {
+        let now_us = (Instant::now() - start).as_micros();
+        if now_us > end_us {
+            break;
+        }
+        while let Some((_, row, col, pressed)) = next_edge.next_if(|(t, ..)| *t <= now_us) {
+            chain.set_switch(*row, *col, *pressed);
+        }
+        // The second poll picks up what the first left in a full channel
+        for _ in 0..2 {
+            let _ = scan.as_mut().poll(&mut cx);
+            while let Ok(event) = KEY_EVENT_CHANNEL.try_receive() {
+                events.push((
+                    now_us,
+                    event.row as usize,
+                    event.col as usize,
+                    event.pressed,
+                ));
+            }
+        }
+        MockDriver::get().advance(Duration::from_micros(STEP_US));
+    }
+    events
+}
+
+/// Check the events of each key against its keystrokes
+fn check_events(
+    strokes: &[Keystroke],
+    events: &[(u64, usize, usize, bool)],
+    thresholds: Thresholds,
+) {
+    for row in 0..ROW {
+        for col in 0..COL {
+            let key_strokes: Vec<_> = strokes
+                .iter()
+                .filter(|s| (s.row, s.col) == (row, col))
+                .collect();
+            let key_events: Vec<_> = events
+                .iter()
+                .filter(|(_, r, c, _)| (*r, *c) == (row, col))
+                .map(|(t, .., pressed)| (*t, *pressed))
+                .collect();
+            assert_eq!(
+                key_events.len(),
+                2 * key_strokes.len(),
+                "({row}, {col}): events {key_events:?} for keystrokes {key_strokes:?}"
+            );
+            for (stroke, events) in key_strokes.iter().zip(key_events.chunks(2)) {
+                let [(press_t, pressed), (release_t, released)] = [events[0], events[1]];
+                assert!(
+                    pressed && !released,
+                    "({row}, {col}): events out of order {events:?}"
+                );
+                let press_limit =
+                    stroke.press_settled_us() + thresholds.press_us + LATENCY_SLACK_US;
+                assert!(
+                    (stroke.press_us..=press_limit).contains(&press_t),
+                    "({row}, {col}): press at {press_t} µs, expected within {}..={press_limit} for {stroke:?}",
+                    stroke.press_us
+                );
+                let release_limit =
+                    stroke.release_settled_us() + thresholds.release_us + LATENCY_SLACK_US;
+                assert!(
+                    (stroke.release_us..=release_limit).contains(&release_t),
+                    "({row}, {col}): release at {release_t} µs, expected within {}..={release_limit} for {stroke:?}",
+                    stroke.release_us
+                );
+            }
+        }
+    }
+}
+
+fn check<D: DebouncerTrait>(debouncer: D, strokes: &[Keystroke], thresholds: Thresholds) {
+    let events = replay(debouncer, strokes);
+    check_events(strokes, &events, thresholds);
+}
+
+/// Run a case with the runtime debouncer under `settings`
+fn check_runtime(settings: DebounceSettings, strokes: &[Keystroke]) {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    set_debounce_settings(settings);
+    check(
+        RuntimeDebouncer::<ROW, COL>::new(),
+        strokes,
+        settings.into(),
+    );
+}
+
+const DEFER: DebounceSettings = DebounceSettings::DEFAULT;
+const EAGER_PRESS: DebounceSettings = DebounceSettings {
+    algorithm: DebounceAlgorithm::EagerPress,
+    press_ms: 10,
+    release_ms: 10,
+};
+const ASYMMETRIC: DebounceSettings = DebounceSettings {
+    algorithm: DebounceAlgorithm::Asymmetric,
+    press_ms: 5,
+    release_ms: 15,
+};
+/// The packed debouncer sends both edges after the default threshold
+const PACKED: Thresholds = Thresholds {
+    press_us: DebounceSettings::DEFAULT.press_ms as u64 * 1000,
+    release_us: DebounceSettings::DEFAULT.press_ms as u64 * 1000,
+    lockout_us: 0,
+};
+/// Debounce time of RMK's debouncers
+const RMK_DEBOUNCE_US: u64 = 10_000;
+/// RMK's default debouncer sends both edges once they have been stable for its debounce time
+const RMK_DEFAULT: Thresholds = Thresholds {
+    press_us: RMK_DEBOUNCE_US,
+    release_us: RMK_DEBOUNCE_US,
+    lockout_us: 0,
+};
+/// RMK's rapid debouncer sends both edges at once, then ignores the key for its debounce time
+const RMK_RAPID: Thresholds = Thresholds {
+    press_us: 0,
+    release_us: 0,
+    lockout_us: RMK_DEBOUNCE_US,
+};
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn clean_switches(strokes in keystrokes(0, DEFER.into())) {
+        check_runtime(DEFER, &strokes);
+    }
+
+    #[test]
+    fn defer(strokes in keystrokes(8, DEFER.into())) {
+        check_runtime(DEFER, &strokes);
+    }
+
+    #[test]
+    fn eager_press(strokes in keystrokes(8, EAGER_PRESS.into())) {
+        check_runtime(EAGER_PRESS, &strokes);
+    }
+
+    #[test]
+    fn asymmetric(strokes in keystrokes(8, ASYMMETRIC.into())) {
+        check_runtime(ASYMMETRIC, &strokes);
+    }
+
+    #[test]
+    fn packed(strokes in keystrokes(8, PACKED)) {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        check(PackedDebouncer::<ROW, COL>::new(), &strokes, PACKED);
+    }
+
+    #[test]
+    fn rmk_default(strokes in keystrokes(8, RMK_DEFAULT)) {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        check(DefaultDebouncer::<COL, ROW>::new(), &strokes, RMK_DEFAULT);
+    }
+
+    #[test]
+    fn rmk_rapid(strokes in keystrokes(8, RMK_RAPID)) {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        check(RapidDebouncer::<COL, ROW>::new(), &strokes, RMK_RAPID);
+    }
+}
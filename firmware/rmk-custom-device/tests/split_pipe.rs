@@ -0,0 +1,441 @@
+//! Both halves of a split keyboard in one process, joined by a simulated serial cable.
+//!
+//! Each half scans its own [ChainModel] with a [SequentialMatrix], and talks over a [SplitLink] on its end of a
+//! [SimPipe]. The peripheral runs RMK's `initialize_serial_split_peripheral_and_run`, the central runs RMK's
+//! `run_peripheral_monitor`, which merges the peripheral's keys into `KEY_EVENT_CHANNEL` next to the central's own,
+//! and the test checks that merged stream:
+//!
+//! ```sh
+//! cargo test --no-default-features --features sim,split --test split_pipe
+//! cargo test --no-default-features --features sim,split,async_matrix --test split_pipe
+//! ```
+//!
+//! RMK's peripheral takes its keys from `KEY_EVENT_CHANNEL` too, where the central monitor puts the merged ones.
+//! The halves are polled in turn, and each one gets its own [KeyChannel] swapped into `KEY_EVENT_CHANNEL`
+//! while it is polled, so each sees only its own keys as on two boards.
+//!
+//! Time is embassy's mock driver, advanced by the test one scan gap at a time, so the cases run one at a time.
+use core::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll},
+};
+use std::{collections::VecDeque, sync::Mutex, task::Waker};
+
+use embassy_futures::join::join;
+use embassy_time::{Duration, Instant, MockDriver};
+use embedded_io_async::{Read, Write};
+use rmk::{
+    event::KeyEvent,
+    keyboard::KEY_EVENT_CHANNEL,
+    matrix::MatrixTrait,
+    split::{
+        central::run_peripheral_monitor, serial::initialize_serial_split_peripheral_and_run,
+        SplitMessage, SPLIT_MESSAGE_MAX_SIZE,
+    },
+};
+use rmk_custom_device::{
+    debounce::{set_debounce_settings, DebounceSettings, RuntimeDebouncer},
+    link::{link_status, LinkConfig, SplitLink},
+    matrix::SequentialMatrix,
+    remap::{Identity, Transform},
+    sim::ChainModel,
+    sim_serial::{PipeFaults, PipeStats, SimPipe},
+    split::{Half, SplitTopology},
+    timing::{ChainTiming, DelayMode},
+};
+
+const ROW: usize = 2;
+const COL: usize = 3;
+/// Two halves of the same chain side by side
+const SPLIT: SplitTopology<1> =
+    SplitTopology::new(ROW, COL)
+        .peripheral(ROW, COL)
+        .check(ROW, 2 * COL, &[]);
+const PERIPHERAL_ROW_OFFSET: usize = SPLIT.peripherals[0].row_offset;
+const PERIPHERAL_COL_OFFSET: usize = SPLIT.peripherals[0].col_offset;
+/// Counters of the central's link are [link_status] 0, the peripheral's link takes 1 so they stay apart
+const PERIPHERAL_LINK_ID: usize = 1;
+const LINK: LinkConfig = LinkConfig::DEFAULT;
+
+/// Sub-scan delays spin a cycle or so, only the scan gap waits on the mock driver
+const TIMING: ChainTiming = ChainTiming::DEFAULT
+    .with_delay(DelayMode::Cycles { cpu_hz: 1 })
+    .with_scan_gap_us(STEP_US as u32);
+/// Time advanced between two polls of the halves
+const STEP_US: u64 = 100;
+/// Time for the matrices to probe their chains and the link to come up, before the first keystroke
+const WARMUP_US: u64 = 200_000;
+/// Debounce of an edge
+const DEBOUNCE_US: u64 = DebounceSettings::DEFAULT.press_ms as u64 * 1000;
+/// Scan period, ms rounding of the debouncers and transfer time of a frame, on top of debounce and latency
+const LATENCY_SLACK_US: u64 = 3_000;
+/// Time after the last step for the last events to come out
+const TAIL_US: u64 = 100_000;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    /// Switch at chain position (row, col) of a half
+    Switch(Half, usize, usize, bool),
+    /// Plug or unplug the cable
+    Cable(bool),
+}
+
+/// Event of the merged stream, as (time, row, col, pressed) in the keymap
+type Event = (u64, usize, usize, bool);
+
+fn keymap_position(half: Half, row: usize, col: usize) -> (usize, usize) {
+    let placement = match half {
+        Half::Central => SPLIT.central,
+        Half::Peripheral(i) => SPLIT.peripherals[i],
+    };
+    (row + placement.row_offset, col + placement.col_offset)
+}
+
+/// Press at `at_us` and release `hold_us` later
+fn tap(half: Half, row: usize, col: usize, at_us: u64, hold_us: u64) -> [(u64, Step); 2] {
+    [
+        (at_us, Step::Switch(half, row, col, true)),
+        (at_us + hold_us, Step::Switch(half, row, col, false)),
+    ]
+}
+
+/// Events the switches of `script` should give, in order, without their time
+fn expected_events(script: &[(u64, Step)], half: Option<Half>) -> Vec<(usize, usize, bool)> {
+    let mut steps = script.to_vec();
+    steps.sort_by_key(|(t, _)| *t);
+    steps
+        .iter()
+        .filter_map(|(_, step)| match *step {
+            Step::Switch(h, row, col, pressed) if half.is_none_or(|half| half == h) => {
+                let (row, col) = keymap_position(h, row, col);
+                Some((row, col, pressed))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keys of a half waiting in `KEY_EVENT_CHANNEL` while the other half is polled
+#[derive(Default)]
+struct KeyChannel(VecDeque<KeyEvent>);
+
+impl KeyChannel {
+    /// Put the keys of this half into `KEY_EVENT_CHANNEL`, which must be empty, before polling the half
+    fn swap_in(&mut self) {
+        for event in self.0.drain(..) {
+            KEY_EVENT_CHANNEL.try_send(event).unwrap();
+        }
+    }
+
+    /// Take the keys of this half back out of `KEY_EVENT_CHANNEL`, after polling the half
+    fn swap_out(&mut self) {
+        while let Ok(event) = KEY_EVENT_CHANNEL.try_receive() {
+            self.0.push_back(event);
+        }
+    }
+}
+
+/// Play `script` on both halves joined by a cable with `faults`, and return the merged events
+fn run(faults: PipeFaults, script: &[(u64, Step)]) -> (Vec<Event>, PipeStats) {
+    let mut steps = script.to_vec();
+    steps.sort_by_key(|(t, _)| *t);
+    let end_us = steps.last().map_or(0, |(t, _)| *t) + TAIL_US;
+
+    set_debounce_settings(DebounceSettings::DEFAULT);
+    while KEY_EVENT_CHANNEL.try_receive().is_ok() {}
+    let pipe = SimPipe::new(faults);
+    let (central_end, peripheral_end) = pipe.ends();
+    let central_chain = ChainModel::<ROW, COL>::new();
+    let peripheral_chain = ChainModel::<ROW, COL>::new();
+
+    let mut central_matrix = SequentialMatrix::<_, _, _, ROW, COL, _>::new_with_timing(
+        central_chain.pins(),
+        RuntimeDebouncer::<ROW, COL>::new(),
+        Transform::<ROW, COL>::offset(SPLIT.central.row_offset, SPLIT.central.col_offset),
+        TIMING,
+    );
+    let peripheral_matrix = SequentialMatrix::<_, _, _, ROW, COL, _>::new_with_timing(
+        peripheral_chain.pins(),
+        RuntimeDebouncer::<ROW, COL>::new(),
+        Identity,
+        TIMING,
+    );
+    let central_link = SplitLink::new(central_end, 0, Some(SPLIT.peripherals[0])).with_config(LINK);
+    let peripheral_link =
+        SplitLink::new(peripheral_end, PERIPHERAL_LINK_ID, None).with_config(LINK);
+
+    let central = join(
+        central_matrix.scan(),
+        run_peripheral_monitor::<ROW, COL, PERIPHERAL_ROW_OFFSET, PERIPHERAL_COL_OFFSET, _>(
+            0,
+            central_link,
+        ),
+    );
+    let peripheral = initialize_serial_split_peripheral_and_run::<_, _, ROW, COL>(
+        peripheral_matrix,
+        peripheral_link,
+    );
+    let mut central = pin!(central);
+    let mut peripheral = pin!(peripheral);
+    let mut peripheral_keys = KeyChannel::default();
+    let mut merged = Vec::new();
+    let mut cx = Context::from_waker(Waker::noop());
+
+    let start = Instant::now();
+    let mut next_step = steps.iter().peekable();
+    loop {
+        let now_us = (Instant::now() - start).as_micros();
+        if now_us > end_us {
+            break;
+        }
+        while let Some((_, step)) = next_step.next_if(|(t, _)| *t <= now_us) {
+            match *step {
+                Step::Switch(Half::Central, row, col, pressed) => {
+                    central_chain.set_switch(row, col, pressed)
+                }
+                Step::Switch(Half::Peripheral(_), row, col, pressed) => {
+                    peripheral_chain.set_switch(row, col, pressed)
+                }
+                Step::Cable(connected) => pipe.set_connected(connected),
+            }
+        }
+        // The second poll picks up what the first left in a full channel
+        for _ in 0..2 {
+            peripheral_keys.swap_in();
+            let _ = peripheral.as_mut().poll(&mut cx);
+            peripheral_keys.swap_out();
+            // The central's keys go to RMK's keyboard task, here the merged stream
+            let _ = central.as_mut().poll(&mut cx);
+            while let Ok(event) = KEY_EVENT_CHANNEL.try_receive() {
+                let t = (Instant::now() - start).as_micros();
+                merged.push((t, event.row as usize, event.col as usize, event.pressed));
+            }
+        }
+        MockDriver::get().advance(Duration::from_micros(STEP_US));
+    }
+    (merged, pipe.stats())
+}
+
+/// Whether `sub` is `full` with some items left out
+fn is_subsequence<T: PartialEq>(sub: &[T], full: &[T]) -> bool {
+    let mut full = full.iter();
+    sub.iter().all(|item| full.any(|f| f == item))
+}
+
+fn positions(events: &[Event]) -> Vec<(usize, usize, bool)> {
+    events
+        .iter()
+        .map(|(_, row, col, pressed)| (*row, *col, *pressed))
+        .collect()
+}
+
+fn events_of(events: &[Event], half: Half) -> Vec<Event> {
+    events
+        .iter()
+        .copied()
+        .filter(|(_, row, col, _)| SPLIT.half_of(*row, *col) == Some(half))
+        .collect()
+}
+
+/// Overlapping keystrokes on both halves, 30 ms or more between any two edges so the merged order is known
+fn both_halves() -> Vec<(u64, Step)> {
+    let central = Half::Central;
+    let peripheral = Half::Peripheral(0);
+    let t = WARMUP_US;
+    [
+        tap(central, 0, 0, t, 60_000),
+        tap(peripheral, 0, 0, t + 30_000, 90_000),
+        tap(central, 1, 2, t + 150_000, 40_000),
+        tap(peripheral, 1, 2, t + 220_000, 40_000),
+        tap(peripheral, 0, 1, t + 300_000, 120_000),
+        tap(central, 1, 1, t + 330_000, 30_000),
+        tap(peripheral, 1, 0, t + 480_000, 30_000),
+    ]
+    .concat()
+}
+
+#[test]
+fn clean_link() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    const LATENCY_US: u64 = 2_000;
+    let script = both_halves();
+    let (events, stats) = run(PipeFaults::NONE.with_latency_us(LATENCY_US as u32), &script);
+
+    assert_eq!(stats.dropped + stats.corrupted, 0);
+    assert_eq!(positions(&events), expected_events(&script, None));
+    // Every event comes out within debounce, plus the cable for the peripheral's
+    let mut switches: Vec<_> = script
+        .iter()
+        .filter_map(|(t, step)| match *step {
+            Step::Switch(half, ..) => Some((*t, half)),
+            _ => None,
+        })
+        .collect();
+    switches.sort_by_key(|(t, _)| *t);
+    for ((edge_us, half), event) in switches.iter().zip(&events) {
+        let link_us = if *half == Half::Central {
+            0
+        } else {
+            LATENCY_US
+        };
+        let limit = edge_us + DEBOUNCE_US + link_us + LATENCY_SLACK_US;
+        assert!(
+            (*edge_us..=limit).contains(&event.0),
+            "{event:?} for the edge at {edge_us} µs, expected by {limit} µs"
+        );
+    }
+}
+
+#[test]
+fn lossy_link() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let script: Vec<_> = (0..6)
+        .flat_map(|round| {
+            let t = round * 600_000;
+            both_halves()
+                .into_iter()
+                .map(move |(at, step)| (at + t, step))
+        })
+        .collect();
+    let expected_central = expected_events(&script, Some(Half::Central));
+    let expected_peripheral = expected_events(&script, Some(Half::Peripheral(0)));
+
+    for seed in 1..=8 {
+        let errors_before = link_status(0).unwrap().framing_errors;
+        let faults = PipeFaults::NONE
+            .with_latency_us(1_000)
+            .with_drops(5)
+            .with_corruption(5)
+            .with_seed(seed);
+        let (events, stats) = run(faults, &script);
+
+        assert!(
+            stats.dropped > 0 && stats.corrupted > 0,
+            "seed {seed}: {stats:?}"
+        );
+        assert!(link_status(0).unwrap().framing_errors > errors_before);
+        // The central's keys never cross the cable
+        assert_eq!(
+            positions(&events_of(&events, Half::Central)),
+            expected_central,
+            "seed {seed}"
+        );
+        // Frames hit by a fault are lost whole, nothing else is invented or reordered
+        let peripheral = positions(&events_of(&events, Half::Peripheral(0)));
+        assert!(
+            !peripheral.is_empty() && is_subsequence(&peripheral, &expected_peripheral),
+            "seed {seed}: {peripheral:?} is not a part of {expected_peripheral:?}"
+        );
+        assert_eq!(
+            events.len(),
+            expected_central.len() + peripheral.len(),
+            "seed {seed}: events outside the halves {events:?}"
+        );
+    }
+}
+
+#[test]
+fn lost_peripheral() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let peripheral = Half::Peripheral(0);
+    let unplug_us = WARMUP_US + 200_000;
+    let replug_us = unplug_us + 1_000_000;
+    let mut script = vec![
+        (WARMUP_US, Step::Switch(peripheral, 0, 1, true)),
+        (unplug_us, Step::Cable(false)),
+        (replug_us, Step::Cable(true)),
+    ];
+    script.extend(tap(peripheral, 1, 2, replug_us + 300_000, 50_000));
+    let (events, _) = run(PipeFaults::NONE, &script);
+
+    let held = keymap_position(peripheral, 0, 1);
+    let tapped = keymap_position(peripheral, 1, 2);
+    // The press, its release when the peripheral is lost and no other key, then the tap once the cable is back
+    let expected = vec![
+        (held.0, held.1, true),
+        (held.0, held.1, false),
+        (tapped.0, tapped.1, true),
+        (tapped.0, tapped.1, false),
+    ];
+    assert_eq!(positions(&events), expected);
+
+    let (release_us, ..) = events[1];
+    let limit = unplug_us + LINK.timeout_ms * 1000 + LATENCY_SLACK_US;
+    assert!(
+        (unplug_us..=limit).contains(&release_us),
+        "Held key released at {release_us} µs, expected within {unplug_us}..={limit}"
+    );
+}
+
+#[test]
+fn interrupted_release() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    while KEY_EVENT_CHANNEL.try_receive().is_ok() {}
+    let pipe = SimPipe::new(PipeFaults::NONE);
+    let (central_end, peripheral_end) = pipe.ends();
+    let mut link = SplitLink::new(central_end, 0, Some(SPLIT.peripherals[0])).with_config(LINK);
+    let mut peripheral_link =
+        SplitLink::new(peripheral_end, PERIPHERAL_LINK_ID, None).with_config(LINK);
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut rx = [0; SPLIT_MESSAGE_MAX_SIZE];
+
+    // The peripheral sends its keys as RMK does, a message per write, and leaves two of them held
+    let keys = [(0, 1, true), (1, 0, true), (1, 2, true), (1, 0, false)];
+    for (row, col, pressed) in keys {
+        let mut tx = [0; SPLIT_MESSAGE_MAX_SIZE];
+        let event = KeyEvent { row, col, pressed };
+        let message = postcard::to_slice(&SplitMessage::Key(event), &mut tx).unwrap();
+        let _ = pin!(peripheral_link.write_all(message)).poll(&mut cx);
+        let mut read = pin!(link.read(&mut rx));
+        assert!(matches!(read.as_mut().poll(&mut cx), Poll::Ready(Ok(_))));
+    }
+    assert!(link_status(0).unwrap().connected);
+
+    // The link is lost while RMK's key channel is full, so the release stops at the first key
+    pipe.set_connected(false);
+    let mut filler = 0;
+    while KEY_EVENT_CHANNEL
+        .try_send(KeyEvent {
+            row: 0,
+            col: 0,
+            pressed: true,
+        })
+        .is_ok()
+    {
+        filler += 1;
+    }
+    MockDriver::get().advance(Duration::from_millis(LINK.timeout_ms + 1));
+    {
+        // Embassy's timers yield once before expiring
+        let mut read = pin!(link.read(&mut rx));
+        for _ in 0..2 {
+            let _ = read.as_mut().poll(&mut cx);
+        }
+        assert!(!link_status(0).unwrap().connected);
+        // RMK's select drops the read here
+    }
+    for _ in 0..filler {
+        KEY_EVENT_CHANNEL.try_receive().unwrap();
+    }
+
+    // The next read carries on with the release
+    {
+        let mut read = pin!(link.read(&mut rx));
+        let _ = read.as_mut().poll(&mut cx);
+    }
+    let mut released = Vec::new();
+    while let Ok(event) = KEY_EVENT_CHANNEL.try_receive() {
+        assert!(!event.pressed);
+        released.push((event.row as usize, event.col as usize));
+    }
+    assert_eq!(
+        released,
+        [
+            keymap_position(Half::Peripheral(0), 0, 1),
+            keymap_position(Half::Peripheral(0), 1, 2)
+        ]
+    );
+}
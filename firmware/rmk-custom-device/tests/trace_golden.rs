@@ -0,0 +1,79 @@
+//! Waveform of the boot and first scans of a small chain, compared with a golden VCD.
+//!
+//! A change of the matrix' pin sequence shows up as a diff of the VCD files, which GTKWave can display side by side
+//! with the logic simulation. After an intended change, rewrite the golden files with `UPDATE_GOLDEN=1`:
+//!
+//! ```sh
+//! UPDATE_GOLDEN=1 cargo test --no-default-features --features sim --test trace_golden
+//! UPDATE_GOLDEN=1 cargo test --no-default-features --features sim,async_matrix --test trace_golden
+//! ```
+use core::{future::Future, pin::pin, task::Context};
+use std::{cell::RefCell, env, fs, path::Path, task::Waker};
+
+use embassy_time::{Duration, Instant, MockDriver};
+use rmk::matrix::MatrixTrait;
+use rmk_custom_device::{
+    debounce::RuntimeDebouncer,
+    matrix::SequentialMatrix,
+    remap::Identity,
+    sim::ChainModel,
+    timing::{ChainTiming, DelayMode},
+    trace::{write_vcd, PinTracer},
+};
+
+const ROW: usize = 2;
+const COL: usize = 3;
+const STEP_US: u64 = 100;
+/// Sub-scan delays spin a cycle or so, only the scan gap waits on the mock driver
+const TIMING: ChainTiming = ChainTiming::DEFAULT
+    .with_delay(DelayMode::Cycles { cpu_hz: 1 })
+    .with_scan_gap_us(STEP_US as u32);
+/// Chain position pressed, and when
+const KEY: (usize, usize) = (1, 2);
+const PRESS_US: u64 = 200;
+const END_US: u64 = 500;
+
+#[cfg(not(feature = "async_matrix"))]
+const GOLDEN: &str = "tests/golden/scan_polling.vcd";
+#[cfg(feature = "async_matrix")]
+const GOLDEN: &str = "tests/golden/scan_async.vcd";
+
+#[test]
+#[cfg_attr(
+    feature = "chain_terminator",
+    ignore = "golden waveforms are recorded without the topology probe"
+)]
+fn scan_waveform() {
+    let events = RefCell::new(Vec::new());
+    let tracer = PinTracer::new(|event| events.borrow_mut().push(event));
+    let chain = ChainModel::<ROW, COL>::new();
+    let mut matrix = SequentialMatrix::<_, _, _, ROW, COL, _>::new_with_timing(
+        chain.pins().traced(&tracer),
+        RuntimeDebouncer::<ROW, COL>::new(),
+        Identity,
+        TIMING,
+    );
+    let mut scan = pin!(matrix.scan());
+    let mut cx = Context::from_waker(Waker::noop());
+
+    let start = Instant::now();
+    while (Instant::now() - start).as_micros() <= END_US {
+        if (Instant::now() - start).as_micros() >= PRESS_US {
+            chain.press(KEY.0, KEY.1);
+        }
+        let _ = scan.as_mut().poll(&mut cx);
+        MockDriver::get().advance(Duration::from_micros(STEP_US));
+    }
+
+    let mut vcd = String::new();
+    write_vcd(&mut vcd, events.take()).unwrap();
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &vcd).unwrap();
+    }
+    let expected = fs::read_to_string(&golden).unwrap();
+    assert!(
+        vcd == expected,
+        "Pin waveform differs from {GOLDEN}, rerun with UPDATE_GOLDEN=1 if the change is intended"
+    );
+}
@@ -1,39 +1,49 @@
 #![no_main]
 #![no_std]
 
-#[macro_use]
-mod keymap;
-#[macro_use]
-mod macros;
-mod vial;
+// Keymap, USB identity and chain pins, generated from keyboard.toml
+rmk_chain_keyboard::board!();
 
-mod custom;
-use custom::monolithic::run_rmk_with_async_flash;
-use rmk_custom_device::matrix::SequentialMatrixPins;
+use crate::keymap::{COL, ROW};
+use rmk_chain_keyboard::rp::unique_serial_number;
+#[cfg(feature = "async_matrix")]
+use rmk_chain_keyboard::rp::clock_gated_sleep;
+use rmk_chain_keyboard::{ChainKeyboard, StorageLayout};
+#[cfg(feature = "async_matrix")]
+use rmk_custom_device::{rp_sleep::RpClockGatedSleep, sleep::IdleConfig};
 
 use defmt::*;
 use defmt_rtt as _;
 use embassy_executor::Spawner;
 use embassy_rp::{
     bind_interrupts,
-    flash::{Async, Flash},
-    gpio::{AnyPin, Input, Output},
-    peripherals::USB,
+    flash::{Async, Flash, ERASE_SIZE},
+    peripherals::{self, USB},
     usb::{Driver, InterruptHandler},
 };
-// use embassy_rp::flash::Blocking;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use panic_probe as _;
-use rmk::config::{KeyboardUsbConfig, RmkConfig, VialConfig};
-use vial::{VIAL_KEYBOARD_DEF, VIAL_KEYBOARD_ID};
+use static_cell::StaticCell;
 
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
 });
 
 const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Debounce settings right below the storage sectors RMK uses by default, the key statistics right below them
+const STORAGE: StorageLayout = StorageLayout {
+    debounce_offset: (FLASH_SIZE - 3 * ERASE_SIZE) as u32,
+    stats_offset: (FLASH_SIZE - 4 * ERASE_SIZE) as u32,
+};
+/// Keep scanning for 1 ms after the last release, and sleep deeply after a minute without keys
+#[cfg(feature = "async_matrix")]
+const IDLE: IdleConfig = IdleConfig {
+    scan_window_ms: 1,
+    sleep_timeout_ms: Some(60_000),
+};
 
 #[embassy_executor::main]
-async fn main(spawner: Spawner) {
+async fn main(_spawner: Spawner) {
     info!("RMK start!");
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
@@ -41,46 +51,24 @@ async fn main(spawner: Spawner) {
     // Create the usb driver, from the HAL
     let driver = Driver::new(p.USB, Irqs);
 
-    // Pin config
-    let pins = config_sequential_matrix_pins_rp!(
-        peripherals: p,
-        row_clock: PIN_9,
-        col_clock: PIN_10,
-        any_not: PIN_11,
-        reset_not: PIN_12,
-        input: PIN_13,
-    );
+    // Pin config, generated from keyboard.toml
+    let pins = config_chain_pins!(p);
 
     // Use internal flash to emulate eeprom
-    // Both blocking and async flash are support, use different API
-    // let flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
-    let flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
-
-    let keyboard_usb_config = KeyboardUsbConfig {
-        vid: 0x4c4b,
-        pid: 0x4643,
-        manufacturer: "Haobo",
-        product_name: "RMK Keyboard",
-        serial_number: "vial:f64c2b3c:000001",
-    };
-
-    let vial_config = VialConfig::new(VIAL_KEYBOARD_ID, VIAL_KEYBOARD_DEF);
+    let mut flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
+    let serial_number = unique_serial_number(&mut flash, IDENTITY.serial_prefix);
 
-    let keyboard_config = RmkConfig {
-        usb_config: keyboard_usb_config,
-        vial_config,
-        ..Default::default()
-    };
+    // RMK's storage shares the flash with the debounce settings and the key statistics
+    static FLASH: StaticCell<Mutex<CriticalSectionRawMutex, Flash<peripherals::FLASH, Async, FLASH_SIZE>>> =
+        StaticCell::new();
 
     // Start serving
-    // Use `run_rmk` for blocking flash
-    run_rmk_with_async_flash(
-        pins,
-        driver,
-        flash,
-        &mut keymap::get_default_keymap(),
-        keyboard_config,
-        spawner,
-    )
-    .await;
-}
\ No newline at end of file
+    let keyboard = ChainKeyboard::<_, ROW, COL>::new(pins)
+        .debounce_default(debounce::DEBOUNCE_DEFAULT)
+        .storage(FLASH.init(Mutex::new(flash)), STORAGE)
+        .usb(driver, IDENTITY.rmk_config(serial_number));
+    // USB stays up so the host can resume the keyboard, flash so stores in flight complete
+    #[cfg(feature = "async_matrix")]
+    let keyboard = keyboard.deep_sleep(clock_gated_sleep(RpClockGatedSleep::new(true).with_flash()), IDLE);
+    keyboard.run(&mut keymap::get_default_keymap()).await
+}
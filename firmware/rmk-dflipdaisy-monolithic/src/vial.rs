@@ -1,3 +0,0 @@
-// Vial config is automatically generated by `build.rs`, according to `vial.json`
-// Please put `vial.json` at your project's root
-include!(concat!(env!("OUT_DIR"), "/config_generated.rs"));
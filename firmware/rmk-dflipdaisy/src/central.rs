@@ -1,51 +1,51 @@
 #![no_main]
 #![no_std]
+// The serial links are unused when the central scans both halves
+#![cfg_attr(split_chain, allow(unused_imports))]
 
-#[macro_use]
-mod keymap;
-#[macro_use]
-mod macros;
-mod vial;
+// Keymap, split topology, USB identity and chain pins, generated from keyboard.toml
+rmk_chain_keyboard::board!();
 
-mod custom;
-
-use crate::keymap::{COL, NUM_LAYER, ROW};
-use crate::custom::central::run_rmk_split_central;
-use rmk_custom_device::matrix::SequentialMatrixPins;
+use crate::split::{CENTRAL_COL, CENTRAL_REMAP, CENTRAL_ROW};
+use rmk_chain_keyboard::rp::unique_serial_number;
+#[cfg(feature = "async_matrix")]
+use rmk_chain_keyboard::rp::clock_gated_sleep;
+use rmk_chain_keyboard::{ChainKeyboard, StorageLayout};
+#[cfg(feature = "async_matrix")]
+use rmk_custom_device::{rp_sleep::RpClockGatedSleep, sleep::IdleConfig};
 
 use defmt::*;
 use defmt_rtt as _;
 use embassy_executor::Spawner;
-use embassy_futures::join::join;
 use embassy_rp::{
-    bind_interrupts,
-    flash::{Async, Flash},
-    gpio::{AnyPin, Input, Output},
-    peripherals::{self, UART0, USB},
-    uart::{self, BufferedUart},
+    flash::{Async, Flash, ERASE_SIZE},
+    peripherals::{self, USB},
     usb::{Driver, InterruptHandler},
 };
-// use embassy_rp::flash::Blocking;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use panic_probe as _;
-use rmk::{
-    config::{KeyboardUsbConfig, RmkConfig, VialConfig},
-    split::{
-        central::run_peripheral_monitor,
-        SPLIT_MESSAGE_MAX_SIZE,
-    },
-};
 use static_cell::StaticCell;
-use vial::{VIAL_KEYBOARD_DEF, VIAL_KEYBOARD_ID};
 
-bind_interrupts!(struct Irqs {
+// UARTs of the links to the peripherals are bound according to keyboard.toml
+bind_split_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
-    UART0_IRQ => uart::BufferedInterruptHandler<UART0>;
-});
+}, central);
 
 const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Debounce settings below the role sector of the symmetric image, the key statistics right below them
+const STORAGE: StorageLayout = StorageLayout {
+    debounce_offset: (FLASH_SIZE - 4 * ERASE_SIZE) as u32,
+    stats_offset: (FLASH_SIZE - 5 * ERASE_SIZE) as u32,
+};
+/// Keep scanning for 1 ms after the last release, and sleep deeply after a minute without keys
+#[cfg(feature = "async_matrix")]
+const IDLE: IdleConfig = IdleConfig {
+    scan_window_ms: 1,
+    sleep_timeout_ms: Some(60_000),
+};
 
 #[embassy_executor::main]
-async fn main(spawner: Spawner) {
+async fn main(_spawner: Spawner) {
     info!("RMK start!");
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
@@ -53,74 +53,35 @@ async fn main(spawner: Spawner) {
     // Create the usb driver, from the HAL
     let driver = Driver::new(p.USB, Irqs);
 
-    // Pin config
-    let pins = config_sequential_matrix_pins_rp!(
-        peripherals: p,
-        row_clock: PIN_9,
-        col_clock: PIN_10,
-        any_not: PIN_11,
-        reset_not: PIN_12,
-        input: PIN_13,
-    );
+    // Pin config, generated from keyboard.toml
+    let pins = config_chain_pins!(p, central);
 
     // Use internal flash to emulate eeprom
-    // Both blocking and async flash are support, use different API
-    // let flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(p.FLASH);
-    let flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
-
-    let keyboard_usb_config = KeyboardUsbConfig {
-        vid: 0x4c4b,
-        pid: 0x4643,
-        manufacturer: "Haobo",
-        product_name: "RMK Keyboard",
-        serial_number: "vial:f64c2b3c:000001",
-    };
-
-    let vial_config = VialConfig::new(VIAL_KEYBOARD_ID, VIAL_KEYBOARD_DEF);
+    let mut flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
+    let serial_number = unique_serial_number(&mut flash, IDENTITY.serial_prefix);
 
-    let keyboard_config = RmkConfig {
-        usb_config: keyboard_usb_config,
-        vial_config,
-        ..Default::default()
-    };
+    // RMK's storage shares the flash with the debounce settings and the key statistics
+    static FLASH: StaticCell<Mutex<CriticalSectionRawMutex, Flash<peripherals::FLASH, Async, FLASH_SIZE>>> =
+        StaticCell::new();
 
-    static TX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
-    let tx_buf = &mut TX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
-    static RX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
-    let rx_buf = &mut RX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
-    let uart_receiver = BufferedUart::new(
-        p.UART0,
-        Irqs,
-        p.PIN_0,
-        p.PIN_1,
-        tx_buf,
-        rx_buf,
-        uart::Config::default(),
-    );
+    // One monitor per peripheral, each on its own serial link
+    #[cfg(not(split_chain))]
+    let monitors = run_peripheral_monitors!(p, Irqs);
+    // The central's chain runs through the peripheral, no link to monitor
+    #[cfg(split_chain)]
+    let monitors = async {};
 
     // Start serving
-    join(
-        run_rmk_split_central::<
-            Input<'_>,
-            Output<'_>,
-            Driver<'_, USB>,
-            Flash<peripherals::FLASH, Async, FLASH_SIZE>,
-            ROW,
-            COL,
-            2,
-            2,
-            0,
-            0,
-            NUM_LAYER,
-        >(
-            pins,
-            driver,
-            flash,
-            &mut keymap::get_default_keymap(),
-            keyboard_config,
-            spawner,
-        ),
-        run_peripheral_monitor::<2, 1, 2, 2, _>(0, uart_receiver),
-    )
-    .await;
-}
\ No newline at end of file
+    let keyboard = ChainKeyboard::<_, CENTRAL_ROW, CENTRAL_COL>::new(pins)
+        .remap(CENTRAL_REMAP)
+        .debounce_default(debounce::DEBOUNCE_DEFAULT)
+        .storage(FLASH.init(Mutex::new(flash)), STORAGE)
+        .usb(driver, IDENTITY.rmk_config(serial_number));
+    // The links to the peripherals keep receiving while asleep
+    #[cfg(feature = "async_matrix")]
+    let keyboard = keyboard.deep_sleep(clock_gated_sleep(RpClockGatedSleep::new(true).with_flash().with_serial_link()), IDLE);
+    keyboard
+        .split_central(monitors)
+        .run(&mut keymap::get_default_keymap())
+        .await
+}
@@ -1,3 +0,0 @@
-
-pub(crate) mod central;
-pub(crate) mod peripheral;
@@ -1,66 +1,61 @@
 #![no_main]
 #![no_std]
+// The peripheral's setup is unused when the central scans both halves
+#![cfg_attr(split_chain, allow(unused_imports, dead_code))]
 
-#[macro_use]
-mod macros;
+// Keymap, split topology, USB identity and chain pins, generated from keyboard.toml
+rmk_chain_keyboard::board!();
 
-mod custom;
-use crate::custom::peripheral::run_rmk_split_peripheral;
-use rmk_custom_device::matrix::SequentialMatrixPins;
+use crate::split::SPLIT;
+#[cfg(feature = "async_matrix")]
+use rmk_chain_keyboard::rp::clock_gated_sleep;
+use rmk_chain_keyboard::ChainKeyboard;
+#[cfg(feature = "async_matrix")]
+use rmk_custom_device::{rp_sleep::RpClockGatedSleep, sleep::IdleConfig};
 
 use defmt::*;
 use defmt_rtt as _;
 use embassy_executor::Spawner;
-use embassy_rp::{
-    bind_interrupts,
-    gpio::{AnyPin, Input, Output},
-    peripherals::{UART0, USB},
-    uart::{self, BufferedUart},
-    usb::InterruptHandler,
-};
+use embassy_rp::{peripherals::USB, usb::InterruptHandler};
 use panic_probe as _;
-use rmk::split::SPLIT_MESSAGE_MAX_SIZE;
-use static_cell::StaticCell;
 
-bind_interrupts!(struct Irqs {
+// This image is the first peripheral of keyboard.toml, `peripheral 0`
+bind_split_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
-    UART0_IRQ => uart::BufferedInterruptHandler<UART0>;
-});
+}, peripheral 0);
+
+/// Keep scanning for 1 ms after the last release, and sleep deeply after a minute without keys
+#[cfg(feature = "async_matrix")]
+const IDLE: IdleConfig = IdleConfig {
+    scan_window_ms: 1,
+    sleep_timeout_ms: Some(60_000),
+};
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     info!("RMK start!");
-    // Initialize peripherals
-    let p = embassy_rp::init(Default::default());
-
-    // Pin config
-    let pins = config_sequential_matrix_pins_rp!(
-        peripherals: p,
-        row_clock: PIN_9,
-        col_clock: PIN_10,
-        any_not: PIN_11,
-        reset_not: PIN_12,
-        input: PIN_13,
-    );
-
-    static TX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
-    let tx_buf = &mut TX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
-    static RX_BUF: StaticCell<[u8; SPLIT_MESSAGE_MAX_SIZE]> = StaticCell::new();
-    let rx_buf = &mut RX_BUF.init([0; SPLIT_MESSAGE_MAX_SIZE])[..];
-    let uart_instance = BufferedUart::new(
-        p.UART0,
-        Irqs,
-        p.PIN_0,
-        p.PIN_1,
-        tx_buf,
-        rx_buf,
-        uart::Config::default(),
-    );
 
-    // Start serving
-    run_rmk_split_peripheral::<Input<'_>, Output<'_>, _, 2, 2>(
-        pins,
-        uart_instance,
-    )
-    .await;
-}
\ No newline at end of file
+    // The central's chain runs through this half, the peripheral MCU has nothing to do
+    #[cfg(split_chain)]
+    info!("Chain extension mode, this half is scanned by the central");
+
+    #[cfg(not(split_chain))]
+    {
+        // Initialize peripherals
+        let p = embassy_rp::init(Default::default());
+
+        // Pin config, generated from keyboard.toml
+        let pins = config_chain_pins!(p, peripheral 0);
+
+        // Start serving
+        let keyboard = ChainKeyboard::<_, { SPLIT.peripherals[0].rows }, { SPLIT.peripherals[0].cols }>::new(pins)
+            .debounce_default(debounce::DEBOUNCE_DEFAULT);
+        // The link to the central keeps receiving while asleep
+        #[cfg(feature = "async_matrix")]
+        let keyboard = keyboard.deep_sleep(clock_gated_sleep(RpClockGatedSleep::new(false).with_serial_link()), IDLE);
+        keyboard
+            .split_peripheral(config_split_serial!(p, Irqs, peripheral 0))
+            .run()
+            .await;
+    }
+}
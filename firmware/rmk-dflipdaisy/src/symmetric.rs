@@ -0,0 +1,143 @@
+//! Single image for both halves, the role is decided at boot.
+//!
+//! A strap on PIN_22 sets the role: tied high for the central, tied low for the peripheral.
+//! Without a strap the half powered by USB, sensed on PIN_24, becomes the central.
+//! The role is kept in flash, so a half keeps it once the strap is removed, and a peripheral stays one
+//! when USB is plugged into it, e.g. to flash it.
+#![no_main]
+#![no_std]
+// Most of the peripheral's setup is unused when the central scans both halves
+#![cfg_attr(split_chain, allow(unused_imports, dead_code))]
+
+// Keymap, split topology, USB identity and chain pins, generated from keyboard.toml
+rmk_chain_keyboard::board!();
+
+use crate::split::{CENTRAL_COL, CENTRAL_REMAP, CENTRAL_ROW, SPLIT};
+use rmk_chain_keyboard::rp::unique_serial_number;
+#[cfg(feature = "async_matrix")]
+use rmk_chain_keyboard::rp::clock_gated_sleep;
+use rmk_chain_keyboard::{ChainKeyboard, StorageLayout};
+use rmk_custom_device::role::{resolve_role, SplitRole};
+#[cfg(feature = "async_matrix")]
+use rmk_custom_device::{rp_sleep::RpClockGatedSleep, sleep::IdleConfig};
+
+use defmt::*;
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_rp::{
+    flash::{Async, Flash, ERASE_SIZE},
+    gpio::{Flex, Input, Pull},
+    peripherals::{self, USB},
+    usb::{Driver, InterruptHandler},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::Timer;
+use panic_probe as _;
+use static_cell::StaticCell;
+
+// UARTs of both roles are bound, the role decides which are used
+bind_split_interrupts!(struct Irqs {
+    USBCTRL_IRQ => InterruptHandler<USB>;
+}, all);
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Sector holding the role, right below the storage sectors RMK uses by default
+const ROLE_OFFSET: u32 = (FLASH_SIZE - 3 * ERASE_SIZE) as u32;
+/// Debounce settings right below the role sector, the key statistics right below them
+const STORAGE: StorageLayout = StorageLayout {
+    debounce_offset: (FLASH_SIZE - 4 * ERASE_SIZE) as u32,
+    stats_offset: (FLASH_SIZE - 5 * ERASE_SIZE) as u32,
+};
+/// Keep scanning for 1 ms after the last release, and sleep deeply after a minute without keys
+#[cfg(feature = "async_matrix")]
+const IDLE: IdleConfig = IdleConfig {
+    scan_window_ms: 1,
+    sleep_timeout_ms: Some(60_000),
+};
+
+// The same image can only be one of the halves, so it serves keyboards with a single peripheral
+const _: () = assert!(SPLIT.peripherals.len() == 1, "The symmetric image supports one peripheral");
+
+/// Role set by the strap: tied high or low, or `None` if the pin follows its pull, i.e. no strap is fitted
+async fn read_strap(pin: &mut Flex<'_>) -> Option<SplitRole> {
+    pin.set_as_input();
+    pin.set_pull(Pull::Up);
+    Timer::after_micros(10).await;
+    let pulled_up = pin.is_high();
+    pin.set_pull(Pull::Down);
+    Timer::after_micros(10).await;
+    let pulled_down = pin.is_high();
+    pin.set_pull(Pull::None);
+    match (pulled_up, pulled_down) {
+        (true, true) => Some(SplitRole::Central),
+        (false, false) => Some(SplitRole::Peripheral(0)),
+        _ => None,
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    info!("RMK start!");
+    // Initialize peripherals
+    let p = embassy_rp::init(Default::default());
+
+    // Use internal flash to emulate eeprom
+    let mut flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
+
+    let strap = read_strap(&mut Flex::new(p.PIN_22)).await;
+    let vbus = Input::new(p.PIN_24, Pull::None).is_high();
+    let role = resolve_role(&mut flash, ROLE_OFFSET, strap, vbus).await;
+    info!("Split role: {:?}", role);
+
+    match role {
+        SplitRole::Central => {
+            // Create the usb driver, from the HAL
+            let driver = Driver::new(p.USB, Irqs);
+
+            // Pin config, generated from keyboard.toml
+            let pins = config_chain_pins!(p, central);
+
+            let serial_number = unique_serial_number(&mut flash, IDENTITY.serial_prefix);
+
+            // RMK's storage shares the flash with the debounce settings and the key statistics
+            static FLASH: StaticCell<Mutex<CriticalSectionRawMutex, Flash<peripherals::FLASH, Async, FLASH_SIZE>>> =
+                StaticCell::new();
+
+            #[cfg(not(split_chain))]
+            let monitors = run_peripheral_monitors!(p, Irqs);
+            #[cfg(split_chain)]
+            let monitors = async {};
+
+            // Start serving
+            let keyboard = ChainKeyboard::<_, CENTRAL_ROW, CENTRAL_COL>::new(pins)
+                .remap(CENTRAL_REMAP)
+                .debounce_default(debounce::DEBOUNCE_DEFAULT)
+                .storage(FLASH.init(Mutex::new(flash)), STORAGE)
+                .usb(driver, IDENTITY.rmk_config(serial_number));
+            #[cfg(feature = "async_matrix")]
+            let keyboard = keyboard.deep_sleep(clock_gated_sleep(RpClockGatedSleep::new(true).with_flash().with_serial_link()), IDLE);
+            keyboard
+                .split_central(monitors)
+                .run(&mut keymap::get_default_keymap())
+                .await
+        }
+        // The central's chain runs through this half, the peripheral MCU has nothing to do
+        #[cfg(split_chain)]
+        SplitRole::Peripheral(_) => info!("Chain extension mode, this half is scanned by the central"),
+        #[cfg(not(split_chain))]
+        SplitRole::Peripheral(_) => {
+            // Pin config, generated from keyboard.toml
+            let pins = config_chain_pins!(p, peripheral 0);
+
+            // Start serving
+            let keyboard = ChainKeyboard::<_, { SPLIT.peripherals[0].rows }, { SPLIT.peripherals[0].cols }>::new(pins)
+                .debounce_default(debounce::DEBOUNCE_DEFAULT);
+            #[cfg(feature = "async_matrix")]
+            let keyboard = keyboard.deep_sleep(clock_gated_sleep(RpClockGatedSleep::new(false).with_serial_link()), IDLE);
+            keyboard
+                .split_peripheral(config_split_serial!(p, Irqs, peripheral 0))
+                .run()
+                .await;
+        }
+    }
+}
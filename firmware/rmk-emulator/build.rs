@@ -0,0 +1,20 @@
+//! Generates the keymap and the debounce defaults of the emulated keyboard from a `keyboard.toml`.
+//!
+//! The config is the one of the monolithic firmware, unless `KEYBOARD_TOML` points at another.
+//! `[layout]` and `[debounce]` are generated by the firmware's own code generation, the other sections are ignored.
+use std::env;
+use std::path::PathBuf;
+
+use rmk_chain_keyboard_build::Board;
+
+const DEFAULT_KEYBOARD_TOML: &str = "../rmk-dflipdaisy-monolithic/keyboard.toml";
+
+fn main() {
+    println!("cargo::rerun-if-env-changed=KEYBOARD_TOML");
+    let path = env::var_os("KEYBOARD_TOML")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_KEYBOARD_TOML));
+    let board = Board::read(path);
+    board.generate_keymap();
+    board.generate_debounce();
+}
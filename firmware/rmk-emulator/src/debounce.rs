@@ -0,0 +1,2 @@
+// Default debounce settings are automatically generated by `build.rs`, according to the `[debounce]` section of `keyboard.toml`
+include!(concat!(env!("OUT_DIR"), "/debounce_generated.rs"));
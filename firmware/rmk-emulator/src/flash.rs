@@ -0,0 +1,89 @@
+//! NOR flash kept in RAM, holding RMK's storage for one run of the emulator.
+//!
+//! Every run starts from erased flash, so the keymap always comes from `keyboard.toml`.
+use embedded_storage_async::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+const ERASE_SIZE: usize = 4096;
+const SECTORS: usize = 16;
+
+#[derive(Debug)]
+pub struct RamFlashError(NorFlashErrorKind);
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+/// Flash of [SECTORS] sectors, where writes can only clear bits, like NOR flash
+pub struct RamFlash {
+    data: Vec<u8>,
+}
+
+impl RamFlash {
+    pub fn new() -> Self {
+        Self {
+            data: vec![0xff; ERASE_SIZE * SECTORS],
+        }
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<usize, RamFlashError> {
+        let offset = offset as usize;
+        if offset % align != 0 || len % align != 0 {
+            return Err(RamFlashError(NorFlashErrorKind::NotAligned));
+        }
+        if offset + len > self.data.len() {
+            return Err(RamFlashError(NorFlashErrorKind::OutOfBounds));
+        }
+        Ok(offset)
+    }
+}
+
+impl Default for RamFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = RamFlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if to < from {
+            return Err(RamFlashError(NorFlashErrorKind::OutOfBounds));
+        }
+        let from = self.check(from, (to - from) as usize, Self::ERASE_SIZE)?;
+        self.data[from..to as usize].fill(0xff);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len(), Self::WRITE_SIZE)?;
+        self.data[offset..offset + bytes.len()]
+            .iter_mut()
+            .zip(bytes)
+            .for_each(|(cell, byte)| *cell &= byte);
+        Ok(())
+    }
+}
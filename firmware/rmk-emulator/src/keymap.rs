@@ -0,0 +1,2 @@
+// `ROW`, `COL`, `NUM_LAYER` and `get_default_keymap` are generated by `build.rs`, according to the `[layout]` section of `keyboard.toml`
+include!(concat!(env!("OUT_DIR"), "/keymap_generated.rs"));
@@ -0,0 +1,189 @@
+//! Run the keyboard on the host, to try keymap changes without flashing a board.
+//!
+//! RMK's keyboard task runs with `get_default_keymap` over a [SequentialMatrix] scanning the simulated chain,
+//! and talks to an emulated USB host, which prints every HID report it receives.
+//! Switches are pressed with the commands of the [script] module, typed in the terminal or read from a file:
+//!
+//! ```sh
+//! cargo run                                  # type commands, e.g. `tap 1 0`
+//! cargo run -- scripts/layers.txt            # replay a script, then exit
+//! cargo run -- --record out.txt scripts/layers.txt
+//! ```
+//!
+//! The keymap and debounce settings come from the monolithic firmware's `keyboard.toml`,
+//! set `KEYBOARD_TOML` when building to use another one. Time runs on the wall clock,
+//! so tap-hold and other timeouts behave as on the board, within the scheduling jitter of the host.
+mod debounce;
+mod flash;
+mod keymap;
+mod report;
+mod script;
+mod usb;
+
+use std::{env, fs::File, io::BufRead, process, thread};
+
+use embassy_executor::Spawner;
+use embassy_futures::{block_on, select::select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Timer;
+use rmk::config::{KeyboardUsbConfig, RmkConfig};
+use rmk::initialize_usb_keyboard_and_run;
+use rmk_custom_device::{
+    debounce::{init_debounce_settings, RuntimeDebouncer},
+    matrix::SequentialMatrix,
+    remap::Identity,
+    sim::{ChainModel, SimOutputPin},
+    timing::{ChainTiming, DelayMode},
+};
+
+use debounce::DEBOUNCE_DEFAULT;
+use flash::RamFlash;
+use keymap::{COL, ROW};
+use report::Recorder;
+use script::{Command, HELP};
+use usb::EmulatedDriver;
+
+/// The chain model answers at once, so sub-scan delays only spin, and scans are 1 ms apart
+const TIMING: ChainTiming = ChainTiming::DEFAULT
+    .with_delay(DelayMode::Cycles { cpu_hz: 1 })
+    .with_scan_gap_us(1000);
+/// Time left after a script for the last reports to be sent
+const SETTLE_MS: u64 = 200;
+
+/// Lines typed in the terminal, read on a thread of their own
+static LINES: Channel<CriticalSectionRawMutex, String, 16> = Channel::new();
+
+/// RMK logs through defmt, which needs a global logger to link.
+/// Its frames can only be decoded against the firmware ELF, so they are dropped here.
+#[defmt::global_logger]
+struct DiscardLogger;
+
+unsafe impl defmt::Logger for DiscardLogger {
+    fn acquire() {}
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {}
+
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+struct Args {
+    script: Option<String>,
+    record: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        script: None,
+        record: None,
+    };
+    let mut words = env::args().skip(1);
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "--record" => args.record = Some(words.next().ok_or("--record needs a file")?),
+            _ if word.starts_with('-') => return Err(format!("Unknown option {}", word)),
+            _ if args.script.is_none() => args.script = Some(word),
+            _ => return Err(format!("Unexpected argument {}", word)),
+        }
+    }
+    Ok(args)
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    eprintln!("Usage: rmk-emulator [--record <file>] [<script>]");
+    process::exit(2);
+}
+
+/// Commands of the script at `path`, all checked before any is run
+fn read_script(path: &str) -> Vec<Command> {
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| fail(format!("Cannot read {}: {}", path, e)));
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(n, line)| {
+            Command::parse(line, ROW, COL)
+                .unwrap_or_else(|e| fail(format!("{}:{}: {}", path, n + 1, e)))
+        })
+        .collect()
+}
+
+async fn run_script(chain: &ChainModel<ROW, COL>, commands: Vec<Command>) {
+    for command in commands {
+        if !command.run(chain).await {
+            return;
+        }
+    }
+    Timer::after_millis(SETTLE_MS).await;
+}
+
+async fn run_terminal(chain: &ChainModel<ROW, COL>) {
+    println!("{} x {} chain, commands:\n{}", ROW, COL, HELP);
+    thread::spawn(|| {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            block_on(LINES.send(line));
+        }
+        block_on(LINES.send("quit".to_string()));
+    });
+    loop {
+        let line = LINES.receive().await;
+        match Command::parse(&line, ROW, COL) {
+            Ok(Some(command)) => {
+                if !command.run(chain).await {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    env_logger::init();
+    let args = parse_args().unwrap_or_else(|e| fail(e));
+    let script = args.script.as_deref().map(read_script);
+    let record = args.record.map(|path| {
+        File::create(&path).unwrap_or_else(|e| fail(format!("Cannot create {}: {}", path, e)))
+    });
+
+    init_debounce_settings(DEBOUNCE_DEFAULT);
+    // RMK keeps the matrix and the config for good, so the chain lives as long as the process
+    let chain: &'static ChainModel<ROW, COL> = Box::leak(Box::new(ChainModel::new()));
+    let matrix = SequentialMatrix::<_, _, _, ROW, COL, _>::new_with_timing(
+        chain.pins(),
+        RuntimeDebouncer::<ROW, COL>::new(),
+        Identity,
+        TIMING,
+    );
+
+    let keyboard_config: RmkConfig<'static, SimOutputPin<'static, ROW, COL>> = RmkConfig {
+        usb_config: KeyboardUsbConfig {
+            vid: 0x4c4b,
+            pid: 0x4643,
+            manufacturer: "rmk",
+            product_name: "rmk-emulator",
+            serial_number: "emulator",
+        },
+        ..Default::default()
+    };
+    let keyboard = initialize_usb_keyboard_and_run(
+        matrix,
+        EmulatedDriver::new(Recorder::new(record)),
+        RamFlash::new(),
+        &mut keymap::get_default_keymap(),
+        keyboard_config,
+    );
+
+    let input = async {
+        match script {
+            Some(commands) => run_script(chain, commands).await,
+            None => run_terminal(chain).await,
+        }
+    };
+    select(keyboard, input).await;
+    process::exit(0);
+}
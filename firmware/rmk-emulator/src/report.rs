@@ -0,0 +1,82 @@
+//! Printing and recording of the HID reports the keyboard sends.
+//!
+//! Each report becomes a line with the time it was sent, in ms since the start of the emulator, e.g.
+//!
+//! ```text
+//!     412 keyboard mods [LShift] keys [Kp4]
+//!     530 keyboard mods [] keys []
+//!     871 ep2 02 e9 00
+//! ```
+//!
+//! The first interrupt IN endpoint is RMK's keyboard, whose boot reports are decoded with RMK's key names.
+//! Reports of the other endpoints, media and system controls or Vial, are written in hex with their report ID first.
+use std::{fs::File, io::LineWriter, io::Write};
+
+use embassy_time::Instant;
+use rmk::keycode::KeyCode;
+
+/// Modifier bits of a keyboard report, least significant first
+const MODIFIERS: [&str; 8] = [
+    "LCtrl", "LShift", "LAlt", "LGui", "RCtrl", "RShift", "RAlt", "RGui",
+];
+/// Size of a boot keyboard report: modifiers, a reserved byte and 6 key codes
+const KEYBOARD_REPORT_SIZE: usize = 8;
+
+pub struct Recorder {
+    /// Endpoint index of the keyboard reports
+    keyboard: Option<usize>,
+    /// Also write the lines there, if set
+    record: Option<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn new(record: Option<File>) -> Self {
+        Self {
+            keyboard: None,
+            record: record.map(LineWriter::new),
+        }
+    }
+
+    /// Called for every interrupt IN endpoint, in allocation order
+    pub(crate) fn add_endpoint(&mut self, index: usize) {
+        self.keyboard.get_or_insert(index);
+    }
+
+    pub(crate) fn record(&mut self, endpoint: usize, report: &[u8]) {
+        let line = format!(
+            "{:>7} {}",
+            Instant::now().as_millis(),
+            describe(report, self.keyboard == Some(endpoint), endpoint)
+        );
+        println!("{}", line);
+        if let Some(record) = &mut self.record {
+            if writeln!(record, "{}", line).is_err() {
+                eprintln!("Cannot write the record, stopped recording");
+                self.record = None;
+            }
+        }
+    }
+}
+
+fn describe(report: &[u8], keyboard: bool, endpoint: usize) -> String {
+    if keyboard && report.len() == KEYBOARD_REPORT_SIZE {
+        let mods: Vec<&str> = MODIFIERS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| report[0] & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        let keys: Vec<String> = report[2..]
+            .iter()
+            .filter(|code| **code != 0)
+            .map(|code| format!("{:?}", KeyCode::from(*code as u16)))
+            .collect();
+        return format!(
+            "keyboard mods [{}] keys [{}]",
+            mods.join(", "),
+            keys.join(", ")
+        );
+    }
+    let bytes: Vec<String> = report.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("ep{} {}", endpoint, bytes.join(" "))
+}
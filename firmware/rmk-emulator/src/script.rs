@@ -0,0 +1,100 @@
+//! Commands pressing the switches of the simulated chain, one per line, typed in the terminal or read from a script.
+//!
+//! ```text
+//! # Shifted Kp4, then Kp7 on layer 1
+//! press 1 1
+//! tap 1 0
+//! release 1 1
+//! wait 100
+//! press 2 0
+//! tap 0 0 250
+//! release 2 0
+//! ```
+//!
+//! Positions are the (row, col) of the chain, as in `[layout]` of `keyboard.toml`. Empty lines and `#` comments are skipped.
+use embassy_time::{Duration, Timer};
+use rmk_custom_device::sim::ChainModel;
+
+/// Hold time of `tap` without a time, well past the debounce time
+pub const DEFAULT_TAP_MS: u64 = 50;
+
+pub const HELP: &str = "\
+press <row> <col>         press the switch at chain position (row, col)
+release <row> <col>       release it
+tap <row> <col> [<ms>]    press it, and release it after ms, 50 by default
+wait <ms>                 do nothing for ms
+quit                      stop the emulator";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Press(usize, usize),
+    Release(usize, usize),
+    Tap(usize, usize, Duration),
+    Wait(Duration),
+    Quit,
+}
+
+impl Command {
+    /// Command of a line, `None` for empty lines and comments
+    pub fn parse(line: &str, rows: usize, cols: usize) -> Result<Option<Self>, String> {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(None);
+        };
+        let args: Vec<u64> = words
+            .map(|word| {
+                word.parse()
+                    .map_err(|_| format!("\"{}\" is not a number", word))
+            })
+            .collect::<Result<_, _>>()?;
+        let position = |row: u64, col: u64| -> Result<(usize, usize), String> {
+            if (row as usize) < rows && (col as usize) < cols {
+                Ok((row as usize, col as usize))
+            } else {
+                Err(format!(
+                    "({}, {}) is outside the {} x {} chain",
+                    row, col, rows, cols
+                ))
+            }
+        };
+        let command = match (name, args.as_slice()) {
+            ("press", [row, col]) => {
+                let (row, col) = position(*row, *col)?;
+                Command::Press(row, col)
+            }
+            ("release", [row, col]) => {
+                let (row, col) = position(*row, *col)?;
+                Command::Release(row, col)
+            }
+            ("tap", [row, col]) | ("tap", [row, col, _]) => {
+                let (row, col) = position(*row, *col)?;
+                let ms = args.get(2).copied().unwrap_or(DEFAULT_TAP_MS);
+                Command::Tap(row, col, Duration::from_millis(ms))
+            }
+            ("wait", [ms]) => Command::Wait(Duration::from_millis(*ms)),
+            ("quit", []) => Command::Quit,
+            _ => return Err(format!("\"{}\" is not a command\n{}", line.trim(), HELP)),
+        };
+        Ok(Some(command))
+    }
+
+    /// Apply the command to the chain, returns `false` on `quit`
+    pub async fn run<const ROW: usize, const COL: usize>(
+        &self,
+        chain: &ChainModel<ROW, COL>,
+    ) -> bool {
+        match *self {
+            Command::Press(row, col) => chain.press(row, col),
+            Command::Release(row, col) => chain.release(row, col),
+            Command::Tap(row, col, hold) => {
+                chain.press(row, col);
+                Timer::after(hold).await;
+                chain.release(row, col);
+            }
+            Command::Wait(time) => Timer::after(time).await,
+            Command::Quit => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,285 @@
+//! USB device controller emulated in memory, standing in for a host which enumerates the keyboard and listens to it.
+//!
+//! The bus powers up and resets once, then the control pipe hands out `SET_ADDRESS` and `SET_CONFIGURATION`,
+//! which is enough for embassy-usb to enable the endpoints and for RMK to start sending reports.
+//! Every packet written to an IN endpoint goes to the [Recorder]; OUT endpoints never receive anything.
+//! Reports are assumed to fit a single packet, which holds for the keyboard, media and Vial reports of RMK.
+use core::future::{pending, poll_fn};
+use core::task::{Poll, Waker};
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use embassy_usb_driver::{
+    Bus, ControlPipe, Direction, Driver, Endpoint, EndpointAddress, EndpointAllocError,
+    EndpointError, EndpointIn, EndpointInfo, EndpointOut, EndpointType, Event, Unsupported,
+};
+
+use crate::report::Recorder;
+
+/// Device address given by the emulated host
+const ADDRESS: u8 = 1;
+/// Setup packets of the enumeration: `SET_ADDRESS`, then `SET_CONFIGURATION` of the first configuration
+const ENUMERATION: [[u8; 8]; 2] = [
+    [0x00, 0x05, ADDRESS, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+struct UsbState {
+    events: VecDeque<Event>,
+    setups: VecDeque<[u8; 8]>,
+    /// Endpoints enabled by the configuration
+    enabled: Vec<EndpointAddress>,
+    wakers: Vec<Waker>,
+}
+
+impl UsbState {
+    fn set_enabled(&mut self, addr: EndpointAddress, enabled: bool) {
+        self.enabled.retain(|ep| *ep != addr);
+        if enabled {
+            self.enabled.push(addr);
+        }
+        self.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+type Shared = Rc<RefCell<UsbState>>;
+
+pub struct EmulatedDriver {
+    state: Shared,
+    recorder: Rc<RefCell<Recorder>>,
+    next_out: usize,
+    next_in: usize,
+}
+
+impl EmulatedDriver {
+    pub fn new(recorder: Recorder) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(UsbState {
+                events: VecDeque::from([Event::PowerDetected, Event::Reset]),
+                setups: VecDeque::from(ENUMERATION),
+                enabled: Vec::new(),
+                wakers: Vec::new(),
+            })),
+            recorder: Rc::new(RefCell::new(recorder)),
+            next_out: 1,
+            next_in: 1,
+        }
+    }
+}
+
+impl Driver<'static> for EmulatedDriver {
+    type EndpointOut = EmulatedEndpointOut;
+    type EndpointIn = EmulatedEndpointIn;
+    type ControlPipe = EmulatedControlPipe;
+    type Bus = EmulatedBus;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        let addr = EndpointAddress::from_parts(self.next_out, Direction::Out);
+        self.next_out += 1;
+        Ok(EmulatedEndpointOut(EmulatedEndpoint {
+            info: EndpointInfo {
+                addr,
+                ep_type,
+                max_packet_size,
+                interval_ms,
+            },
+            state: self.state.clone(),
+        }))
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        let addr = EndpointAddress::from_parts(self.next_in, Direction::In);
+        self.next_in += 1;
+        if ep_type == EndpointType::Interrupt {
+            self.recorder.borrow_mut().add_endpoint(addr.index());
+        }
+        Ok(EmulatedEndpointIn {
+            endpoint: EmulatedEndpoint {
+                info: EndpointInfo {
+                    addr,
+                    ep_type,
+                    max_packet_size,
+                    interval_ms,
+                },
+                state: self.state.clone(),
+            },
+            recorder: self.recorder.clone(),
+        })
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        (
+            EmulatedBus {
+                state: self.state.clone(),
+            },
+            EmulatedControlPipe {
+                state: self.state,
+                max_packet_size: control_max_packet_size as usize,
+            },
+        )
+    }
+}
+
+pub struct EmulatedBus {
+    state: Shared,
+}
+
+impl Bus for EmulatedBus {
+    async fn enable(&mut self) {}
+
+    async fn disable(&mut self) {}
+
+    async fn poll(&mut self) -> Event {
+        let event = self.state.borrow_mut().events.pop_front();
+        match event {
+            Some(event) => event,
+            None => pending().await,
+        }
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        self.state.borrow_mut().set_enabled(ep_addr, enabled);
+    }
+
+    fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {}
+
+    fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
+        false
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+}
+
+pub struct EmulatedControlPipe {
+    state: Shared,
+    max_packet_size: usize,
+}
+
+impl ControlPipe for EmulatedControlPipe {
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        let setup = self.state.borrow_mut().setups.pop_front();
+        match setup {
+            Some(setup) => setup,
+            None => pending().await,
+        }
+    }
+
+    async fn data_out(
+        &mut self,
+        _buf: &mut [u8],
+        _first: bool,
+        _last: bool,
+    ) -> Result<usize, EndpointError> {
+        Ok(0)
+    }
+
+    async fn data_in(
+        &mut self,
+        _data: &[u8],
+        _first: bool,
+        _last: bool,
+    ) -> Result<(), EndpointError> {
+        Ok(())
+    }
+
+    async fn accept(&mut self) {}
+
+    async fn reject(&mut self) {}
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        debug_assert_eq!(addr, ADDRESS);
+    }
+}
+
+struct EmulatedEndpoint {
+    info: EndpointInfo,
+    state: Shared,
+}
+
+impl EmulatedEndpoint {
+    async fn wait_enabled(&self) {
+        poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            if state.enabled.contains(&self.info.addr) {
+                Poll::Ready(())
+            } else {
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.state.borrow().enabled.contains(&self.info.addr)
+    }
+}
+
+pub struct EmulatedEndpointOut(EmulatedEndpoint);
+
+impl Endpoint for EmulatedEndpointOut {
+    fn info(&self) -> &EndpointInfo {
+        &self.0.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        self.0.wait_enabled().await
+    }
+}
+
+impl EndpointOut for EmulatedEndpointOut {
+    async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, EndpointError> {
+        if !self.0.is_enabled() {
+            return Err(EndpointError::Disabled);
+        }
+        pending().await
+    }
+}
+
+pub struct EmulatedEndpointIn {
+    endpoint: EmulatedEndpoint,
+    recorder: Rc<RefCell<Recorder>>,
+}
+
+impl Endpoint for EmulatedEndpointIn {
+    fn info(&self) -> &EndpointInfo {
+        &self.endpoint.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        self.endpoint.wait_enabled().await
+    }
+}
+
+impl EndpointIn for EmulatedEndpointIn {
+    async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        if !self.endpoint.is_enabled() {
+            return Err(EndpointError::Disabled);
+        }
+        if buf.len() > self.endpoint.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+        // Zero length packets only end transfers
+        if !buf.is_empty() {
+            self.recorder
+                .borrow_mut()
+                .record(self.endpoint.info.addr.index(), buf);
+        }
+        Ok(())
+    }
+}